@@ -0,0 +1,36 @@
+use async_trait::async_trait;
+use sea_orm_migration::{prelude::*, schema::json_null};
+
+use crate::data::models::playlist::Column;
+
+use super::create_playlist_table::PlaylistTable;
+
+/// Add `smart_rules` to the playlist table, the json rules of smart playlists.
+/// Playlists with rules get their musics from the rules instead of the junction table.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PlaylistTable::Playlist)
+                    .add_column(json_null(Column::SmartRules))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PlaylistTable::Playlist)
+                    .drop_column(Column::SmartRules)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,55 @@
+use async_trait::async_trait;
+use sea_orm_migration::{prelude::*, schema::string_len_null};
+
+use crate::data::models::playlist::Column;
+
+use super::create_playlist_table::PlaylistTable;
+
+/// Add `system_type` to the playlist table, it marks built-in playlists like "Liked songs".
+/// The unique index keeps at most one playlist per system type, user playlists stay `NULL`.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PlaylistTable::Playlist)
+                    .add_column(string_len_null(Column::SystemType, 1))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_playlist_system_type")
+                    .table(PlaylistTable::Playlist)
+                    .col(Column::SystemType)
+                    .unique()
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_playlist_system_type")
+                    .table(PlaylistTable::Playlist)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PlaylistTable::Playlist)
+                    .drop_column(Column::SystemType)
+                    .to_owned(),
+            )
+            .await
+    }
+}
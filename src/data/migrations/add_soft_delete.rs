@@ -0,0 +1,72 @@
+use async_trait::async_trait;
+use sea_orm_migration::{prelude::*, schema::big_integer_null};
+
+use crate::data::models::{playlist, playlist_collection, playlist_music_junction};
+
+use super::{
+    create_playlist_collection_table::PlaylistCollectionTable,
+    create_playlist_music_junction_table::PlaylistMusicJunctionTable,
+    create_playlist_table::PlaylistTable,
+};
+
+/// Add `deleted_at` (unix milliseconds) to the playlist tables.
+/// Deleted rows stay in the trash until they are restored or purged, `NULL` means not deleted.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+fn tables() -> Vec<(DynIden, DynIden)> {
+    vec![
+        (
+            PlaylistCollectionTable::PlaylistCollection.into_iden(),
+            playlist_collection::Column::DeletedAt.into_iden(),
+        ),
+        (
+            PlaylistTable::Playlist.into_iden(),
+            playlist::Column::DeletedAt.into_iden(),
+        ),
+        (
+            PlaylistMusicJunctionTable::PlaylistMusicJunction.into_iden(),
+            playlist_music_junction::Column::DeletedAt.into_iden(),
+        ),
+    ]
+}
+
+#[async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for (table, column) in tables() {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(table)
+                        .add_column(big_integer_null(column))
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for (table, column) in tables() {
+            // drop what is in the trash, it would show up again otherwise
+            manager
+                .exec_stmt(
+                    Query::delete()
+                        .from_table(table.clone())
+                        .and_where(Expr::col(column.clone()).is_not_null())
+                        .to_owned(),
+                )
+                .await?;
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use async_trait::async_trait;
+use sea_orm_migration::{prelude::*, schema::big_integer};
+
+use crate::{
+    data::models::{music_aggregator, playlist, playlist_collection, playlist_music_junction},
+    interface::utils::now_millis,
+};
+
+use super::{
+    create_music_aggregator_table::MusicAggregatorTable,
+    create_playlist_collection_table::PlaylistCollectionTable,
+    create_playlist_music_junction_table::PlaylistMusicJunctionTable,
+    create_playlist_table::PlaylistTable,
+};
+
+/// Add `created_at`/`updated_at` (unix milliseconds) to the playlist tables.
+/// Existing rows don't know when they were created, they are backfilled with the migration time.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+fn tables() -> Vec<(DynIden, DynIden, DynIden)> {
+    vec![
+        (
+            PlaylistCollectionTable::PlaylistCollection.into_iden(),
+            playlist_collection::Column::CreatedAt.into_iden(),
+            playlist_collection::Column::UpdatedAt.into_iden(),
+        ),
+        (
+            PlaylistTable::Playlist.into_iden(),
+            playlist::Column::CreatedAt.into_iden(),
+            playlist::Column::UpdatedAt.into_iden(),
+        ),
+        (
+            MusicAggregatorTable::MusicAggragator.into_iden(),
+            music_aggregator::Column::CreatedAt.into_iden(),
+            music_aggregator::Column::UpdatedAt.into_iden(),
+        ),
+        (
+            PlaylistMusicJunctionTable::PlaylistMusicJunction.into_iden(),
+            playlist_music_junction::Column::CreatedAt.into_iden(),
+            playlist_music_junction::Column::UpdatedAt.into_iden(),
+        ),
+    ]
+}
+
+#[async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let now = now_millis();
+        for (table, created_at, updated_at) in tables() {
+            // sqlite can only add one column per statement
+            for column in [created_at.clone(), updated_at.clone()] {
+                manager
+                    .alter_table(
+                        Table::alter()
+                            .table(table.clone())
+                            .add_column(big_integer(column).default(0))
+                            .to_owned(),
+                    )
+                    .await?;
+            }
+            manager
+                .exec_stmt(
+                    Query::update()
+                        .table(table)
+                        .value(created_at, now)
+                        .value(updated_at, now)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for (table, created_at, updated_at) in tables() {
+            for column in [created_at, updated_at] {
+                manager
+                    .alter_table(
+                        Table::alter()
+                            .table(table.clone())
+                            .drop_column(column)
+                            .to_owned(),
+                    )
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use async_trait::async_trait;
+use sea_orm::DatabaseBackend;
+use sea_orm_migration::prelude::*;
+
+// todo: add more music server
+const MUSIC_TABLES: [&str; 2] = ["kuwo_music", "netease_music"];
+
+/// Full text search over the music tables.
+/// On sqlite every music table gets a fts5 trigram table kept in sync by triggers,
+/// the other backends search the music tables directly.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+fn artist_names(row: &str) -> String {
+    format!(
+        "(SELECT group_concat(json_extract(value, '$.name'), ' ') FROM json_each({row}.artists))"
+    )
+}
+
+#[async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() != DatabaseBackend::Sqlite {
+            return Ok(());
+        }
+        let db = manager.get_connection();
+        for table in MUSIC_TABLES {
+            let fts = format!("{table}_fts");
+            db.execute_unprepared(&format!(
+                "CREATE VIRTUAL TABLE IF NOT EXISTS {fts} USING fts5(music_id UNINDEXED, name, artists, album, tokenize = 'trigram')"
+            ))
+            .await?;
+            db.execute_unprepared(&format!(
+                "CREATE TRIGGER IF NOT EXISTS {fts}_insert AFTER INSERT ON {table} BEGIN \
+                 INSERT INTO {fts}(rowid, music_id, name, artists, album) \
+                 VALUES (new.rowid, new.music_id, new.name, {}, new.album); END",
+                artist_names("new")
+            ))
+            .await?;
+            db.execute_unprepared(&format!(
+                "CREATE TRIGGER IF NOT EXISTS {fts}_delete AFTER DELETE ON {table} BEGIN \
+                 DELETE FROM {fts} WHERE rowid = old.rowid; END"
+            ))
+            .await?;
+            db.execute_unprepared(&format!(
+                "CREATE TRIGGER IF NOT EXISTS {fts}_update AFTER UPDATE ON {table} BEGIN \
+                 UPDATE {fts} SET music_id = new.music_id, name = new.name, artists = {}, album = new.album \
+                 WHERE rowid = old.rowid; END",
+                artist_names("new")
+            ))
+            .await?;
+            db.execute_unprepared(&format!(
+                "INSERT INTO {fts}(rowid, music_id, name, artists, album) \
+                 SELECT rowid, music_id, name, {}, album FROM {table}",
+                artist_names(table)
+            ))
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() != DatabaseBackend::Sqlite {
+            return Ok(());
+        }
+        let db = manager.get_connection();
+        for table in MUSIC_TABLES {
+            let fts = format!("{table}_fts");
+            for trigger in ["insert", "delete", "update"] {
+                db.execute_unprepared(&format!("DROP TRIGGER IF EXISTS {fts}_{trigger}"))
+                    .await?;
+            }
+            db.execute_unprepared(&format!("DROP TABLE IF EXISTS {fts}"))
+                .await?;
+        }
+        Ok(())
+    }
+}
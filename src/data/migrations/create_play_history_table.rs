@@ -0,0 +1,71 @@
+use async_trait::async_trait;
+use sea_orm_migration::{
+    prelude::*,
+    schema::{big_integer, boolean, string},
+};
+
+use crate::data::models::play_history::Column;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PlayHistoryTable::PlayHistory)
+                    .col(
+                        big_integer(Column::Id)
+                            .auto_increment()
+                            .primary_key()
+                            .not_null(),
+                    )
+                    .col(string(Column::MusicAggregatorId))
+                    .col(string(Column::Server))
+                    .col(big_integer(Column::PlayedAt))
+                    .col(big_integer(Column::PlayedDuration))
+                    .col(boolean(Column::Completed))
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_play_history_played_at")
+                    .table(PlayHistoryTable::PlayHistory)
+                    .col(Column::PlayedAt)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_play_history_music_aggregator_id")
+                    .table(PlayHistoryTable::PlayHistory)
+                    .col(Column::MusicAggregatorId)
+                    .if_not_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(
+                Table::drop()
+                    .table(PlayHistoryTable::PlayHistory)
+                    .if_exists()
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+pub enum PlayHistoryTable {
+    PlayHistory,
+}
@@ -4,9 +4,7 @@ use sea_orm_migration::{
     schema::{big_integer, string},
 };
 
-use crate::{
-    data::models::playlist_collection::Column, interface::playlist_collection::PlaylistCollection,
-};
+use crate::data::models::playlist_collection::Column;
 
 #[derive(DeriveMigrationName)]
 pub struct Migration;
@@ -14,6 +12,8 @@ pub struct Migration;
 #[async_trait]
 impl MigrationTrait for Migration {
     async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // schema only, the migrated database may not be the one in use.
+        // `reinit_db` seeds the default collection.
         manager
             .create_table(
                 Table::create()
@@ -29,11 +29,7 @@ impl MigrationTrait for Migration {
                     .if_not_exists()
                     .to_owned(),
             )
-            .await?;
-        let _ = PlaylistCollection::new("我的歌单".to_string())
-            .insert_to_db()
-            .await;
-        Ok(())
+            .await
     }
 
     async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
@@ -69,7 +69,7 @@ impl MigrationTrait for Migration {
 }
 
 #[derive(DeriveIden)]
-enum PlaylistMusicJunctionTable {
+pub enum PlaylistMusicJunctionTable {
     #[sea_orm(iden = "playlist_music_junction")]
     PlaylistMusicJunction,
 }
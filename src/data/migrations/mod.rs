@@ -1,6 +1,14 @@
+pub mod add_playlist_smart_rules;
+pub mod add_playlist_system_type;
+pub mod add_soft_delete;
+pub mod add_timestamps;
+pub mod create_artist_aggregator_table;
+pub mod create_library_journal_table;
 pub mod create_music_aggregator_table;
+pub mod create_music_search_index;
 pub mod create_playlist_collection_table;
 pub mod create_playlist_music_junction_table;
+pub mod create_play_history_table;
 pub mod create_playlist_table;
 
 use async_trait::async_trait;
@@ -19,6 +27,14 @@ impl MigratorTrait for Migrator {
             // todo: add more music server
             Box::new(crate::server::kuwo::create_kuwo_music_table_migration::Migration),
             Box::new(crate::server::netease::create_netease_music_table_migration::Migration),
+            Box::new(create_artist_aggregator_table::Migration),
+            Box::new(create_music_search_index::Migration),
+            Box::new(add_timestamps::Migration),
+            Box::new(create_play_history_table::Migration),
+            Box::new(add_playlist_system_type::Migration),
+            Box::new(add_playlist_smart_rules::Migration),
+            Box::new(add_soft_delete::Migration),
+            Box::new(create_library_journal_table::Migration),
         ]
     }
 }
@@ -0,0 +1,19 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// One artist, mapped to its artist id on each music server
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "artist_aggregator")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i64,
+    pub name: String,
+    // todo: add more music server
+    pub kuwo_artist_id: Option<String>,
+    pub netease_artist_id: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,24 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::interface::journal::JournalChangeVec;
+
+/// One undoable library operation with the rows it changed.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "library_journal")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i64,
+    /// what the operation did, e.g. `delete playlist`
+    pub label: String,
+    pub changes: JournalChangeVec,
+    /// unix time in milliseconds
+    pub created_at: i64,
+    /// undone entries can be redone until a new operation is recorded
+    pub undone: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
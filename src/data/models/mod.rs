@@ -1,5 +1,8 @@
+pub mod artist_aggregator;
+pub mod library_journal;
 pub mod music_aggregator;
 pub mod music_platform;
+pub mod play_history;
 pub mod playlist;
 pub mod playlist_music_junction;
 pub mod playlist_collection;
\ No newline at end of file
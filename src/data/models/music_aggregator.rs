@@ -1,10 +1,10 @@
+use std::collections::HashMap;
+
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    interface::{
-        music_aggregator::MusicAggregator, server::MusicServer, utils::split_string,
-    },
+    interface::{music_aggregator::MusicAggregator, server::MusicServer, utils::split_string},
     server::{kuwo, netease},
 };
 
@@ -16,6 +16,11 @@ pub struct Model {
     pub default_server: MusicServer,
     pub kuwo_music_id: Option<String>,
     pub netease_music_id: Option<String>,
+    /// unix time in milliseconds
+    #[serde(default)]
+    pub created_at: i64,
+    #[serde(default)]
+    pub updated_at: i64,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter)]
@@ -49,40 +54,108 @@ impl RelationTrait for Relation {
     }
 }
 
+// ids per `IN` query when loading the musics of many music aggregators
+const LOAD_CHUNK_SIZE: usize = 500;
+
 impl Model {
     pub async fn get_music_aggregator(
         &self,
         db: &DatabaseConnection,
         order: i64,
     ) -> anyhow::Result<MusicAggregator> {
-        let (name, artist) = split_string(&self.identity)?;
-        let mut musics = Vec::with_capacity(MusicServer::length());
+        // todo: add more music server
+        let kuwo_music = match self.kuwo_music_id.as_ref() {
+            Some(id) => kuwo::model::Entity::find_by_id(id).one(db).await?,
+            None => None,
+        };
+        let netease_music = match self.netease_music_id.as_ref() {
+            Some(id) => netease::model::Entity::find_by_id(id).one(db).await?,
+            None => None,
+        };
+        self.clone()
+            .into_music_aggregator(order, kuwo_music, netease_music)
+    }
 
+    /// build music aggregators of many models with one `IN` query per music server,
+    /// the order of `models` is kept
+    pub async fn get_music_aggregators<C: ConnectionTrait>(
+        db: &C,
+        models: Vec<(Self, i64)>,
+    ) -> anyhow::Result<Vec<MusicAggregator>> {
         // todo: add more music server
-        if let Some(id) = self.kuwo_music_id.as_ref() {
-            let kuwo_music = kuwo::model::Entity::find_by_id(id).one(db).await?;
+        let kuwo_ids: Vec<String> = models
+            .iter()
+            .filter_map(|(m, _)| m.kuwo_music_id.clone())
+            .collect();
+        let netease_ids: Vec<String> = models
+            .iter()
+            .filter_map(|(m, _)| m.netease_music_id.clone())
+            .collect();
 
-            if let Some(kuwo_music) = kuwo_music {
-                musics.push(kuwo_music.into_music(true));
+        let mut kuwo_musics: HashMap<String, kuwo::model::Model> = HashMap::new();
+        for chunk in kuwo_ids.chunks(LOAD_CHUNK_SIZE) {
+            for music in kuwo::model::Entity::find()
+                .filter(kuwo::model::Column::MusicId.is_in(chunk.to_vec()))
+                .all(db)
+                .await?
+            {
+                kuwo_musics.insert(music.music_id.clone(), music);
             }
         }
-
-        if let Some(id) = self.netease_music_id.as_ref() {
-            let netease_music = netease::model::Entity::find_by_id(id).one(db).await?;
-            if let Some(netease_music) = netease_music {
-                musics.push(netease_music.into_music(true));
+        let mut netease_musics: HashMap<String, netease::model::Model> = HashMap::new();
+        for chunk in netease_ids.chunks(LOAD_CHUNK_SIZE) {
+            for music in netease::model::Entity::find()
+                .filter(netease::model::Column::MusicId.is_in(chunk.to_vec()))
+                .all(db)
+                .await?
+            {
+                netease_musics.insert(music.music_id.clone(), music);
             }
         }
 
-        let agg = MusicAggregator {
+        let mut aggs = Vec::with_capacity(models.len());
+        for (model, order) in models {
+            let kuwo_music = model
+                .kuwo_music_id
+                .as_ref()
+                .and_then(|id| kuwo_musics.get(id).cloned());
+            let netease_music = model
+                .netease_music_id
+                .as_ref()
+                .and_then(|id| netease_musics.get(id).cloned());
+            aggs.push(model.into_music_aggregator(order, kuwo_music, netease_music)?);
+        }
+        Ok(aggs)
+    }
+
+    pub fn into_music_aggregator(
+        self,
+        order: i64,
+        kuwo_music: Option<kuwo::model::Model>,
+        netease_music: Option<netease::model::Model>,
+    ) -> anyhow::Result<MusicAggregator> {
+        let (name, artist) = split_string(&self.identity)?;
+        let mut musics = Vec::with_capacity(MusicServer::length());
+
+        // todo: add more music server
+        if let Some(kuwo_music) = kuwo_music {
+            musics.push(kuwo_music.into_music(true));
+        }
+        if let Some(netease_music) = netease_music {
+            musics.push(netease_music.into_music(true));
+        }
+
+        Ok(MusicAggregator {
             name,
             artist,
             from_db: true,
             musics,
-            default_server: self.default_server.clone(),
+            default_server: self.default_server,
             order: Some(order),
-        };
-        Ok(agg)
+            created_at: Some(self.created_at),
+            updated_at: Some(self.updated_at),
+            added_at: None,
+        })
     }
 }
 
@@ -0,0 +1,26 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::interface::server::MusicServer;
+
+/// One play of a music aggregator.
+/// There is no foreign key to `music_aggregator`, the history outlives removed music.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "play_history")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = true)]
+    pub id: i64,
+    pub music_aggregator_id: String,
+    /// the server the music was played from
+    pub server: MusicServer,
+    /// unix time in milliseconds when the play was recorded
+    pub played_at: i64,
+    /// seconds actually listened
+    pub played_duration: i64,
+    pub completed: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
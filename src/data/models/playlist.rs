@@ -1,4 +1,7 @@
-use crate::interface::playlist_subscription::PlayListSubscriptionVec;
+use crate::interface::{
+    playlist::SystemPlaylistType, playlist_subscription::PlayListSubscriptionVec,
+    smart_playlist::SmartPlaylistRules, utils::now_millis,
+};
 use anyhow::Result;
 use sea_orm::{entity::prelude::*, ActiveValue::NotSet, Set};
 use serde::{Deserialize, Serialize};
@@ -16,6 +19,21 @@ pub struct Model {
     #[sea_orm(nullable)]
     pub cover: Option<String>,
     pub subscriptions: Option<PlayListSubscriptionVec>,
+    /// unix time in milliseconds
+    #[serde(default)]
+    pub created_at: i64,
+    #[serde(default)]
+    pub updated_at: i64,
+    /// built-in playlists can't be deleted, `None` for user playlists
+    #[sea_orm(nullable)]
+    #[serde(default)]
+    pub system_type: Option<SystemPlaylistType>,
+    /// smart playlists get their musics from these rules, not from the junction table
+    #[serde(default)]
+    pub smart_rules: Option<SmartPlaylistRules>,
+    /// unix time in milliseconds, set while the playlist is in the trash
+    #[serde(default)]
+    pub deleted_at: Option<i64>,
 }
 
 impl ActiveModel {
@@ -35,6 +53,11 @@ impl ActiveModel {
             subscriptions: Set(subscriptions),
             order: Set(order),
             collection_id: Set(collection_id),
+            created_at: Set(now_millis()),
+            updated_at: Set(now_millis()),
+            system_type: Set(None),
+            smart_rules: Set(None),
+            deleted_at: Set(None),
         }
     }
 }
@@ -8,6 +8,14 @@ pub struct Model {
     pub id: i64,
     pub order: i64,
     pub name: String,
+    /// unix time in milliseconds
+    #[serde(default)]
+    pub created_at: i64,
+    #[serde(default)]
+    pub updated_at: i64,
+    /// unix time in milliseconds, set while the collection is in the trash
+    #[serde(default)]
+    pub deleted_at: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter)]
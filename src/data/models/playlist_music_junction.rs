@@ -1,6 +1,8 @@
 use sea_orm::{entity::prelude::*, Set};
 use serde::{Deserialize, Serialize};
 
+use crate::interface::utils::now_millis;
+
 #[derive(Copy, Clone, Default, Debug, DeriveEntity)]
 pub struct Entity;
 
@@ -15,6 +17,14 @@ pub struct Model {
     pub playlist_id: i64,
     pub music_aggregator_id: String,
     pub order: i64,
+    /// unix time in milliseconds, `created_at` is when the music was added to the playlist
+    #[serde(default)]
+    pub created_at: i64,
+    #[serde(default)]
+    pub updated_at: i64,
+    /// unix time in milliseconds, set while the music is in the trash
+    #[serde(default)]
+    pub deleted_at: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveColumn)]
@@ -22,6 +32,9 @@ pub enum Column {
     PlaylistId,
     MusicAggregatorId,
     Order,
+    CreatedAt,
+    UpdatedAt,
+    DeletedAt,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DerivePrimaryKey)]
@@ -89,6 +102,9 @@ impl ActiveModel {
             playlist_id: Set(playlist_id),
             music_aggregator_id: Set(music_aggregator_id),
             order: Set(order),
+            created_at: Set(now_millis()),
+            updated_at: Set(now_millis()),
+            deleted_at: Set(None),
         }
     }
 }
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    music_aggregator::MusicAggregator,
+    playlist::{Playlist, PlaylistType},
+    server::MusicServer,
+    song_group::VariantType,
+    utils::normalize_name,
+};
+
+/// The same album across music servers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlbumAggregator {
+    pub name: String,
+    pub artist: String,
+    /// the `Album` playlist on each server, the first one decides the track order
+    pub albums: Vec<Playlist>,
+}
+
+impl AlbumAggregator {
+    pub fn from_album(album: Playlist) -> anyhow::Result<Self> {
+        if album.type_field != PlaylistType::Album || album.server.is_none() {
+            return Err(anyhow::anyhow!(
+                "Playlist {} is not an online album",
+                album.name
+            ));
+        }
+        Ok(Self {
+            name: album.name.clone(),
+            artist: album.creator.clone().unwrap_or_default(),
+            albums: vec![album],
+        })
+    }
+
+    pub fn album(&self, server: &MusicServer) -> Option<&Playlist> {
+        self.albums
+            .iter()
+            .find(|album| album.server.as_ref() == Some(server))
+    }
+
+    /// whether an album title matches this album, edition notes like `(Live)` are ignored
+    pub fn title_matches(&self, title: &str) -> bool {
+        let base = |t: &str| normalize_name(&VariantType::split_title(t).0);
+        base(&self.name) == base(title)
+    }
+
+    /// Merge the track lists of the same album on several servers.
+    /// Tracks are matched by title, the order of the first list is kept and
+    /// tracks only found on the other servers are appended.
+    pub fn merge_tracks(track_lists: Vec<Vec<MusicAggregator>>) -> Vec<MusicAggregator> {
+        let mut tracks: Vec<MusicAggregator> = Vec::new();
+        let mut index_map: HashMap<String, usize> = HashMap::new();
+        for track_list in track_lists {
+            for track in track_list {
+                let key = normalize_name(&track.name);
+                match index_map.get(&key) {
+                    Some(index) => {
+                        let existing = &mut tracks[*index];
+                        for music in track.musics {
+                            if !existing.musics.iter().any(|x| x.server == music.server) {
+                                existing.musics.push(music);
+                            }
+                        }
+                    }
+                    None => {
+                        index_map.insert(key, tracks.len());
+                        tracks.push(track);
+                    }
+                }
+            }
+        }
+        for (order, track) in tracks.iter_mut().enumerate() {
+            track.order = Some(order as i64);
+        }
+        tracks
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interface::{music_aggregator::Music, server::MusicServer};
+
+    use super::*;
+
+    fn track(server: MusicServer, name: &str) -> MusicAggregator {
+        MusicAggregator::from_music(Music {
+            from_db: false,
+            server,
+            identity: name.to_string(),
+            name: name.to_string(),
+            duration: None,
+            artists: vec![],
+            album: Some("STRAY SHEEP".to_string()),
+            album_id: None,
+            qualities: vec![],
+            cover: None,
+        })
+    }
+
+    #[test]
+    fn test_merge_tracks() {
+        let tracks = AlbumAggregator::merge_tracks(vec![
+            vec![
+                track(MusicServer::Kuwo, "カムパネルラ"),
+                track(MusicServer::Kuwo, "Flamingo"),
+            ],
+            vec![
+                track(MusicServer::Netease, "flamingo"),
+                track(MusicServer::Netease, "カムパネルラ"),
+                track(MusicServer::Netease, "Lemon"),
+            ],
+        ]);
+        assert_eq!(
+            tracks.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(),
+            vec!["カムパネルラ", "Flamingo", "Lemon"]
+        );
+        assert_eq!(tracks[1].musics.len(), 2);
+        assert_eq!(tracks[2].musics.len(), 1);
+        assert_eq!(tracks[2].order, Some(2));
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::HashSet;
+
+use sea_orm::{
+    ColumnTrait as _, Condition, EntityTrait, IntoActiveModel as _, QueryFilter, QueryOrder as _,
+    Set, TransactionTrait as _,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::data::models::artist_aggregator;
+
+use super::{
+    artist::Artist, database::get_db, music_aggregator::MusicAggregator, server::MusicServer,
+    song_group::VariantType, utils::normalize_name,
+};
+
+/// An artist on one music server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerArtist {
+    pub server: MusicServer,
+    pub id: String,
+    pub name: String,
+}
+
+/// The same artist across music servers, artist ids are only meaningful on their own server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtistAggregator {
+    pub from_db: bool,
+    /// primary key in db, `None` if not saved yet
+    pub id: Option<i64>,
+    pub name: String,
+    pub artists: Vec<ServerArtist>,
+}
+
+impl From<artist_aggregator::Model> for ArtistAggregator {
+    fn from(value: artist_aggregator::Model) -> Self {
+        let mut artists = Vec::with_capacity(MusicServer::length());
+        // todo: add more music server
+        if let Some(id) = value.kuwo_artist_id {
+            artists.push(ServerArtist {
+                server: MusicServer::Kuwo,
+                id,
+                name: value.name.clone(),
+            });
+        }
+        if let Some(id) = value.netease_artist_id {
+            artists.push(ServerArtist {
+                server: MusicServer::Netease,
+                id,
+                name: value.name.clone(),
+            });
+        }
+        Self {
+            from_db: true,
+            id: Some(value.id),
+            name: value.name,
+            artists,
+        }
+    }
+}
+
+impl ArtistAggregator {
+    pub fn from_artist(server: MusicServer, artist: &Artist) -> anyhow::Result<Self> {
+        let id = artist
+            .id
+            .clone()
+            .ok_or(anyhow::anyhow!("Artist {} has no id", artist.name))?;
+        Ok(Self {
+            from_db: false,
+            id: None,
+            name: artist.name.clone(),
+            artists: vec![ServerArtist {
+                server,
+                id,
+                name: artist.name.clone(),
+            }],
+        })
+    }
+
+    pub fn artist_id(&self, server: &MusicServer) -> Option<&str> {
+        self.artists
+            .iter()
+            .find(|a| a.server == *server)
+            .map(|a| a.id.as_str())
+    }
+
+    /// whether two artist names are the same after normalization
+    pub fn name_matches(&self, name: &str) -> bool {
+        let name = normalize_name(name);
+        normalize_name(&self.name) == name
+            || self.artists.iter().any(|a| normalize_name(&a.name) == name)
+    }
+
+    /// Save the mapping to db and return its id.
+    /// If any server artist id is already mapped, the missing ids are merged into that row.
+    /// Rows which each hold a part of the mapping are merged into the oldest one,
+    /// rows mapping a server to a different artist id are a conflict.
+    pub async fn save_to_db(&self) -> anyhow::Result<i64> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited"))?;
+
+        // todo: add more music server
+        let mut kuwo_id = self.artist_id(&MusicServer::Kuwo).map(|s| s.to_string());
+        let mut netease_id = self.artist_id(&MusicServer::Netease).map(|s| s.to_string());
+        if kuwo_id.is_none() && netease_id.is_none() {
+            return Err(anyhow::anyhow!("Artist aggregator has no server artist"));
+        }
+
+        let mut condition = Condition::any();
+        if let Some(id) = self.id {
+            condition = condition.add(artist_aggregator::Column::Id.eq(id));
+        }
+        if let Some(id) = kuwo_id.as_ref() {
+            condition = condition.add(artist_aggregator::Column::KuwoArtistId.eq(id));
+        }
+        if let Some(id) = netease_id.as_ref() {
+            condition = condition.add(artist_aggregator::Column::NeteaseArtistId.eq(id));
+        }
+
+        let txn = db.begin().await?;
+        let models = artist_aggregator::Entity::find()
+            .filter(condition)
+            .order_by_asc(artist_aggregator::Column::Id)
+            .all(&txn)
+            .await?;
+        let Some(model) = models.first().cloned() else {
+            let active = artist_aggregator::ActiveModel {
+                id: sea_orm::ActiveValue::NotSet,
+                name: Set(self.name.clone()),
+                kuwo_artist_id: Set(kuwo_id),
+                netease_artist_id: Set(netease_id),
+            };
+            let id = artist_aggregator::Entity::insert(active)
+                .exec(&txn)
+                .await?
+                .last_insert_id;
+            txn.commit().await?;
+            return Ok(id);
+        };
+
+        let merge = |target: &mut Option<String>, other: &Option<String>| -> anyhow::Result<()> {
+            match (target.as_ref(), other) {
+                (Some(a), Some(b)) if a != b => Err(anyhow::anyhow!(
+                    "Artist aggregator {} conflicts with saved artist ids {} and {}",
+                    self.name,
+                    a,
+                    b
+                )),
+                (None, Some(b)) => {
+                    *target = Some(b.clone());
+                    Ok(())
+                }
+                _ => Ok(()),
+            }
+        };
+        for other in &models {
+            merge(&mut kuwo_id, &other.kuwo_artist_id)?;
+            merge(&mut netease_id, &other.netease_artist_id)?;
+        }
+
+        // drop the merged rows first, their artist ids are unique
+        let merged_ids: Vec<i64> = models.iter().skip(1).map(|m| m.id).collect();
+        if !merged_ids.is_empty() {
+            artist_aggregator::Entity::delete_many()
+                .filter(artist_aggregator::Column::Id.is_in(merged_ids))
+                .exec(&txn)
+                .await?;
+        }
+        let id = model.id;
+        if model.kuwo_artist_id != kuwo_id || model.netease_artist_id != netease_id {
+            let mut active = model.into_active_model();
+            active.kuwo_artist_id = Set(kuwo_id);
+            active.netease_artist_id = Set(netease_id);
+            artist_aggregator::Entity::update(active).exec(&txn).await?;
+        }
+        txn.commit().await?;
+        Ok(id)
+    }
+
+    /// find the artist aggregator which contains the server artist id
+    pub async fn find_in_db(server: MusicServer, artist_id: &str) -> anyhow::Result<Option<Self>> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited"))?;
+        // todo: add more music server
+        let column = match server {
+            MusicServer::Kuwo => artist_aggregator::Column::KuwoArtistId,
+            MusicServer::Netease => artist_aggregator::Column::NeteaseArtistId,
+        };
+        let model = artist_aggregator::Entity::find()
+            .filter(column.eq(artist_id))
+            .one(&db)
+            .await?;
+        Ok(model.map(|m| m.into()))
+    }
+
+    pub async fn get_from_db() -> anyhow::Result<Vec<Self>> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited"))?;
+        let models = artist_aggregator::Entity::find().all(&db).await?;
+        Ok(models.into_iter().map(|m| m.into()).collect())
+    }
+
+    pub async fn del_from_db(&self) -> anyhow::Result<()> {
+        let id = self
+            .id
+            .ok_or(anyhow::anyhow!("Can't del non-database artist aggregator"))?;
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited"))?;
+        artist_aggregator::Entity::delete_by_id(id).exec(&db).await?;
+        Ok(())
+    }
+}
+
+/// ratio of shared songs between two discographies, variants are compared by their base title
+pub(crate) fn discography_overlap(a: &[MusicAggregator], b: &[MusicAggregator]) -> f64 {
+    let titles = |aggs: &[MusicAggregator]| -> HashSet<String> {
+        aggs.iter()
+            .map(|agg| normalize_name(&VariantType::split_title(&agg.name).0))
+            .collect()
+    };
+    let (a, b) = (titles(a), titles(b));
+    let min = a.len().min(b.len());
+    if min == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / min as f64
+}
+
+#[cfg(test)]
+mod test {
+    use serial_test::serial;
+
+    use crate::interface::{
+        artist::Artist, database::set_db, music_aggregator::Music, server::MusicServer,
+    };
+
+    use super::*;
+
+    fn agg(name: &str) -> MusicAggregator {
+        MusicAggregator::from_music(Music {
+            from_db: false,
+            server: MusicServer::Kuwo,
+            identity: name.to_string(),
+            name: name.to_string(),
+            duration: None,
+            artists: vec![],
+            album: None,
+            album_id: None,
+            qualities: vec![],
+            cover: None,
+        })
+    }
+
+    #[test]
+    fn test_discography_overlap() {
+        let a = vec![agg("Lemon"), agg("馬と鹿"), agg("感電")];
+        let b = vec![agg("lemon (Live)"), agg("感電"), agg("KICK BACK"), agg("M八七")];
+        assert!((discography_overlap(&a, &b) - 2.0 / 3.0).abs() < f64::EPSILON);
+        assert_eq!(discography_overlap(&a, &[]), 0.0);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_save_to_db() {
+        set_db("sqlite::memory:").await.unwrap();
+
+        let kuwo = ArtistAggregator::from_artist(
+            MusicServer::Kuwo,
+            &Artist {
+                name: "米津玄师".to_string(),
+                id: Some("1".to_string()),
+            },
+        )
+        .unwrap();
+        let id = kuwo.save_to_db().await.unwrap();
+
+        let mut both = kuwo.clone();
+        both.artists.push(ServerArtist {
+            server: MusicServer::Netease,
+            id: "2".to_string(),
+            name: "米津玄師".to_string(),
+        });
+        assert_eq!(both.save_to_db().await.unwrap(), id);
+
+        let found = ArtistAggregator::find_in_db(MusicServer::Netease, "2")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.id, Some(id));
+        assert_eq!(found.artist_id(&MusicServer::Kuwo), Some("1"));
+        assert!(found.name_matches("米津玄师"));
+        assert_eq!(ArtistAggregator::get_from_db().await.unwrap().len(), 1);
+
+        found.del_from_db().await.unwrap();
+        assert!(ArtistAggregator::get_from_db().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_save_to_db_merge() {
+        set_db("sqlite::memory:").await.unwrap();
+
+        let server_artist = |server: MusicServer, id: &str| ServerArtist {
+            server,
+            id: id.to_string(),
+            name: "米津玄师".to_string(),
+        };
+        let artist = |artists: Vec<ServerArtist>| ArtistAggregator {
+            from_db: false,
+            id: None,
+            name: "米津玄师".to_string(),
+            artists,
+        };
+
+        let kuwo_id = artist(vec![server_artist(MusicServer::Kuwo, "1")])
+            .save_to_db()
+            .await
+            .unwrap();
+        let netease_id = artist(vec![server_artist(MusicServer::Netease, "2")])
+            .save_to_db()
+            .await
+            .unwrap();
+        assert_ne!(kuwo_id, netease_id);
+
+        // both rows hold a part of the mapping, they are merged into the oldest one
+        let both = artist(vec![
+            server_artist(MusicServer::Kuwo, "1"),
+            server_artist(MusicServer::Netease, "2"),
+        ]);
+        assert_eq!(both.save_to_db().await.unwrap(), kuwo_id);
+        let saved = ArtistAggregator::get_from_db().await.unwrap();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].artist_id(&MusicServer::Netease), Some("2"));
+
+        // kuwo 1 is already mapped to netease 2
+        let conflict = artist(vec![
+            server_artist(MusicServer::Kuwo, "1"),
+            server_artist(MusicServer::Netease, "3"),
+        ]);
+        assert!(conflict.save_to_db().await.is_err());
+        let saved = ArtistAggregator::get_from_db().await.unwrap();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].artist_id(&MusicServer::Netease), Some("2"));
+    }
+}
@@ -0,0 +1,334 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    library::{Library, LibrarySearchFilters},
+    music_aggregator::MusicAggregator,
+    playlist::Playlist,
+    results::TrackImportResult,
+    server::MusicServer,
+    tracklist::{resolve_tracks, split_artists, TrackEntry},
+};
+
+/// the separator of exported rows, imports detect it from the header
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CsvDelimiter {
+    #[default]
+    Comma,
+    Tab,
+}
+
+impl CsvDelimiter {
+    fn char(&self) -> char {
+        match self {
+            CsvDelimiter::Comma => ',',
+            CsvDelimiter::Tab => '\t',
+        }
+    }
+}
+
+impl Playlist {
+    /// export as CSV or TSV, db playlists use their saved music and online ones are fetched
+    pub async fn to_csv(&self, delimiter: CsvDelimiter) -> Result<String> {
+        let music_aggs = match self.from_db {
+            true => self.get_musics_from_db().await?,
+            false => self.fetch_musics_online(1, 2333).await?,
+        };
+        Ok(write_csv(&music_aggs, delimiter))
+    }
+
+    /// read rows exported by `to_csv`, or a sheet with just `title` and `artist` columns,
+    /// and match them to music aggregators, see `resolve_tracks`.
+    /// rows are taken in the order of their `order` column if there is one.
+    pub async fn resolve_csv(content: &str) -> Result<TrackImportResult> {
+        resolve_tracks(parse_csv(content)?).await
+    }
+}
+
+impl Library {
+    /// export every saved music aggregator as CSV or TSV
+    pub async fn to_csv(delimiter: CsvDelimiter) -> Result<String> {
+        let music_aggs = Library::search("", LibrarySearchFilters::default()).await?;
+        Ok(write_csv(&music_aggs, delimiter))
+    }
+}
+
+/// `name, artists, album, duration, default_server, <server>_id..., qualities, order`,
+/// the values of the default music, durations in seconds
+pub fn write_csv(music_aggs: &[MusicAggregator], delimiter: CsvDelimiter) -> String {
+    let mut header: Vec<String> = ["name", "artists", "album", "duration", "default_server"]
+        .iter()
+        .map(|column| column.to_string())
+        .collect();
+    header.extend(MusicServer::all().iter().map(id_column));
+    header.extend(["qualities".to_string(), "order".to_string()]);
+
+    let mut csv = String::new();
+    push_row(&mut csv, &header, delimiter);
+    for (index, agg) in music_aggs.iter().enumerate() {
+        let music = agg
+            .musics
+            .iter()
+            .find(|music| music.server == agg.default_server)
+            .or(agg.musics.first());
+        let mut row = vec![
+            agg.name.clone(),
+            agg.artist.clone(),
+            music
+                .and_then(|music| music.album.clone())
+                .unwrap_or_default(),
+            music
+                .and_then(|music| music.duration)
+                .map(|duration| duration.to_string())
+                .unwrap_or_default(),
+            agg.default_server.to_string(),
+        ];
+        row.extend(MusicServer::all().iter().map(|server| {
+            agg.musics
+                .iter()
+                .find(|music| music.server == *server)
+                .map(|music| music.identity.clone())
+                .unwrap_or_default()
+        }));
+        row.push(
+            music
+                .map(|music| {
+                    music
+                        .qualities
+                        .iter()
+                        .map(|quality| quality.summary.clone())
+                        .collect::<Vec<_>>()
+                        .join("|")
+                })
+                .unwrap_or_default(),
+        );
+        row.push(agg.order.unwrap_or(index as i64).to_string());
+        push_row(&mut csv, &row, delimiter);
+    }
+    csv
+}
+
+/// tracks of CSV or TSV content with a header row
+pub fn parse_csv(content: &str) -> Result<Vec<TrackEntry>> {
+    let content = content.trim_start_matches('\u{feff}');
+    let first_line = content.lines().next().unwrap_or_default();
+    let delimiter = match first_line.contains('\t') {
+        true => CsvDelimiter::Tab,
+        false => CsvDelimiter::Comma,
+    };
+    let mut rows = read_rows(content, delimiter)?.into_iter();
+    let header: Vec<String> = rows
+        .next()
+        .unwrap_or_default()
+        .iter()
+        .map(|column| column.trim().to_lowercase())
+        .collect();
+    let column = |names: &[&str]| header.iter().position(|c| names.contains(&c.as_str()));
+    let title = column(&["name", "title"]).ok_or(anyhow!(
+        "The header has no name or title column: {}",
+        first_line
+    ))?;
+    let artists = column(&["artists", "artist"]);
+    let album = column(&["album"]);
+    let duration = column(&["duration"]);
+    let order = column(&["order"]);
+    let servers: Vec<(MusicServer, usize)> = MusicServer::all()
+        .into_iter()
+        .filter_map(|server| {
+            let index = column(&[id_column(&server).as_str()])?;
+            Some((server, index))
+        })
+        .collect();
+
+    let mut tracks = Vec::new();
+    for row in rows {
+        let value = |index: Option<usize>| {
+            index
+                .and_then(|index| row.get(index))
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        let Some(name) = value(Some(title)) else {
+            continue;
+        };
+        let track = TrackEntry {
+            title: name,
+            artists: value(artists)
+                .map(|artists| split_artists(&artists))
+                .unwrap_or_default(),
+            album: value(album),
+            duration: value(duration).and_then(|duration| parse_duration(&duration)),
+            cover: None,
+            music_ids: servers
+                .iter()
+                .filter_map(|(server, index)| Some((server.clone(), value(Some(*index))?)))
+                .collect(),
+        };
+        let order = value(order).and_then(|order| order.parse::<i64>().ok());
+        tracks.push((order, track));
+    }
+    // rows without an order keep their place after the ordered ones
+    tracks.sort_by_key(|(order, _)| order.unwrap_or(i64::MAX));
+    Ok(tracks.into_iter().map(|(_, track)| track).collect())
+}
+
+fn id_column(server: &MusicServer) -> String {
+    format!("{}_id", server.to_string().to_lowercase())
+}
+
+/// seconds, or `m:ss` like spreadsheets show them
+fn parse_duration(duration: &str) -> Option<i64> {
+    duration.split(':').try_fold(0, |total, part| {
+        Some(total * 60 + part.trim().parse::<f64>().ok()?.round() as i64)
+    })
+}
+
+fn push_row(csv: &mut String, row: &[String], delimiter: CsvDelimiter) {
+    let delimiter = delimiter.char();
+    let fields: Vec<String> = row
+        .iter()
+        .map(|field| match field.contains([delimiter, '"', '\r', '\n']) {
+            true => format!("\"{}\"", field.replace('"', "\"\"")),
+            false => field.clone(),
+        })
+        .collect();
+    csv.push_str(&fields.join(&delimiter.to_string()));
+    csv.push_str("\r\n");
+}
+
+/// RFC 4180 rows, quoted fields may hold delimiters, quotes and line breaks
+fn read_rows(content: &str, delimiter: CsvDelimiter) -> Result<Vec<Vec<String>>> {
+    let delimiter = delimiter.char();
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, c) if c == delimiter => row.push(std::mem::take(&mut field)),
+            (false, '\r') => {}
+            (false, '\n') => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(anyhow!("Unclosed quote in row {}.", rows.len() + 1));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows.retain(|row| row.iter().any(|field| !field.trim().is_empty()));
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod test {
+    use serial_test::serial;
+
+    use super::*;
+    use crate::interface::{music_aggregator, quality::Quality};
+
+    /// an album that has to be quoted and a quality
+    fn music_agg(server: MusicServer, id: &str, name: &str) -> MusicAggregator {
+        let mut agg = music_aggregator::music_agg(server, id, name);
+        agg.musics[0].album = Some("a, \"b\"".to_string());
+        agg.musics[0].qualities = vec![Quality {
+            summary: "128k".to_string(),
+            bitrate: None,
+            format: None,
+            size: None,
+        }];
+        agg
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let csv = write_csv(
+            &[music_agg(MusicServer::Netease, "7", "x")],
+            CsvDelimiter::Comma,
+        );
+        assert_eq!(
+            csv,
+            "name,artists,album,duration,default_server,kuwo_id,netease_id,qualities,order\r\n\
+             x,artist,\"a, \"\"b\"\"\",215,Netease,,7,128k,0\r\n"
+        );
+        assert_eq!(
+            parse_csv(&csv).unwrap(),
+            vec![TrackEntry {
+                title: "x".to_string(),
+                artists: vec!["artist".to_string()],
+                album: Some("a, \"b\"".to_string()),
+                duration: Some(215),
+                cover: None,
+                music_ids: vec![(MusicServer::Netease, "7".to_string())],
+            }]
+        );
+
+        let tsv = write_csv(&[music_agg(MusicServer::Kuwo, "1", "x")], CsvDelimiter::Tab);
+        assert_eq!(
+            parse_csv(&tsv).unwrap()[0].album,
+            Some("a, \"b\"".to_string())
+        );
+
+        // a sheet of curators
+        let tracks =
+            parse_csv("Title,Artist,Duration\n晴天,周杰伦,4:29\n\n\"Lemon\",米津玄師,\n").unwrap();
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[0].duration, Some(269));
+        assert_eq!(tracks[1].label(), "米津玄師 - Lemon");
+        assert!(parse_csv("artist\nx\n").is_err());
+        assert!(parse_csv("title\n\"x\n").is_err());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_resolve_csv() {
+        let (_, playlist) = music_aggregator::empty_playlist().await;
+        playlist
+            .add_aggs_to_db(&vec![
+                music_agg(MusicServer::Kuwo, "1", "a"),
+                music_agg(MusicServer::Netease, "2", "b"),
+            ])
+            .await
+            .unwrap();
+
+        let csv = playlist.to_csv(CsvDelimiter::Tab).await.unwrap();
+        assert_eq!(csv.lines().count(), 3);
+        assert_eq!(
+            Library::to_csv(CsvDelimiter::Comma)
+                .await
+                .unwrap()
+                .lines()
+                .count(),
+            3
+        );
+
+        // rows are matched by music id, or by name and artists
+        let result = Playlist::resolve_csv(&csv).await.unwrap();
+        assert_eq!(result.music_aggs.len(), 2);
+        let result = Playlist::resolve_csv("title,artist,order\nB,Artist,1\na,artist,0\n")
+            .await
+            .unwrap();
+        assert!(result.unresolved.is_empty());
+        assert_eq!(
+            result
+                .music_aggs
+                .iter()
+                .map(|agg| agg.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+    }
+}
@@ -1,9 +1,60 @@
-use std::{path::PathBuf, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    str::FromStr,
+};
 
-use sea_orm::{ConnectOptions, Database, DatabaseConnection};
-use sea_orm_migration::MigratorTrait as _;
+use sea_orm::{
+    sea_query::{Expr, Query},
+    ActiveModelTrait,
+    ActiveValue::NotSet,
+    ConnectOptions, ConnectionTrait, Database, DatabaseConnection, DbErr, EntityName as _,
+    EntityTrait, IntoActiveModel, Iterable as _, PaginatorTrait as _, PrimaryKeyToColumn as _,
+    PrimaryKeyTrait, QueryOrder as _, QuerySelect as _, Set, TransactionTrait as _,
+};
+use sea_orm_migration::{seaql_migrations, MigratorTrait as _, SchemaManager};
+use tokio::sync::mpsc::UnboundedSender;
 
-use crate::{data::migrations::Migrator, DB_POOL};
+use crate::{
+    data::{
+        migrations::Migrator,
+        models::{
+            artist_aggregator, library_journal, music_aggregator, play_history, playlist,
+            playlist_collection, playlist_music_junction,
+        },
+    },
+    server::{kuwo, netease},
+    DB_POOL,
+};
+
+use super::{
+    results::{DatabaseMigrationResult, MigrationProgress},
+    utils::now_millis,
+};
+
+/// how `migrate_database` copies a library
+#[derive(Debug, Clone)]
+pub struct MigrationOptions {
+    /// rows read and inserted at once
+    pub batch_size: u64,
+    /// overwrite a target which already holds a library, otherwise it must be empty
+    pub replace_target: bool,
+    /// gets a `MigrationProgress` after every batch
+    pub progress: Option<UnboundedSender<MigrationProgress>>,
+    /// copy from a source without a library, or without a database file, like from a new one
+    pub allow_empty_source: bool,
+}
+
+impl Default for MigrationOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 500,
+            replace_target: false,
+            progress: None,
+            allow_empty_source: false,
+        }
+    }
+}
 
 pub async fn create_sqlite_db_file(database_url: &str) -> Result<(), anyhow::Error> {
     if database_url == "sqlite::memory:" {
@@ -30,19 +81,143 @@ pub async fn create_sqlite_db_file(database_url: &str) -> Result<(), anyhow::Err
     Ok(())
 }
 
-pub async fn set_db(database_url: &str) -> Result<(), anyhow::Error> {
-    close_db().await?;
+/// the source of a migration, a sqlite file is read through an upgraded copy
+struct MigrationSource {
+    db: DatabaseConnection,
+    copy: Option<PathBuf>,
+}
 
-    if database_url.starts_with("sqlite") {
-        create_sqlite_db_file(database_url).await?;
+impl MigrationSource {
+    async fn close(self) -> Result<(), anyhow::Error> {
+        self.db.close().await?;
+        if let Some(copy) = self.copy {
+            tokio::fs::remove_file(copy).await?;
+        }
+        Ok(())
     }
+}
 
+fn connect_options(database_url: String) -> ConnectOptions {
     let mut connection_options = ConnectOptions::new(database_url);
     connection_options.sqlx_logging_level(log::LevelFilter::Debug);
+    connection_options
+}
+
+/// connect to the source of a migration, the source itself is never written.
+/// a sqlite file of an older version is copied to a temporary file and the copy is migrated,
+/// other databases have to be up to date. `None` when the sqlite file doesn't exist.
+async fn connect_source(database_url: &str) -> Result<Option<MigrationSource>, anyhow::Error> {
+    if !database_url.starts_with("sqlite") || database_url == "sqlite::memory:" {
+        let db = Database::connect(connect_options(database_url.to_string())).await?;
+        if holds_library(&db).await? && !is_up_to_date(&db).await? {
+            db.close().await?;
+            return Err(anyhow::anyhow!(
+                "The source database is of an older version, open it with this version before migrating it."
+            ));
+        }
+        return Ok(Some(MigrationSource { db, copy: None }));
+    }
+
+    let db_file = database_url
+        .split("//")
+        .last()
+        .and_then(|path| path.split('?').next())
+        .ok_or(anyhow::anyhow!(
+            "Invalid database url, use 'sqlite://path/to/database.db'"
+        ))?;
+    if !PathBuf::from_str(db_file)?.exists() {
+        return Ok(None);
+    }
+    let mut source_url = database_url.to_string();
+    source_url.push(if source_url.contains('?') { '&' } else { '?' });
+    source_url.push_str("mode=ro");
+    let source = Database::connect(connect_options(source_url)).await?;
+    let copy = std::env::temp_dir().join(format!(
+        "music_api_migration_{}_{}.db",
+        std::process::id(),
+        now_millis()
+    ));
+    // a consistent snapshot, with what is still in the write-ahead log
+    let vacuum = source
+        .execute_unprepared(&format!(
+            "VACUUM INTO '{}'",
+            copy.display().to_string().replace('\'', "''")
+        ))
+        .await;
+    source.close().await?;
+    vacuum?;
+
+    let source = MigrationSource {
+        db: Database::connect(connect_options(format!("sqlite://{}", copy.display()))).await?,
+        copy: Some(copy),
+    };
+    if let Err(e) = Migrator::up(&source.db, None).await {
+        source.close().await?;
+        return Err(e.into());
+    }
+    Ok(Some(source))
+}
 
-    let db_connection = Database::connect(connection_options).await?;
+/// whether every migration ran on `db`, nothing is written to it
+async fn is_up_to_date(db: &DatabaseConnection) -> Result<bool, anyhow::Error> {
+    if !SchemaManager::new(db)
+        .has_table(seaql_migrations::Entity.table_name())
+        .await?
+    {
+        return Ok(false);
+    }
+    let applied = seaql_migrations::Entity::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect::<HashSet<_>>();
+    Ok(Migrator::migrations()
+        .iter()
+        .all(|migration| applied.contains(migration.name())))
+}
+
+/// whether `db` has playlists, music or play history, a database of an older version counts
+async fn holds_library(db: &DatabaseConnection) -> Result<bool, anyhow::Error> {
+    // no `E::find().count`, it would select columns an older version doesn't have
+    async fn has_rows<E: EntityTrait>(db: &DatabaseConnection, entity: E) -> Result<bool, DbErr> {
+        if !SchemaManager::new(db)
+            .has_table(entity.table_name())
+            .await?
+        {
+            return Ok(false);
+        }
+        let statement = Query::select()
+            .expr(Expr::val(1))
+            .from(entity)
+            .limit(1)
+            .to_owned();
+        Ok(db
+            .query_one(db.get_database_backend().build(&statement))
+            .await?
+            .is_some())
+    }
+    Ok(has_rows(db, playlist::Entity).await?
+        || has_rows(db, music_aggregator::Entity).await?
+        || has_rows(db, play_history::Entity).await?)
+}
+
+/// connect and bring the schema up to date, the database in use is left alone
+async fn connect_db(database_url: &str) -> Result<DatabaseConnection, anyhow::Error> {
+    if database_url.starts_with("sqlite") {
+        create_sqlite_db_file(database_url).await?;
+    }
+
+    let db_connection = Database::connect(connect_options(database_url.to_string())).await?;
 
     Migrator::up(&db_connection, None).await?;
+    Ok(db_connection)
+}
+
+pub async fn set_db(database_url: &str) -> Result<(), anyhow::Error> {
+    close_db().await?;
+
+    let db_connection = connect_db(database_url).await?;
 
     let mut db_pool = DB_POOL.write().await;
 
@@ -73,15 +248,300 @@ pub async fn clear_db() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+/// drop and create every table, the library is left with the default collection
 pub async fn reinit_db() -> Result<(), anyhow::Error> {
     let db = get_db()
         .await
         .ok_or(anyhow::anyhow!("Database is not inited"))?;
     Migrator::down(&db, None).await?;
     Migrator::up(&db, None).await?;
+    // not through `PlaylistCollection::insert_to_db`, there is nothing to undo
+    playlist_collection::Entity::insert(playlist_collection::ActiveModel {
+        id: NotSet,
+        order: Set(1),
+        name: Set("我的歌单".to_string()),
+        created_at: Set(now_millis()),
+        updated_at: Set(now_millis()),
+        deleted_at: Set(None),
+    })
+    .exec(&db)
+    .await?;
     Ok(())
 }
 
+/// copy the library from one database to another, e.g. from sqlite on a phone to postgres
+/// on a server. every table is copied in batches inside one transaction, collections and
+/// playlists get new ids and the rows pointing at them are remapped. the row counts of the
+/// target are checked against the source before the copy is committed.
+/// the source is only read, it has to hold a library unless `allow_empty_source` is set.
+/// the undo history stays behind, its entries refer to the old ids.
+pub async fn migrate_database(
+    from_url: &str,
+    to_url: &str,
+    options: MigrationOptions,
+) -> Result<DatabaseMigrationResult, anyhow::Error> {
+    if from_url == to_url {
+        return Err(anyhow::anyhow!(
+            "The source and the target database are the same."
+        ));
+    }
+    if options.batch_size == 0 {
+        return Err(anyhow::anyhow!("The migration batch size is 0."));
+    }
+    let source = match connect_source(from_url).await? {
+        Some(source) if holds_library(&source.db).await? => source,
+        source => {
+            if let Some(source) = source {
+                source.close().await?;
+            }
+            if !options.allow_empty_source {
+                return Err(anyhow::anyhow!(
+                    "The source database holds no library, set allow_empty_source to copy it anyway."
+                ));
+            }
+            // nothing to copy, the target gets the library of a new database
+            MigrationSource {
+                db: connect_db("sqlite::memory:").await?,
+                copy: None,
+            }
+        }
+    };
+    let target = match connect_db(to_url).await {
+        Ok(target) => target,
+        Err(e) => {
+            source.close().await?;
+            return Err(e);
+        }
+    };
+    let result = migrate(&source.db, &target, &options).await;
+    source.close().await?;
+    target.close().await?;
+    result
+}
+
+/// delete every row of the library and the undo history, unlike `reinit_db`
+/// it can run inside the transaction that writes the new library
+pub(crate) async fn clear_library<C: ConnectionTrait>(db: &C) -> Result<(), anyhow::Error> {
+    // referencing rows first, the undo history would point at the deleted rows
+    library_journal::Entity::delete_many().exec(db).await?;
+    playlist_music_junction::Entity::delete_many()
+        .exec(db)
+        .await?;
+    // todo: add more music server
+    kuwo::model::Entity::delete_many().exec(db).await?;
+    netease::model::Entity::delete_many().exec(db).await?;
+    music_aggregator::Entity::delete_many().exec(db).await?;
+    playlist::Entity::delete_many().exec(db).await?;
+    playlist_collection::Entity::delete_many().exec(db).await?;
+    play_history::Entity::delete_many().exec(db).await?;
+    artist_aggregator::Entity::delete_many().exec(db).await?;
+    Ok(())
+}
+
+async fn migrate(
+    source: &DatabaseConnection,
+    target: &DatabaseConnection,
+    options: &MigrationOptions,
+) -> Result<DatabaseMigrationResult, anyhow::Error> {
+    if !options.replace_target && holds_library(target).await? {
+        return Err(anyhow::anyhow!(
+            "The target database already holds a library, set replace_target to overwrite it."
+        ));
+    }
+
+    let txn = target.begin().await?;
+    clear_library(&txn).await?;
+
+    let mut copier = TableCopier {
+        source,
+        target: &txn,
+        options,
+        result: DatabaseMigrationResult::default(),
+    };
+    copier
+        .copy::<play_history::Entity>(|model| {
+            let mut m = model.into_active_model().reset_all();
+            m.id = NotSet;
+            Ok(m)
+        })
+        .await?;
+    copier
+        .copy::<artist_aggregator::Entity>(|model| {
+            let mut m = model.into_active_model().reset_all();
+            m.id = NotSet;
+            Ok(m)
+        })
+        .await?;
+    let collection_ids = copier
+        .copy_with_ids::<playlist_collection::Entity>(|model| {
+            let old_id = model.id;
+            let mut m = model.into_active_model().reset_all();
+            m.id = NotSet;
+            Ok((old_id, m))
+        })
+        .await?;
+    let playlist_ids = copier
+        .copy_with_ids::<playlist::Entity>(|model| {
+            let old_id = model.id;
+            let collection_id =
+                *collection_ids
+                    .get(&model.collection_id)
+                    .ok_or(anyhow::anyhow!(
+                        "Playlist {} has no playlist collection.",
+                        model.id
+                    ))?;
+            let mut m = model.into_active_model().reset_all();
+            m.id = NotSet;
+            m.collection_id = Set(collection_id);
+            Ok((old_id, m))
+        })
+        .await?;
+    copier
+        .copy::<music_aggregator::Entity>(|model| Ok(model.into_active_model().reset_all()))
+        .await?;
+    // todo: add more music server
+    copier
+        .copy::<kuwo::model::Entity>(|model| Ok(model.into_active_model().reset_all()))
+        .await?;
+    copier
+        .copy::<netease::model::Entity>(|model| Ok(model.into_active_model().reset_all()))
+        .await?;
+    copier
+        .copy::<playlist_music_junction::Entity>(|model| {
+            let playlist_id = *playlist_ids.get(&model.playlist_id).ok_or(anyhow::anyhow!(
+                "Playlist music junction of {} has no playlist.",
+                model.music_aggregator_id
+            ))?;
+            let mut m = model.into_active_model().reset_all();
+            m.playlist_id = Set(playlist_id);
+            Ok(m)
+        })
+        .await?;
+
+    let result = copier.result;
+    txn.commit().await?;
+    Ok(result)
+}
+
+/// copies tables from the source into the target transaction, in primary key order
+struct TableCopier<'a, C: ConnectionTrait> {
+    source: &'a DatabaseConnection,
+    target: &'a C,
+    options: &'a MigrationOptions,
+    result: DatabaseMigrationResult,
+}
+
+impl<C: ConnectionTrait> TableCopier<'_, C> {
+    async fn fetch<E: EntityTrait>(&self, offset: u64) -> Result<Vec<E::Model>, anyhow::Error> {
+        let mut query = E::find();
+        for key in E::PrimaryKey::iter() {
+            query = query.order_by_asc(key.into_column());
+        }
+        Ok(query
+            .offset(offset)
+            .limit(self.options.batch_size)
+            .all(self.source)
+            .await?)
+    }
+
+    fn report(&self, table: &str, copied: u64, total: u64) {
+        if let Some(progress) = &self.options.progress {
+            let _ = progress.send(MigrationProgress {
+                table: table.to_string(),
+                copied,
+                total,
+            });
+        }
+    }
+
+    /// the target must hold as many rows as the source
+    async fn verify<E>(&mut self, copied: u64, total: u64) -> Result<(), anyhow::Error>
+    where
+        E: EntityTrait,
+        E::Model: Sync,
+    {
+        let table = E::default().table_name().to_string();
+        let rows = E::find().count(self.target).await?;
+        if copied != total || rows != total {
+            return Err(anyhow::anyhow!(
+                "Table {} has {} rows in the source but {} in the target, the migration is rolled back.",
+                table,
+                total,
+                rows
+            ));
+        }
+        self.result.tables.push((table, rows));
+        Ok(())
+    }
+
+    /// insert a batch at a time, `convert` prepares a row for the target
+    async fn copy<E>(
+        &mut self,
+        mut convert: impl FnMut(E::Model) -> Result<E::ActiveModel, anyhow::Error>,
+    ) -> Result<(), anyhow::Error>
+    where
+        E: EntityTrait,
+        E::Model: IntoActiveModel<E::ActiveModel> + Sync,
+        E::ActiveModel: ActiveModelTrait<Entity = E> + Send,
+    {
+        let table = E::default().table_name().to_string();
+        let total = E::find().count(self.source).await?;
+        let mut copied = 0;
+        loop {
+            let batch = self.fetch::<E>(copied).await?;
+            let len = batch.len() as u64;
+            if len > 0 {
+                let models = batch
+                    .into_iter()
+                    .map(&mut convert)
+                    .collect::<Result<Vec<_>, _>>()?;
+                E::insert_many(models)
+                    .exec_without_returning(self.target)
+                    .await?;
+            }
+            copied += len;
+            self.report(&table, copied, total);
+            if len < self.options.batch_size {
+                break;
+            }
+        }
+        self.verify::<E>(copied, total).await
+    }
+
+    /// insert row by row to learn the new ids, `convert` returns the old id along with the row
+    async fn copy_with_ids<E>(
+        &mut self,
+        mut convert: impl FnMut(E::Model) -> Result<(i64, E::ActiveModel), anyhow::Error>,
+    ) -> Result<HashMap<i64, i64>, anyhow::Error>
+    where
+        E: EntityTrait,
+        E::Model: Sync,
+        E::PrimaryKey: PrimaryKeyTrait<ValueType = i64>,
+        E::ActiveModel: ActiveModelTrait<Entity = E> + Send,
+    {
+        let table = E::default().table_name().to_string();
+        let total = E::find().count(self.source).await?;
+        let mut ids = HashMap::new();
+        let mut copied = 0;
+        loop {
+            let batch = self.fetch::<E>(copied).await?;
+            let len = batch.len() as u64;
+            for model in batch {
+                let (old_id, m) = convert(model)?;
+                let id = E::insert(m).exec(self.target).await?.last_insert_id;
+                ids.insert(old_id, id);
+            }
+            copied += len;
+            self.report(&table, copied, total);
+            if len < self.options.batch_size {
+                break;
+            }
+        }
+        self.verify::<E>(copied, total).await?;
+        Ok(ids)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::interface::{
@@ -145,7 +605,10 @@ mod test {
     #[serial_test::serial]
     async fn test_sqlite() {
         let _ = tracing_subscriber::fmt::try_init();
-        set_db("sqlite://./sample_data/test.db").await.unwrap();
+        let path = std::env::temp_dir().join("music_api_test.db");
+        set_db(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
         reinit_db().await.unwrap();
         test_op().await;
     }
@@ -171,4 +634,166 @@ mod test {
         reinit_db().await.unwrap();
         test_op().await;
     }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_migrate_database() {
+        use crate::interface::music_aggregator::music_agg;
+
+        let dir = std::env::temp_dir().join("music_api_test_migrate");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let from_url = format!("sqlite://{}", dir.join("from.db").display());
+        let to_url = format!("sqlite://{}", dir.join("to.db").display());
+
+        set_db(&from_url).await.unwrap();
+        for name in ["a", "b"] {
+            let collection_id = PlaylistCollection::new(name.to_string())
+                .insert_to_db()
+                .await
+                .unwrap();
+            let id = Playlist::new(name.to_string(), None, None, vec![])
+                .insert_to_db(collection_id)
+                .await
+                .unwrap();
+            let music_aggs: Vec<MusicAggregator> = (0..3)
+                .map(|i| {
+                    music_agg(
+                        MusicServer::Kuwo,
+                        &format!("{}{}", name, i),
+                        &format!("{}{}", name, i),
+                    )
+                })
+                .collect();
+            Playlist::find_in_db(id)
+                .await
+                .unwrap()
+                .add_aggs_to_db(&music_aggs)
+                .await
+                .unwrap();
+        }
+        close_db().await.unwrap();
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let options = MigrationOptions {
+            batch_size: 2,
+            progress: Some(sender),
+            ..Default::default()
+        };
+        let result = migrate_database(&from_url, &to_url, options.clone())
+            .await
+            .unwrap();
+        let rows = |table: &str| {
+            result
+                .tables
+                .iter()
+                .find(|(name, _)| name == table)
+                .map(|(_, rows)| *rows)
+        };
+        assert_eq!(rows("playlist"), Some(2));
+        assert_eq!(rows("music_aggregator"), Some(6));
+        assert_eq!(rows("kuwo_music"), Some(6));
+        assert_eq!(rows("playlist_music_junction"), Some(6));
+        let mut last = None;
+        while let Ok(progress) = receiver.try_recv() {
+            assert!(progress.copied <= progress.total);
+            last = Some(progress);
+        }
+        assert_eq!(last.unwrap().table, "playlist_music_junction");
+
+        // the target holds a library now
+        assert!(migrate_database(&from_url, &to_url, options.clone())
+            .await
+            .is_err());
+        let options = MigrationOptions {
+            replace_target: true,
+            ..options
+        };
+        migrate_database(&from_url, &to_url, options).await.unwrap();
+
+        set_db(&to_url).await.unwrap();
+        let playlists = Playlist::get_from_db().await.unwrap();
+        assert_eq!(playlists.len(), 2);
+        for playlist in playlists {
+            let music_aggs = playlist.get_musics_from_db().await.unwrap();
+            assert_eq!(music_aggs.len(), 3);
+            assert!(music_aggs[0].name.starts_with(&playlist.name));
+        }
+        close_db().await.unwrap();
+
+        // a missing source is neither created nor copied, unless that is allowed
+        let missing = dir.join("missing.db");
+        let missing_url = format!("sqlite://{}", missing.display());
+        let options = MigrationOptions {
+            replace_target: true,
+            ..Default::default()
+        };
+        assert!(migrate_database(&missing_url, &to_url, options.clone())
+            .await
+            .is_err());
+        assert!(!missing.exists());
+        let options = MigrationOptions {
+            allow_empty_source: true,
+            ..options
+        };
+        migrate_database(&missing_url, &to_url, options)
+            .await
+            .unwrap();
+        assert!(!missing.exists());
+        set_db(&to_url).await.unwrap();
+        assert!(Playlist::get_from_db().await.unwrap().is_empty());
+        close_db().await.unwrap();
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_migrate_old_database() {
+        let dir = std::env::temp_dir().join("music_api_test_migrate_old");
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let from_url = format!("sqlite://{}", dir.join("from.db").display());
+        let to_url = format!("sqlite://{}", dir.join("to.db").display());
+
+        // the tables of the first release, without timestamps, trash or play history
+        create_sqlite_db_file(&from_url).await.unwrap();
+        let source = Database::connect(&from_url).await.unwrap();
+        Migrator::up(&source, Some(6)).await.unwrap();
+        for statement in [
+            r#"INSERT INTO playlist_collection (name, "order") VALUES ('old', 1)"#,
+            r#"INSERT INTO playlist (name, "order", collection_id) VALUES ('old', 1, 1)"#,
+            r#"INSERT INTO music_aggregator (identity, kuwo_music_id, default_server)
+               VALUES ('song#+#artist', '1', 'K')"#,
+            r#"INSERT INTO kuwo_music (music_id, name, artists, qualities, cover, duration)
+               VALUES ('1', 'song', '[{"name":"artist","id":null}]', '[]', 'cover', 200)"#,
+            r#"INSERT INTO playlist_music_junction (playlist_id, music_aggregator_id, "order")
+               VALUES (1, 'song#+#artist', 1)"#,
+        ] {
+            source.execute_unprepared(statement).await.unwrap();
+        }
+        source.close().await.unwrap();
+
+        let result = migrate_database(&from_url, &to_url, MigrationOptions::default())
+            .await
+            .unwrap();
+        assert!(result
+            .tables
+            .contains(&("playlist_music_junction".to_string(), 1)));
+        // the source is left as it was
+        let source = Database::connect(&from_url).await.unwrap();
+        assert!(!SchemaManager::new(&source)
+            .has_table(play_history::Entity.table_name())
+            .await
+            .unwrap());
+        source.close().await.unwrap();
+
+        set_db(&to_url).await.unwrap();
+        let playlists = Playlist::get_from_db().await.unwrap();
+        assert_eq!(playlists.len(), 1);
+        let music_aggs = playlists[0].get_musics_from_db().await.unwrap();
+        assert_eq!(music_aggs.len(), 1);
+        assert_eq!(music_aggs[0].identity(), "song#+#artist");
+        close_db().await.unwrap();
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
 }
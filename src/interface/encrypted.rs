@@ -0,0 +1,337 @@
+use std::{path::PathBuf, str::FromStr};
+
+use aes::{
+    cipher::{BlockEncrypt, KeyInit},
+    Aes256,
+};
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+
+use super::json::MusicDataJson;
+
+/// bump when the header or the ciphers change, older archives are still read
+pub const ENCRYPTED_FORMAT_VERSION: u8 = 1;
+
+const MAGIC: &[u8; 7] = b"MAPIENC";
+// pbkdf2-hmac-sha256 rounds of new archives, stored in the header
+const KDF_ITERATIONS: u32 = 600_000;
+// archives asking for more are refused instead of hanging on a forged header
+const MAX_KDF_ITERATIONS: u32 = 10_000_000;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const CHECK_LEN: usize = 8;
+const TAG_LEN: usize = 16;
+// magic, version, iterations, salt, nonce and password check
+const HEADER_LEN: usize = MAGIC.len() + 1 + 4 + SALT_LEN + NONCE_LEN + CHECK_LEN;
+
+impl MusicDataJson {
+    /// `to_json` encrypted with a password, see `encrypt_backup`
+    pub fn to_encrypted(&self, password: &str) -> Result<Vec<u8>> {
+        encrypt_backup(self.to_json()?.as_bytes(), password)
+    }
+
+    /// read an archive from `to_encrypted`, of any format version like `from_json`
+    pub fn from_encrypted(data: &[u8], password: &str) -> Result<Self> {
+        let json = decrypt_backup(data, password)?;
+        Self::from_json(std::str::from_utf8(&json)?)
+    }
+
+    pub async fn save_encrypted_to(&self, path: &str, password: &str) -> Result<()> {
+        let path = PathBuf::from_str(path)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        tokio::fs::write(path, self.to_encrypted(password)?).await?;
+        Ok(())
+    }
+
+    pub async fn load_encrypted_from(path: &str, password: &str) -> Result<Self> {
+        let data = tokio::fs::read(path).await?;
+        Self::from_encrypted(&data, password)
+    }
+}
+
+/// whether the data starts like an archive of `encrypt_backup`
+pub fn is_encrypted_backup(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// AES-256-GCM with a key derived from the password by PBKDF2-HMAC-SHA256.
+/// the header carries the format version, the rounds, the salt, the nonce and a password check,
+/// and is authenticated along with the ciphertext.
+pub fn encrypt_backup(plaintext: &[u8], password: &str) -> Result<Vec<u8>> {
+    encrypt_with_iterations(plaintext, password, KDF_ITERATIONS)
+}
+
+fn encrypt_with_iterations(plaintext: &[u8], password: &str, iterations: u32) -> Result<Vec<u8>> {
+    if password.is_empty() {
+        return Err(anyhow!("The backup password is empty."));
+    }
+    let salt: [u8; SALT_LEN] = rand::random();
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let (key, check) = derive_keys(password, &salt, iterations);
+
+    let mut data = Vec::with_capacity(HEADER_LEN + plaintext.len() + TAG_LEN);
+    data.extend_from_slice(MAGIC);
+    data.push(ENCRYPTED_FORMAT_VERSION);
+    data.extend_from_slice(&iterations.to_be_bytes());
+    data.extend_from_slice(&salt);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&check);
+    data.extend_from_slice(plaintext);
+
+    let gcm = Gcm::new(&key);
+    let (header, body) = data.split_at_mut(HEADER_LEN);
+    let tag = gcm.seal(&nonce, header, body);
+    data.extend_from_slice(&tag);
+    Ok(data)
+}
+
+/// the plaintext of an archive from `encrypt_backup`.
+/// a wrong password and a damaged or tampered archive are told apart in the error.
+pub fn decrypt_backup(data: &[u8], password: &str) -> Result<Vec<u8>> {
+    if !is_encrypted_backup(data) {
+        return Err(anyhow!("Not an encrypted backup."));
+    }
+    if data.len() < HEADER_LEN + TAG_LEN {
+        return Err(anyhow!("The encrypted backup is truncated."));
+    }
+    let version = data[MAGIC.len()];
+    if version > ENCRYPTED_FORMAT_VERSION {
+        return Err(anyhow!(
+            "Encrypted backup version {} is newer than the supported version {}.",
+            version,
+            ENCRYPTED_FORMAT_VERSION
+        ));
+    }
+    let (header, rest) = data.split_at(HEADER_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+    let mut fields = &header[MAGIC.len() + 1..];
+    let mut take = |len: usize| {
+        let (field, rest) = fields.split_at(len);
+        fields = rest;
+        field
+    };
+    let iterations = u32::from_be_bytes(take(4).try_into()?);
+    let salt = take(SALT_LEN);
+    let nonce: [u8; NONCE_LEN] = take(NONCE_LEN).try_into()?;
+    let check = take(CHECK_LEN);
+    if iterations == 0 || iterations > MAX_KDF_ITERATIONS {
+        return Err(anyhow!(
+            "The encrypted backup is damaged: invalid key derivation rounds {}.",
+            iterations
+        ));
+    }
+
+    let (key, expected_check) = derive_keys(password, salt, iterations);
+    if !constant_time_eq(check, &expected_check) {
+        return Err(anyhow!("Wrong password for the encrypted backup."));
+    }
+    let mut plaintext = ciphertext.to_vec();
+    if !Gcm::new(&key).open(&nonce, header, &mut plaintext, tag) {
+        return Err(anyhow!(
+            "The encrypted backup is damaged or was tampered with."
+        ));
+    }
+    Ok(plaintext)
+}
+
+/// the cipher key and the password check, from 64 bytes of PBKDF2
+fn derive_keys(password: &str, salt: &[u8], iterations: u32) -> ([u8; 32], [u8; CHECK_LEN]) {
+    let derived = pbkdf2_hmac_sha256(password.as_bytes(), salt, iterations, 64);
+    let mut key = [0; 32];
+    key.copy_from_slice(&derived[..32]);
+    let mut check = [0; CHECK_LEN];
+    check.copy_from_slice(&Sha256::digest(&derived[32..])[..CHECK_LEN]);
+    (key, check)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// HMAC-SHA256 with the padded key absorbed once, rounds only clone the states
+#[derive(Clone)]
+struct HmacSha256 {
+    inner: Sha256,
+    outer: Sha256,
+}
+
+impl HmacSha256 {
+    fn new(key: &[u8]) -> Self {
+        let mut block = [0u8; 64];
+        match key.len() > 64 {
+            true => block[..32].copy_from_slice(&Sha256::digest(key)),
+            false => block[..key.len()].copy_from_slice(key),
+        }
+        let pad = |byte: u8| {
+            let mut hasher = Sha256::new();
+            hasher.update(block.map(|b| b ^ byte));
+            hasher
+        };
+        Self {
+            inner: pad(0x36),
+            outer: pad(0x5c),
+        }
+    }
+
+    fn mac(&self, message: &[&[u8]]) -> [u8; 32] {
+        let mut inner = self.inner.clone();
+        for part in message {
+            inner.update(part);
+        }
+        let mut outer = self.outer.clone();
+        outer.update(inner.finalize());
+        outer.finalize().into()
+    }
+}
+
+/// RFC 8018 PBKDF2 with HMAC-SHA256
+fn pbkdf2_hmac_sha256(password: &[u8], salt: &[u8], iterations: u32, len: usize) -> Vec<u8> {
+    let hmac = HmacSha256::new(password);
+    let mut derived = Vec::with_capacity(len);
+    for block in 1..=len.div_ceil(32) as u32 {
+        let mut u = hmac.mac(&[salt, &block.to_be_bytes()]);
+        let mut t = u;
+        for _ in 1..iterations {
+            u = hmac.mac(&[&u]);
+            t.iter_mut().zip(u).for_each(|(t, u)| *t ^= u);
+        }
+        derived.extend_from_slice(&t);
+    }
+    derived.truncate(len);
+    derived
+}
+
+/// AES-256-GCM of NIST SP 800-38D with 96-bit nonces and 128-bit tags
+struct Gcm {
+    cipher: Aes256,
+    h: u128,
+}
+
+impl Gcm {
+    fn new(key: &[u8; 32]) -> Self {
+        let cipher = Aes256::new(key.into());
+        let h = u128::from_be_bytes(encrypt_block(&cipher, [0; 16]));
+        Self { cipher, h }
+    }
+
+    /// encrypt in place and return the tag
+    fn seal(&self, nonce: &[u8; NONCE_LEN], aad: &[u8], data: &mut [u8]) -> [u8; TAG_LEN] {
+        self.ctr(nonce, data);
+        self.tag(nonce, aad, data)
+    }
+
+    /// check the tag, then decrypt in place
+    fn open(&self, nonce: &[u8; NONCE_LEN], aad: &[u8], data: &mut [u8], tag: &[u8]) -> bool {
+        if !constant_time_eq(&self.tag(nonce, aad, data), tag) {
+            return false;
+        }
+        self.ctr(nonce, data);
+        true
+    }
+
+    fn counter_block(nonce: &[u8; NONCE_LEN], counter: u32) -> [u8; 16] {
+        let mut block = [0; 16];
+        block[..NONCE_LEN].copy_from_slice(nonce);
+        block[NONCE_LEN..].copy_from_slice(&counter.to_be_bytes());
+        block
+    }
+
+    fn ctr(&self, nonce: &[u8; NONCE_LEN], data: &mut [u8]) {
+        for (index, chunk) in data.chunks_mut(16).enumerate() {
+            let counter = (index as u32).wrapping_add(2);
+            let stream = encrypt_block(&self.cipher, Self::counter_block(nonce, counter));
+            chunk.iter_mut().zip(stream).for_each(|(b, s)| *b ^= s);
+        }
+    }
+
+    fn tag(&self, nonce: &[u8; NONCE_LEN], aad: &[u8], ciphertext: &[u8]) -> [u8; TAG_LEN] {
+        let mut y = 0u128;
+        for data in [aad, ciphertext] {
+            for chunk in data.chunks(16) {
+                let mut block = [0; 16];
+                block[..chunk.len()].copy_from_slice(chunk);
+                y = gf_mul(y ^ u128::from_be_bytes(block), self.h);
+            }
+        }
+        let lengths = ((aad.len() as u128 * 8) << 64) | (ciphertext.len() as u128 * 8);
+        y = gf_mul(y ^ lengths, self.h);
+        let mask = u128::from_be_bytes(encrypt_block(&self.cipher, Self::counter_block(nonce, 1)));
+        (y ^ mask).to_be_bytes()
+    }
+}
+
+fn encrypt_block(cipher: &Aes256, block: [u8; 16]) -> [u8; 16] {
+    let mut block = aes::Block::from(block);
+    cipher.encrypt_block(&mut block);
+    block.into()
+}
+
+/// multiplication in GF(2^128) of GHASH, without branches on the operands
+fn gf_mul(x: u128, y: u128) -> u128 {
+    const R: u128 = 0xe1 << 120;
+    let mut z = 0;
+    let mut v = y;
+    for i in 0..128 {
+        z ^= v & 0u128.wrapping_sub((x >> (127 - i)) & 1);
+        v = (v >> 1) ^ (R & 0u128.wrapping_sub(v & 1));
+    }
+    z
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interface::json::MusicAggregatorJsonVec;
+
+    #[test]
+    fn test_primitives() {
+        // RFC 7914 section 11
+        assert_eq!(
+            hex::encode(pbkdf2_hmac_sha256(b"passwd", b"salt", 1, 32)),
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc"
+        );
+        // GCM spec test cases 13 and 14
+        let gcm = Gcm::new(&[0; 32]);
+        assert_eq!(
+            hex::encode(gcm.seal(&[0; 12], &[], &mut [])),
+            "530f8afbc74536b9a963b4f1c4cb738b"
+        );
+        let mut data = [0; 16];
+        let tag = gcm.seal(&[0; 12], &[], &mut data);
+        assert_eq!(hex::encode(data), "cea7403d4d606b6e074ec5d3baf39d18");
+        assert_eq!(hex::encode(tag), "d0d1c8a799996bf0265b98b5d48ab919");
+        assert!(gcm.open(&[0; 12], &[], &mut data, &tag));
+        assert_eq!(data, [0; 16]);
+    }
+
+    #[test]
+    fn test_encrypted_backup() {
+        let json = MusicDataJson::MusicAggregators(MusicAggregatorJsonVec(vec![]));
+        let plaintext = json.to_json().unwrap();
+        let data = encrypt_with_iterations(plaintext.as_bytes(), "secret", 1000).unwrap();
+        assert!(is_encrypted_backup(&data));
+        assert_eq!(
+            MusicDataJson::from_encrypted(&data, "secret").unwrap(),
+            json
+        );
+
+        let error = decrypt_backup(&data, "wrong").unwrap_err().to_string();
+        assert!(error.contains("Wrong password"));
+        // flipped bits in the ciphertext or in the header
+        for index in [data.len() - TAG_LEN - 1, MAGIC.len() + 1 + 4] {
+            let mut tampered = data.clone();
+            tampered[index] ^= 1;
+            assert!(decrypt_backup(&tampered, "secret").is_err());
+        }
+        let mut tampered = data.clone();
+        tampered[HEADER_LEN] ^= 1;
+        let error = decrypt_backup(&tampered, "secret").unwrap_err().to_string();
+        assert!(error.contains("tampered"));
+        assert!(decrypt_backup(&data[..HEADER_LEN], "secret").is_err());
+        assert!(decrypt_backup(plaintext.as_bytes(), "secret").is_err());
+        assert!(encrypt_backup(b"", "").is_err());
+    }
+}
@@ -0,0 +1,551 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use sea_orm::{
+    ActiveModelTrait as _, ColumnTrait as _, Condition, ConnectionTrait, DbErr, EntityTrait,
+    FromJsonQueryResult, IntoActiveModel as _, Iterable as _, ModelTrait as _,
+    PrimaryKeyToColumn as _, QueryFilter, QueryOrder as _, Set, TransactionTrait as _,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    data::models::{
+        library_journal, music_aggregator, playlist, playlist_collection, playlist_music_junction,
+    },
+    server::{kuwo::model as kuwo_music, netease::model as netease_music},
+};
+
+use super::{database::get_db, utils::now_millis};
+
+// journal entries kept for undo, older ones are dropped
+const JOURNAL_LIMIT: i64 = 100;
+// values per `IN` query when taking snapshots
+const SNAPSHOT_CHUNK_SIZE: usize = 500;
+
+/// tables covered by the journal, in foreign key order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum JournalTable {
+    PlaylistCollection,
+    Playlist,
+    MusicAggregator,
+    // todo: add more music server
+    KuwoMusic,
+    NeteaseMusic,
+    PlaylistMusicJunction,
+}
+
+/// one changed row as json of its model, `None` if the row doesn't exist
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalChange {
+    pub table: JournalTable,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+pub struct JournalChangeVec(pub Vec<JournalChange>);
+
+type Snapshot = BTreeMap<(JournalTable, String), Value>;
+
+/// the rows an operation may change, they are compared before and after it
+#[derive(Debug, Clone, Default)]
+pub(crate) struct JournalScope {
+    all_collections: bool,
+    /// collection rows with their playlist rows
+    collections: Vec<i64>,
+    /// playlist rows with their junctions
+    playlists: Vec<i64>,
+    /// music aggregator rows with their musics and junctions
+    music_aggs: Vec<String>,
+    /// music aggregator rows with their musics, without their junctions
+    music_agg_rows: Vec<String>,
+    /// single junction rows, by playlist id and music aggregator identity
+    junctions: Vec<(i64, String)>,
+}
+
+impl JournalScope {
+    pub(crate) fn all_collections() -> Self {
+        Self {
+            all_collections: true,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn collection(id: i64) -> Self {
+        Self {
+            collections: vec![id],
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn playlist(id: i64) -> Self {
+        Self {
+            playlists: vec![id],
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn music_aggs(identities: Vec<String>) -> Self {
+        Self {
+            music_aggs: identities,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn music_agg_rows(identities: Vec<String>) -> Self {
+        Self {
+            music_agg_rows: identities,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn junctions(playlist_id: i64, identities: Vec<String>) -> Self {
+        Self {
+            junctions: identities
+                .into_iter()
+                .map(|identity| (playlist_id, identity))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn and(mut self, other: Self) -> Self {
+        self.all_collections |= other.all_collections;
+        self.collections.extend(other.collections);
+        self.playlists.extend(other.playlists);
+        self.music_aggs.extend(other.music_aggs);
+        self.music_agg_rows.extend(other.music_agg_rows);
+        self.junctions.extend(other.junctions);
+        self
+    }
+
+    async fn snapshot<C: ConnectionTrait>(&self, db: &C) -> anyhow::Result<Snapshot> {
+        let mut snapshot = Snapshot::new();
+        let mut add = |table: JournalTable, key: String, value: Value| {
+            snapshot.insert((table, key), value);
+        };
+
+        // collections
+        let mut conditions = Vec::new();
+        if self.all_collections {
+            conditions.push(Condition::all());
+        }
+        for chunk in self.collections.chunks(SNAPSHOT_CHUNK_SIZE) {
+            conditions
+                .push(Condition::all().add(playlist_collection::Column::Id.is_in(chunk.to_vec())));
+        }
+        for condition in conditions {
+            for model in playlist_collection::Entity::find()
+                .filter(condition)
+                .all(db)
+                .await?
+            {
+                add(
+                    JournalTable::PlaylistCollection,
+                    model.id.to_string(),
+                    serde_json::to_value(model)?,
+                );
+            }
+        }
+
+        // playlists
+        let mut conditions = Vec::new();
+        for chunk in self.playlists.chunks(SNAPSHOT_CHUNK_SIZE) {
+            conditions.push(Condition::all().add(playlist::Column::Id.is_in(chunk.to_vec())));
+        }
+        for chunk in self.collections.chunks(SNAPSHOT_CHUNK_SIZE) {
+            conditions
+                .push(Condition::all().add(playlist::Column::CollectionId.is_in(chunk.to_vec())));
+        }
+        for condition in conditions {
+            for model in playlist::Entity::find().filter(condition).all(db).await? {
+                add(
+                    JournalTable::Playlist,
+                    model.id.to_string(),
+                    serde_json::to_value(model)?,
+                );
+            }
+        }
+
+        // music aggregators, deleting one cascades to its musics
+        let mut kuwo_ids = Vec::new();
+        let mut netease_ids = Vec::new();
+        for chunk in self
+            .music_aggs
+            .chunks(SNAPSHOT_CHUNK_SIZE)
+            .chain(self.music_agg_rows.chunks(SNAPSHOT_CHUNK_SIZE))
+        {
+            for model in music_aggregator::Entity::find()
+                .filter(music_aggregator::Column::Identity.is_in(chunk.to_vec()))
+                .all(db)
+                .await?
+            {
+                kuwo_ids.extend(model.kuwo_music_id.clone());
+                netease_ids.extend(model.netease_music_id.clone());
+                add(
+                    JournalTable::MusicAggregator,
+                    model.identity.clone(),
+                    serde_json::to_value(model)?,
+                );
+            }
+        }
+
+        // todo: add more music server
+        for chunk in kuwo_ids.chunks(SNAPSHOT_CHUNK_SIZE) {
+            for model in kuwo_music::Entity::find()
+                .filter(kuwo_music::Column::MusicId.is_in(chunk.to_vec()))
+                .all(db)
+                .await?
+            {
+                add(
+                    JournalTable::KuwoMusic,
+                    model.music_id.clone(),
+                    serde_json::to_value(model)?,
+                );
+            }
+        }
+        for chunk in netease_ids.chunks(SNAPSHOT_CHUNK_SIZE) {
+            for model in netease_music::Entity::find()
+                .filter(netease_music::Column::MusicId.is_in(chunk.to_vec()))
+                .all(db)
+                .await?
+            {
+                add(
+                    JournalTable::NeteaseMusic,
+                    model.music_id.clone(),
+                    serde_json::to_value(model)?,
+                );
+            }
+        }
+
+        // junctions
+        let mut conditions = Vec::new();
+        for chunk in self.playlists.chunks(SNAPSHOT_CHUNK_SIZE) {
+            conditions.push(
+                Condition::all()
+                    .add(playlist_music_junction::Column::PlaylistId.is_in(chunk.to_vec())),
+            );
+        }
+        for chunk in self.music_aggs.chunks(SNAPSHOT_CHUNK_SIZE) {
+            conditions.push(
+                Condition::all()
+                    .add(playlist_music_junction::Column::MusicAggregatorId.is_in(chunk.to_vec())),
+            );
+        }
+        let mut junctions: BTreeMap<i64, Vec<String>> = BTreeMap::new();
+        for (playlist_id, identity) in &self.junctions {
+            junctions
+                .entry(*playlist_id)
+                .or_default()
+                .push(identity.clone());
+        }
+        for (playlist_id, identities) in junctions {
+            for chunk in identities.chunks(SNAPSHOT_CHUNK_SIZE) {
+                conditions.push(
+                    Condition::all()
+                        .add(playlist_music_junction::Column::PlaylistId.eq(playlist_id))
+                        .add(
+                            playlist_music_junction::Column::MusicAggregatorId
+                                .is_in(chunk.to_vec()),
+                        ),
+                );
+            }
+        }
+        for condition in conditions {
+            for model in playlist_music_junction::Entity::find()
+                .filter(condition)
+                .all(db)
+                .await?
+            {
+                add(
+                    JournalTable::PlaylistMusicJunction,
+                    junction_key(model.playlist_id, &model.music_aggregator_id),
+                    serde_json::to_value(model)?,
+                );
+            }
+        }
+
+        Ok(snapshot)
+    }
+}
+
+/// the snapshot key of a junction row
+pub(crate) fn junction_key(playlist_id: i64, identity: &str) -> String {
+    format!("{}\n{}", playlist_id, identity)
+}
+
+/// the snapshot key of a row stored as json of its model
+fn row_key(table: JournalTable, value: &Value) -> Option<String> {
+    let field = |name: &str| {
+        value.get(name).map(|value| match value {
+            Value::String(value) => value.clone(),
+            value => value.to_string(),
+        })
+    };
+    match table {
+        JournalTable::PlaylistCollection | JournalTable::Playlist => field("id"),
+        JournalTable::MusicAggregator => field("identity"),
+        // todo: add more music server
+        JournalTable::KuwoMusic | JournalTable::NeteaseMusic => field("music_id"),
+        JournalTable::PlaylistMusicJunction => Some(junction_key(
+            field("playlist_id")?.parse().ok()?,
+            &field("music_aggregator_id")?,
+        )),
+    }
+}
+
+/// drop the journal entries changing any of `rows`, for rows which are gone for good.
+/// undoing such an entry would bring them back.
+pub(crate) async fn forget_rows<C: ConnectionTrait>(
+    db: &C,
+    rows: &BTreeSet<(JournalTable, String)>,
+) -> anyhow::Result<()> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    for entry in library_journal::Entity::find().all(db).await? {
+        let touches = entry.changes.0.iter().any(|change| {
+            change
+                .before
+                .iter()
+                .chain(change.after.iter())
+                .filter_map(|value| row_key(change.table, value))
+                .any(|key| rows.contains(&(change.table, key)))
+        });
+        if touches {
+            library_journal::Entity::delete_by_id(entry.id)
+                .exec(db)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Records one library operation.
+/// Take it with `begin` before the operation and `commit` it afterwards, with the same connection.
+pub(crate) struct Journal {
+    scope: JournalScope,
+    before: Snapshot,
+}
+
+impl Journal {
+    pub(crate) async fn begin<C: ConnectionTrait>(
+        db: &C,
+        scope: JournalScope,
+    ) -> anyhow::Result<Self> {
+        let before = scope.snapshot(db).await?;
+        Ok(Self { scope, before })
+    }
+
+    /// rows of `scope` that didn't exist before the operation, like the ones it inserted
+    pub(crate) fn created(mut self, scope: JournalScope) -> Self {
+        self.scope = self.scope.and(scope);
+        self
+    }
+
+    /// write the changed rows to the journal, nothing is written if no row changed.
+    /// the undone operations can't be redone any more after this.
+    pub(crate) async fn commit<C: ConnectionTrait>(
+        self,
+        db: &C,
+        label: &str,
+    ) -> anyhow::Result<()> {
+        let mut after = self.scope.snapshot(db).await?;
+        let mut before = self.before;
+        let keys: BTreeSet<(JournalTable, String)> =
+            before.keys().chain(after.keys()).cloned().collect();
+        let changes: Vec<JournalChange> = keys
+            .into_iter()
+            .filter_map(|key| {
+                let table = key.0;
+                let (before, after) = (before.remove(&key), after.remove(&key));
+                (before != after).then_some(JournalChange {
+                    table,
+                    before,
+                    after,
+                })
+            })
+            .collect();
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        library_journal::Entity::delete_many()
+            .filter(library_journal::Column::Undone.eq(true))
+            .exec(db)
+            .await?;
+        let id = library_journal::Entity::insert(library_journal::ActiveModel {
+            id: sea_orm::ActiveValue::NotSet,
+            label: Set(label.to_string()),
+            changes: Set(JournalChangeVec(changes)),
+            created_at: Set(now_millis()),
+            undone: Set(false),
+        })
+        .exec(db)
+        .await?
+        .last_insert_id;
+        library_journal::Entity::delete_many()
+            .filter(library_journal::Column::Id.lte(id - JOURNAL_LIMIT))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+}
+
+/// write `target` of a `module` row, deleting the row described by `other` if `target` is `None`
+macro_rules! write_row {
+    ($module:ident, $db:expr, $target:expr, $other:expr) => {
+        match $target {
+            Some(value) => {
+                let model: $module::Model = serde_json::from_value(value.clone())?;
+                let active = model.into_active_model().reset_all();
+                match $module::Entity::update(active.clone()).exec($db).await {
+                    Ok(_) => {}
+                    Err(DbErr::RecordNotUpdated) => {
+                        $module::Entity::insert(active)
+                            .on_conflict_do_nothing()
+                            .exec_without_returning($db)
+                            .await?;
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            None => {
+                let model: $module::Model = serde_json::from_value($other.clone())?;
+                model.delete($db).await?;
+            }
+        }
+    };
+}
+
+/// the row stored in `value` as it is in the database now, as json of its model.
+/// `value` is read back through the model as well, so both compare equal if the row is unchanged
+async fn current_row<E, C>(db: &C, value: &Value) -> anyhow::Result<(Value, Option<Value>)>
+where
+    E: EntityTrait,
+    E::Model: Serialize + DeserializeOwned,
+    C: ConnectionTrait,
+{
+    let model: E::Model = serde_json::from_value(value.clone())?;
+    let mut condition = Condition::all();
+    for key in E::PrimaryKey::iter() {
+        let column = key.into_column();
+        condition = condition.add(column.eq(model.get(column)));
+    }
+    let current = match E::find().filter(condition).one(db).await? {
+        Some(current) => Some(serde_json::to_value(current)?),
+        None => None,
+    };
+    Ok((serde_json::to_value(model)?, current))
+}
+
+/// whether the row of `change` is still as the operation (`undo`) or its undo left it
+async fn is_unchanged<C: ConnectionTrait>(
+    db: &C,
+    change: &JournalChange,
+    undo: bool,
+) -> anyhow::Result<bool> {
+    let (expected, other) = if undo {
+        (change.after.as_ref(), change.before.as_ref())
+    } else {
+        (change.before.as_ref(), change.after.as_ref())
+    };
+    let Some(value) = expected.or(other) else {
+        return Ok(true);
+    };
+    let (value, current) = match change.table {
+        JournalTable::PlaylistCollection => {
+            current_row::<playlist_collection::Entity, _>(db, value).await?
+        }
+        JournalTable::Playlist => current_row::<playlist::Entity, _>(db, value).await?,
+        JournalTable::MusicAggregator => {
+            current_row::<music_aggregator::Entity, _>(db, value).await?
+        }
+        JournalTable::KuwoMusic => current_row::<kuwo_music::Entity, _>(db, value).await?,
+        JournalTable::NeteaseMusic => current_row::<netease_music::Entity, _>(db, value).await?,
+        JournalTable::PlaylistMusicJunction => {
+            current_row::<playlist_music_junction::Entity, _>(db, value).await?
+        }
+    };
+    Ok(match expected {
+        Some(_) => current == Some(value),
+        None => current.is_none(),
+    })
+}
+
+/// bring the rows of `changes` to their state before (`undo`) or after the operation.
+/// refuses if a row was changed since, by something which isn't in the journal
+async fn apply_changes<C: ConnectionTrait>(
+    db: &C,
+    changes: &[JournalChange],
+    undo: bool,
+) -> anyhow::Result<()> {
+    for change in changes {
+        if !is_unchanged(db, change, undo).await? {
+            return Err(anyhow::anyhow!(
+                "The library was changed since, can't {} this operation.",
+                if undo { "undo" } else { "redo" }
+            ));
+        }
+    }
+    let mut changes: Vec<(&JournalChange, Option<&Value>, &Value)> = changes
+        .iter()
+        .filter_map(|change| {
+            let (target, other) = if undo {
+                (change.before.as_ref(), change.after.as_ref())
+            } else {
+                (change.after.as_ref(), change.before.as_ref())
+            };
+            Some((change, target, other.or(target)?))
+        })
+        .collect();
+    // rows are deleted children first, then written parents first.
+    // a music row must be gone before its music aggregator stops pointing at it
+    changes.sort_by_key(|(change, target, _)| match target {
+        None => (0, -(change.table as i64)),
+        Some(_) => (1, change.table as i64),
+    });
+
+    for (change, target, other) in changes {
+        match change.table {
+            JournalTable::PlaylistCollection => write_row!(playlist_collection, db, target, other),
+            JournalTable::Playlist => write_row!(playlist, db, target, other),
+            JournalTable::MusicAggregator => write_row!(music_aggregator, db, target, other),
+            JournalTable::KuwoMusic => write_row!(kuwo_music, db, target, other),
+            JournalTable::NeteaseMusic => write_row!(netease_music, db, target, other),
+            JournalTable::PlaylistMusicJunction => {
+                write_row!(playlist_music_junction, db, target, other)
+            }
+        }
+    }
+    Ok(())
+}
+
+/// undo the latest operation (`undo`) or redo the latest undone one, returns its label
+pub(crate) async fn step(undo: bool) -> anyhow::Result<Option<String>> {
+    let db = get_db()
+        .await
+        .ok_or(anyhow::anyhow!("Database is not inited."))?;
+
+    let txn = db.begin().await?;
+    let select = library_journal::Entity::find().filter(library_journal::Column::Undone.eq(!undo));
+    // the latest undone operation is the oldest undone entry
+    let entry = if undo {
+        select.order_by_desc(library_journal::Column::Id)
+    } else {
+        select.order_by_asc(library_journal::Column::Id)
+    }
+    .one(&txn)
+    .await?;
+    let Some(entry) = entry else {
+        return Ok(None);
+    };
+
+    apply_changes(&txn, &entry.changes.0, undo).await?;
+    let label = entry.label.clone();
+    let mut active = entry.into_active_model();
+    active.undone = Set(undo);
+    active.update(&txn).await?;
+    txn.commit().await?;
+    Ok(Some(label))
+}
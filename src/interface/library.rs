@@ -0,0 +1,495 @@
+use std::collections::BTreeMap;
+
+use sea_orm::{
+    sea_query::{Alias, Expr, Func, Query},
+    ColumnTrait as _, Condition, ConnectionTrait as _, DatabaseBackend, EntityTrait, JoinType,
+    QueryFilter, QuerySelect as _, RelationTrait as _,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::data::models::{music_aggregator, playlist_music_junction};
+
+use super::{
+    database::get_db,
+    journal,
+    music_aggregator::{Music, MusicAggregator},
+    quality::QualityTier,
+    server::MusicServer,
+    smart_playlist::contains_pattern,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LibrarySearchFilters {
+    /// servers to search in, all servers if empty
+    pub servers: Vec<MusicServer>,
+    /// only search the musics of this playlist
+    pub playlist_id: Option<i64>,
+    pub min_duration: Option<i64>,
+    pub max_duration: Option<i64>,
+    /// at least one music must have a quality of this tier or better
+    pub min_quality: Option<QualityTier>,
+}
+
+/// The saved musics, across all playlists
+pub struct Library;
+
+// todo: add more music server
+pub(crate) fn server_table(server: &MusicServer) -> (&'static str, music_aggregator::Relation) {
+    match server {
+        MusicServer::Kuwo => ("kuwo_music", music_aggregator::Relation::KuwoMusic),
+        MusicServer::Netease => ("netease_music", music_aggregator::Relation::NeteaseMusicId),
+    }
+}
+
+fn music_matches(music: &Music, query: &str) -> bool {
+    music.name.to_lowercase().contains(query)
+        || music
+            .album
+            .as_ref()
+            .is_some_and(|album| album.to_lowercase().contains(query))
+        || music
+            .artists
+            .iter()
+            .any(|artist| artist.name.to_lowercase().contains(query))
+}
+
+impl Library {
+    /// Search the saved music aggregators by music name, artist or album.
+    /// An empty query lists every music aggregator passing the filters.
+    pub async fn search(
+        query: &str,
+        filters: LibrarySearchFilters,
+    ) -> anyhow::Result<Vec<MusicAggregator>> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+        let query = query.trim().to_lowercase();
+        let servers = if filters.servers.is_empty() {
+            MusicServer::all()
+        } else {
+            filters.servers.clone()
+        };
+
+        let mut models = BTreeMap::new();
+        for server in &servers {
+            let (table, relation) = server_table(server);
+            let column = |name: &str| Expr::col((Alias::new(table), Alias::new(name)));
+
+            let mut select =
+                music_aggregator::Entity::find().join(JoinType::InnerJoin, relation.def());
+            if let Some(playlist_id) = filters.playlist_id {
+                select = select
+                    .join(
+                        JoinType::InnerJoin,
+                        music_aggregator::Relation::PlaylistMusicJunction.def(),
+                    )
+                    .filter(playlist_music_junction::Column::PlaylistId.eq(playlist_id))
+                    .filter(playlist_music_junction::Column::DeletedAt.is_null());
+            }
+            if let Some(min) = filters.min_duration {
+                select = select.filter(column("duration").gte(min));
+            }
+            if let Some(max) = filters.max_duration {
+                select = select.filter(column("duration").lte(max));
+            }
+            if !query.is_empty() {
+                let condition: Condition = match db.get_database_backend() {
+                    // served by the fts5 trigram index, see `create_music_search_index`
+                    DatabaseBackend::Sqlite => Condition::all().add(
+                        column("music_id").in_subquery(
+                            Query::select()
+                                .column(Alias::new("music_id"))
+                                .from(Alias::new(format!("{}_fts", table)))
+                                .cond_where(
+                                    Condition::any()
+                                        .add(
+                                            Expr::col(Alias::new("name"))
+                                                .like(contains_pattern(&query)),
+                                        )
+                                        .add(
+                                            Expr::col(Alias::new("artists"))
+                                                .like(contains_pattern(&query)),
+                                        )
+                                        .add(
+                                            Expr::col(Alias::new("album"))
+                                                .like(contains_pattern(&query)),
+                                        ),
+                                )
+                                .to_owned(),
+                        ),
+                    ),
+                    backend => {
+                        let text_type = match backend {
+                            DatabaseBackend::MySql => "CHAR",
+                            _ => "TEXT",
+                        };
+                        Condition::any()
+                            .add(
+                                Expr::expr(Func::lower(column("name")))
+                                    .like(contains_pattern(&query)),
+                            )
+                            .add(
+                                Expr::expr(Func::lower(column("album")))
+                                    .like(contains_pattern(&query)),
+                            )
+                            .add(
+                                Expr::expr(Func::lower(
+                                    column("artists").cast_as(Alias::new(text_type)),
+                                ))
+                                .like(contains_pattern(&query)),
+                            )
+                    }
+                };
+                select = select.filter(condition);
+            }
+
+            for model in select.all(&db).await? {
+                models.entry(model.identity.clone()).or_insert(model);
+            }
+        }
+
+        let models = models
+            .into_values()
+            .enumerate()
+            .map(|(order, model)| (model, order as i64))
+            .collect();
+        let mut aggs = music_aggregator::Model::get_music_aggregators(&db, models).await?;
+        // the sql only narrows the candidates, the rules are checked again on the loaded musics
+        aggs.retain(|agg| {
+            let musics: Vec<&Music> = agg
+                .musics
+                .iter()
+                .filter(|music| servers.contains(&music.server))
+                .collect();
+            !musics.is_empty()
+                && (query.is_empty() || musics.iter().any(|music| music_matches(music, &query)))
+                && filters.min_quality.is_none_or(|tier| {
+                    musics
+                        .iter()
+                        .any(|music| music.qualities.iter().any(|q| q.tier() >= tier))
+                })
+        });
+        Ok(aggs)
+    }
+
+    /// undo the latest change to playlists, collections and their music, returns its label.
+    /// `None` if there is nothing to undo.
+    pub async fn undo() -> anyhow::Result<Option<String>> {
+        journal::step(true).await
+    }
+
+    /// redo the latest undone change, returns its label.
+    /// `None` if there is nothing to redo, any new change drops the undone ones.
+    pub async fn redo() -> anyhow::Result<Option<String>> {
+        journal::step(false).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serial_test::serial;
+
+    use crate::interface::{
+        database::set_db, music_aggregator, playlist::Playlist,
+        playlist_collection::PlaylistCollection, quality::Quality,
+    };
+
+    use super::*;
+
+    fn music_agg(
+        server: MusicServer,
+        id: &str,
+        name: &str,
+        artist: &str,
+        duration: i64,
+        bitrate: &str,
+    ) -> MusicAggregator {
+        let mut agg = music_aggregator::music_agg_by(server, id, name, artist);
+        agg.musics[0].duration = Some(duration);
+        agg.musics[0].qualities = vec![Quality {
+            summary: bitrate.to_string(),
+            bitrate: Some(bitrate.to_string()),
+            format: None,
+            size: None,
+        }];
+        agg
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_search() {
+        set_db("sqlite::memory:").await.unwrap();
+        let collection_id = PlaylistCollection::new("test".to_string())
+            .insert_to_db()
+            .await
+            .unwrap();
+        let playlist_id = Playlist::new("test".to_string(), None, None, vec![])
+            .insert_to_db(collection_id)
+            .await
+            .unwrap();
+        let other_id = Playlist::new("other".to_string(), None, None, vec![])
+            .insert_to_db(collection_id)
+            .await
+            .unwrap();
+        Playlist::find_in_db(playlist_id)
+            .await
+            .unwrap()
+            .add_aggs_to_db(&vec![
+                music_agg(MusicServer::Kuwo, "k1", "Lemon", "米津玄師", 255, "2000"),
+                music_agg(MusicServer::Netease, "n1", "晴天", "周杰伦", 269, "128"),
+            ])
+            .await
+            .unwrap();
+        Playlist::find_in_db(other_id)
+            .await
+            .unwrap()
+            .add_aggs_to_db(&vec![
+                music_agg(MusicServer::Kuwo, "k2", "LEMONADE", "someone", 180, "320"),
+                music_agg(MusicServer::Kuwo, "k3", "100% Love", "someone", 180, "128"),
+            ])
+            .await
+            .unwrap();
+
+        let names =
+            |aggs: Vec<MusicAggregator>| aggs.into_iter().map(|agg| agg.name).collect::<Vec<_>>();
+        let search = |query: &'static str, filters: LibrarySearchFilters| async move {
+            names(Library::search(query, filters).await.unwrap())
+        };
+
+        assert_eq!(
+            search("lemon", Default::default()).await,
+            vec!["lemon", "lemonade"]
+        );
+        assert_eq!(search("周杰", Default::default()).await, vec!["晴天"]);
+        assert_eq!(search("", Default::default()).await.len(), 4);
+        // like wildcards in the query are plain text
+        assert_eq!(search("0%", Default::default()).await, vec!["100% love"]);
+        assert!(search("_", Default::default()).await.is_empty());
+        assert_eq!(
+            search(
+                "lemon",
+                LibrarySearchFilters {
+                    playlist_id: Some(playlist_id),
+                    ..Default::default()
+                }
+            )
+            .await,
+            vec!["lemon"]
+        );
+        assert_eq!(
+            search(
+                "",
+                LibrarySearchFilters {
+                    servers: vec![MusicServer::Netease],
+                    ..Default::default()
+                }
+            )
+            .await,
+            vec!["晴天"]
+        );
+        assert_eq!(
+            search(
+                "",
+                LibrarySearchFilters {
+                    min_duration: Some(200),
+                    max_duration: Some(260),
+                    ..Default::default()
+                }
+            )
+            .await,
+            vec!["lemon"]
+        );
+        assert_eq!(
+            search(
+                "",
+                LibrarySearchFilters {
+                    min_quality: Some(QualityTier::High),
+                    ..Default::default()
+                }
+            )
+            .await,
+            vec!["lemon", "lemonade"]
+        );
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_undo_redo() {
+        set_db("sqlite::memory:").await.unwrap();
+        assert_eq!(Library::undo().await.unwrap(), None);
+
+        let collection_id = PlaylistCollection::new("collection".to_string())
+            .insert_to_db()
+            .await
+            .unwrap();
+        let playlist_id = Playlist::new("playlist".to_string(), None, None, vec![])
+            .insert_to_db(collection_id)
+            .await
+            .unwrap();
+        let playlist = Playlist::find_in_db(playlist_id).await.unwrap();
+        let a = music_agg(MusicServer::Kuwo, "1", "a", "artist", 200, "320");
+        let b = music_agg(MusicServer::Kuwo, "2", "b", "artist", 200, "320");
+        playlist
+            .add_aggs_to_db(&vec![a.clone(), b.clone()])
+            .await
+            .unwrap();
+        let identities =
+            |aggs: Vec<MusicAggregator>| aggs.iter().map(|agg| agg.identity()).collect::<Vec<_>>();
+        let added = identities(playlist.get_musics_from_db().await.unwrap());
+
+        // reorder, then undo it
+        playlist
+            .reorder(&[b.identity(), a.identity()])
+            .await
+            .unwrap();
+        assert_eq!(
+            Library::undo().await.unwrap(),
+            Some("Reorder playlist".to_string())
+        );
+        assert_eq!(
+            identities(playlist.get_musics_from_db().await.unwrap()),
+            added
+        );
+        Library::redo().await.unwrap();
+        assert_eq!(
+            identities(playlist.get_musics_from_db().await.unwrap()),
+            vec![b.identity(), a.identity()]
+        );
+        assert_eq!(Library::redo().await.unwrap(), None);
+
+        // delete the playlist, undo brings it back
+        playlist.clone().del_from_db().await.unwrap();
+        assert!(Playlist::find_in_db(playlist_id).await.is_none());
+        Library::undo().await.unwrap();
+        assert!(Playlist::find_in_db(playlist_id).await.is_some());
+
+        // undo down to the empty library, redo rebuilds it
+        Library::undo().await.unwrap();
+        assert_eq!(
+            Library::undo().await.unwrap(),
+            Some("Add music to playlist".to_string())
+        );
+        assert_eq!(playlist.count_musics_in_db().await.unwrap(), 0);
+        Library::undo().await.unwrap();
+        Library::undo().await.unwrap();
+        assert!(PlaylistCollection::get_form_db().await.unwrap().is_empty());
+        assert_eq!(Library::undo().await.unwrap(), None);
+        for _ in 0..3 {
+            Library::redo().await.unwrap();
+        }
+        assert_eq!(
+            identities(playlist.get_musics_from_db().await.unwrap()),
+            added
+        );
+
+        // a new change drops the undone ones
+        Library::undo().await.unwrap();
+        PlaylistCollection::new("other".to_string())
+            .insert_to_db()
+            .await
+            .unwrap();
+        assert_eq!(Library::redo().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_undo_changed_since() {
+        set_db("sqlite::memory:").await.unwrap();
+        let collection_id = PlaylistCollection::new("collection".to_string())
+            .insert_to_db()
+            .await
+            .unwrap();
+        let playlist_id = Playlist::new("playlist".to_string(), None, None, vec![])
+            .insert_to_db(collection_id)
+            .await
+            .unwrap();
+        let playlist = Playlist::find_in_db(playlist_id).await.unwrap();
+        let a = music_agg(MusicServer::Kuwo, "1", "a", "artist", 200, "320");
+        let b = music_agg(MusicServer::Kuwo, "2", "b", "artist", 200, "320");
+        playlist
+            .add_aggs_to_db(&vec![a.clone(), b.clone()])
+            .await
+            .unwrap();
+        playlist
+            .reorder(&[b.identity(), a.identity()])
+            .await
+            .unwrap();
+
+        // a change which isn't in the journal, undo would overwrite it
+        let db = get_db().await.unwrap();
+        playlist_music_junction::Entity::update_many()
+            .col_expr(playlist_music_junction::Column::Order, Expr::value(10))
+            .exec(&db)
+            .await
+            .unwrap();
+        assert!(Library::undo().await.is_err());
+        let orders = |aggs: Vec<MusicAggregator>| {
+            aggs.into_iter()
+                .map(|agg| agg.order.unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            orders(playlist.get_musics_from_db().await.unwrap()),
+            vec![10, 10]
+        );
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_undo_reused_music_agg() {
+        set_db("sqlite::memory:").await.unwrap();
+        let collection_id = PlaylistCollection::new("collection".to_string())
+            .insert_to_db()
+            .await
+            .unwrap();
+        let mut playlists = Vec::new();
+        for name in ["a", "b"] {
+            let id = Playlist::new(name.to_string(), None, None, vec![])
+                .insert_to_db(collection_id)
+                .await
+                .unwrap();
+            playlists.push(Playlist::find_in_db(id).await.unwrap());
+        }
+        let song = music_agg(MusicServer::Kuwo, "k1", "song", "artist", 200, "320");
+        playlists[0]
+            .add_aggs_to_db(&vec![song.clone()])
+            .await
+            .unwrap();
+
+        // the alias shares the kuwo id, so the saved song is reused,
+        // then the song itself brings a netease music
+        let alias = music_agg(MusicServer::Kuwo, "k1", "alias", "artist", 200, "320");
+        let mut both = song.clone();
+        both.musics.push(
+            music_agg(MusicServer::Netease, "n1", "song", "artist", 200, "320").musics[0].clone(),
+        );
+        let result = playlists[1]
+            .add_aggs_to_db(&vec![alias, both])
+            .await
+            .unwrap();
+        assert_eq!(result.reused_music_aggs, 1);
+        assert_eq!(result.updated_music_aggs, 1);
+        let saved = playlists[1].get_musics_from_db().await.unwrap();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].musics.len(), 2);
+
+        assert_eq!(
+            Library::undo().await.unwrap(),
+            Some("Add music to playlist".to_string())
+        );
+        assert!(playlists[1].get_musics_from_db().await.unwrap().is_empty());
+        let saved = playlists[0].get_musics_from_db().await.unwrap();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].identity(), song.identity());
+        assert_eq!(saved[0].musics.len(), 1);
+        assert_eq!(saved[0].musics[0].server, MusicServer::Kuwo);
+
+        Library::redo().await.unwrap();
+        assert_eq!(
+            playlists[1].get_musics_from_db().await.unwrap()[0]
+                .musics
+                .len(),
+            2
+        );
+    }
+}
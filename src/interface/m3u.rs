@@ -0,0 +1,184 @@
+use anyhow::Result;
+
+use super::{
+    music_aggregator::MusicAggregator,
+    playlist::Playlist,
+    results::TrackImportResult,
+    tracklist::{music_uri, parse_music_uri, resolve_tracks, split_artists, TrackEntry},
+};
+
+impl Playlist {
+    /// export as extended M3U, db playlists use their saved music and online ones are fetched.
+    /// every entry is located by the `music_uri` of its default server.
+    pub async fn to_m3u(&self) -> Result<String> {
+        let music_aggs = match self.from_db {
+            true => self.get_musics_from_db().await?,
+            false => self.fetch_musics_online(1, 2333).await?,
+        };
+        Ok(write_m3u(&self.name, &music_aggs))
+    }
+
+    /// read M3U or M3U8 content and match its entries to music aggregators,
+    /// see `resolve_tracks`. they can be saved with `add_aggs_to_db`.
+    pub async fn resolve_m3u(content: &str) -> Result<TrackImportResult> {
+        resolve_tracks(parse_m3u(content)).await
+    }
+}
+
+/// `#EXTINF:duration,artist - title`, followed by `#EXTALB`, `#EXTIMG` and the location
+pub fn write_m3u(name: &str, music_aggs: &[MusicAggregator]) -> String {
+    let mut m3u = String::from("#EXTM3U\n");
+    m3u.push_str(&format!("#PLAYLIST:{}\n", one_line(name)));
+    for agg in music_aggs {
+        let Some(music) = agg
+            .musics
+            .iter()
+            .find(|music| music.server == agg.default_server)
+            .or(agg.musics.first())
+        else {
+            continue;
+        };
+        m3u.push_str(&format!(
+            "#EXTINF:{},{} - {}\n",
+            music.duration.unwrap_or(-1),
+            one_line(&agg.artist),
+            one_line(&agg.name)
+        ));
+        if let Some(album) = &music.album {
+            m3u.push_str(&format!("#EXTALB:{}\n", one_line(album)));
+        }
+        if let Some(cover) = &music.cover {
+            m3u.push_str(&format!("#EXTIMG:{}\n", one_line(cover)));
+        }
+        m3u.push_str(&music_uri(&music.server, &music.identity));
+        m3u.push('\n');
+    }
+    m3u
+}
+
+/// entries of M3U or M3U8 content, plain M3U files name their tracks by file name only
+pub fn parse_m3u(content: &str) -> Vec<TrackEntry> {
+    let mut tracks = Vec::new();
+    let mut track = TrackEntry::default();
+    for line in content
+        .trim_start_matches('\u{feff}')
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            // `duration [attributes],artist - title`
+            let (head, name) = info.split_once(',').unwrap_or((info, ""));
+            track.duration = head
+                .split_whitespace()
+                .next()
+                .and_then(|duration| duration.parse::<f64>().ok())
+                .filter(|duration| *duration >= 0.0)
+                .map(|duration| duration.round() as i64);
+            (track.artists, track.title) = split_name(name);
+        } else if let Some(album) = line.strip_prefix("#EXTALB:") {
+            track.album = Some(album.trim().to_string());
+        } else if let Some(cover) = line.strip_prefix("#EXTIMG:") {
+            track.cover = Some(cover.trim().to_string());
+        } else if !line.starts_with('#') {
+            // the location ends an entry
+            let mut entry = std::mem::take(&mut track);
+            match parse_music_uri(line) {
+                Some(music_id) => entry.music_ids.push(music_id),
+                None if entry.title.is_empty() => {
+                    let file = line.rsplit(['/', '\\']).next().unwrap_or(line);
+                    let stem = file.rsplit_once('.').map_or(file, |(stem, _)| stem);
+                    (entry.artists, entry.title) = split_name(stem);
+                }
+                None => {}
+            }
+            tracks.push(entry);
+        }
+    }
+    tracks
+}
+
+/// `artist - title`, or just a title
+fn split_name(name: &str) -> (Vec<String>, String) {
+    match name.split_once(" - ") {
+        Some((artists, title)) => (split_artists(artists), title.trim().to_string()),
+        None => (Vec::new(), name.trim().to_string()),
+    }
+}
+
+fn one_line(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod test {
+    use serial_test::serial;
+
+    use super::*;
+    use crate::interface::{
+        music_aggregator::{empty_playlist, music_agg},
+        server::MusicServer,
+    };
+
+    #[test]
+    fn test_parse_m3u() {
+        let m3u = write_m3u("p", &[music_agg(MusicServer::Netease, "7", "title")]);
+        assert_eq!(
+            m3u,
+            "#EXTM3U\n#PLAYLIST:p\n#EXTINF:215,artist - title\n#EXTALB:album\n#EXTIMG:cover\nnetease:music:7\n"
+        );
+        assert_eq!(
+            parse_m3u(&m3u),
+            vec![TrackEntry {
+                title: "title".to_string(),
+                artists: vec!["artist".to_string()],
+                album: Some("album".to_string()),
+                duration: Some(215),
+                cover: Some("cover".to_string()),
+                music_ids: vec![(MusicServer::Netease, "7".to_string())],
+            }]
+        );
+
+        // files of desktop players
+        let tracks = parse_m3u(
+            "\u{feff}#EXTM3U\r\n#EXTINF:-1 tvg-id=\"x\",A & B - Song\r\nC:\\Music\\song.mp3\r\n/music/周杰伦 - 晴天.flac\r\n",
+        );
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[0].duration, None);
+        assert_eq!(tracks[0].artists, vec!["A", "B"]);
+        assert_eq!(tracks[0].title, "Song");
+        assert_eq!(tracks[1].artists, vec!["周杰伦"]);
+        assert_eq!(tracks[1].title, "晴天");
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_resolve_m3u() {
+        let (_, playlist) = empty_playlist().await;
+        playlist
+            .add_aggs_to_db(&vec![
+                music_agg(MusicServer::Kuwo, "1", "a"),
+                music_agg(MusicServer::Netease, "2", "b"),
+            ])
+            .await
+            .unwrap();
+        let m3u = playlist.to_m3u().await.unwrap();
+
+        // saved music is found by music id, or by name and artists
+        let m3u = m3u.replace("#EXTINF:215,artist - b", "#EXTINF:215,Artist - B");
+        let result = Playlist::resolve_m3u(&m3u).await.unwrap();
+        assert!(result.unresolved.is_empty());
+        assert_eq!(
+            result
+                .music_aggs
+                .iter()
+                .map(|agg| agg.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+        let result = Playlist::resolve_m3u(&m3u.replace("netease:music:2", "b.mp3"))
+            .await
+            .unwrap();
+        assert_eq!(result.music_aggs.len(), 2);
+    }
+}
@@ -1,13 +1,27 @@
+pub mod album_aggregator;
 pub mod artist;
+pub mod artist_aggregator;
 pub mod music_chart;
 pub mod database;
+pub mod journal;
 pub mod json;
+pub mod library;
 pub mod music_aggregator;
+pub mod play_history;
 pub mod playlist;
 pub mod playlist_subscription;
 pub mod quality;
 pub mod results;
 pub mod server;
+pub mod smart_playlist;
+pub mod song_group;
+pub mod trash;
 pub mod playlist_tag;
 pub mod utils;
-pub mod playlist_collection;
\ No newline at end of file
+pub mod playlist_collection;
+pub mod m3u;
+pub mod tracklist;
+pub mod xspf;
+pub mod csv;
+pub mod ndjson;
+pub mod encrypted;
@@ -1,19 +1,26 @@
 use std::collections::HashSet;
 
 use sea_orm::{
-    prelude::Expr, ColumnTrait, Condition, EntityTrait, IntoActiveModel as _, ModelTrait,
-    QueryFilter, Set,
+    prelude::Expr,
+    sea_query::{Query, SimpleExpr},
+    ColumnTrait, Condition, ConnectionTrait, EntityTrait, IntoActiveModel as _, ModelTrait,
+    QueryFilter, Set, TransactionTrait as _,
 };
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    data::models::{music_aggregator, playlist_music_junction},
+    data::models::{music_aggregator, play_history, playlist, playlist_music_junction},
     server::{kuwo, netease},
 };
 
 use super::{
-    artist::Artist, database::get_db, quality::Quality, server::MusicServer,
-    utils::find_duplicate_music_agg,
+    artist::Artist,
+    database::get_db,
+    journal::{Journal, JournalScope},
+    playlist::{Playlist, SystemPlaylistType},
+    quality::Quality,
+    server::MusicServer,
+    utils::{find_duplicate_music_agg, now_millis},
 };
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -73,7 +80,11 @@ impl Music {
         let db = get_db()
             .await
             .ok_or(anyhow::anyhow!("Database is not inited"))?;
+        self.insert(&db).await
+    }
 
+    /// `insert_to_db` with the given connection, like the transaction of the music aggregator
+    pub(crate) async fn insert<C: ConnectionTrait>(&self, db: &C) -> anyhow::Result<()> {
         match self.server {
             MusicServer::Kuwo => {
                 let clone = self.clone();
@@ -81,7 +92,7 @@ impl Music {
                 let active = model.into_active_model();
                 kuwo::model::Entity::insert(active)
                     .on_conflict_do_nothing()
-                    .exec(&db)
+                    .exec(db)
                     .await?;
                 return Ok(());
             }
@@ -91,7 +102,7 @@ impl Music {
                 let active = model.into_active_model();
                 netease::model::Entity::insert(active)
                     .on_conflict_do_nothing()
-                    .exec(&db)
+                    .exec(db)
                     .await?;
                 return Ok(());
             }
@@ -107,6 +118,14 @@ pub struct MusicAggregator {
     pub order: Option<i64>,
     pub musics: Vec<Music>,
     pub default_server: MusicServer,
+    /// unix time in milliseconds, only db music aggregators have them
+    #[serde(default)]
+    pub created_at: Option<i64>,
+    #[serde(default)]
+    pub updated_at: Option<i64>,
+    /// when the music aggregator was added to the playlist it was loaded from
+    #[serde(default)]
+    pub added_at: Option<i64>,
 }
 
 impl MusicAggregator {
@@ -130,6 +149,9 @@ impl MusicAggregator {
             default_server: music.server.clone(),
             musics: vec![music],
             order: None,
+            created_at: None,
+            updated_at: None,
+            added_at: None,
         }
     }
 
@@ -146,8 +168,10 @@ impl MusicAggregator {
         let db = get_db()
             .await
             .ok_or(anyhow::anyhow!("Database is not inited"))?;
+        let txn = db.begin().await?;
+        let journal = Journal::begin(&txn, JournalScope::music_aggs(vec![self.identity()])).await?;
         let agg = music_aggregator::Entity::find_by_id(self.identity())
-            .one(&db)
+            .one(&txn)
             .await?
             .ok_or(anyhow::anyhow!("Music aggregator not found in db"))?;
         match server {
@@ -165,7 +189,10 @@ impl MusicAggregator {
 
         let mut active = agg.into_active_model();
         active.default_server = Set(server);
-        music_aggregator::Entity::update(active).exec(&db).await?;
+        active.updated_at = Set(now_millis());
+        music_aggregator::Entity::update(active).exec(&txn).await?;
+        journal.commit(&txn, "Change default server").await?;
+        txn.commit().await?;
         Ok(())
     }
 
@@ -188,9 +215,11 @@ impl MusicAggregator {
             .find(|x| x.server == MusicServer::Netease)
             .and_then(|x| Some(x.identity.clone()));
         let mut duplicate_identity = None::<String>;
+        let txn = db.begin().await?;
+        let journal = Journal::begin(&txn, JournalScope::music_aggs(vec![self.identity()])).await?;
 
         if let Some(agg) = music_aggregator::Entity::find_by_id(self.identity())
-            .one(&db)
+            .one(&txn)
             .await?
         {
             let should_update_kuwo = kuwo_id.is_some() && agg.kuwo_music_id.is_none();
@@ -202,7 +231,10 @@ impl MusicAggregator {
             if should_update_netease {
                 active.netease_music_id = Set(netease_id);
             }
-            music_aggregator::Entity::update(active).exec(&db).await?;
+            if should_update_kuwo || should_update_netease {
+                active.updated_at = Set(now_millis());
+            }
+            music_aggregator::Entity::update(active).exec(&txn).await?;
         } else {
             let agg = music_aggregator::ActiveModel {
                 identity: Set(self.identity()),
@@ -214,13 +246,21 @@ impl MusicAggregator {
                     .ok_or(anyhow::anyhow!("No music"))?
                     .server
                     .clone()),
+                created_at: Set(now_millis()),
+                updated_at: Set(now_millis()),
             };
 
-            if let Err(e) = music_aggregator::Entity::insert(agg)
+            // in a savepoint, a failed statement would abort the whole transaction on postgres
+            let savepoint = txn.begin().await?;
+            let inserted = music_aggregator::Entity::insert(agg)
                 .on_conflict_do_nothing()
-                .exec_without_returning(&db)
-                .await
-            {
+                .exec_without_returning(&savepoint)
+                .await;
+            match inserted {
+                Ok(_) => savepoint.commit().await?,
+                Err(_) => savepoint.rollback().await?,
+            }
+            if let Err(e) = inserted {
                 if let sea_orm::DbErr::Exec(sea_orm::RuntimeErr::SqlxError(
                     sea_orm::SqlxError::Database(ref database_error),
                 )) = &e
@@ -233,7 +273,7 @@ impl MusicAggregator {
                         // PgSql Unique error
                         || database_error_str.contains("duplicate")
                     {
-                        duplicate_identity = find_duplicate_music_agg(&db, self).await;
+                        duplicate_identity = find_duplicate_music_agg(&txn, self).await;
                     } else {
                         return Err(anyhow::anyhow!(e));
                     }
@@ -244,8 +284,10 @@ impl MusicAggregator {
         }
 
         for music in &self.musics {
-            let _ = music.insert_to_db().await;
+            let _ = music.insert(&txn).await;
         }
+        journal.commit(&txn, "Save music").await?;
+        txn.commit().await?;
 
         Ok(duplicate_identity)
     }
@@ -254,6 +296,12 @@ impl MusicAggregator {
         let db = get_db()
             .await
             .ok_or(anyhow::anyhow!("Database is not inited"))?;
+        let txn = db.begin().await?;
+        let journal = Journal::begin(
+            &txn,
+            JournalScope::junctions(playlist_id, vec![self.identity()]),
+        )
+        .await?;
         let junction = playlist_music_junction::Entity::find()
             .filter(
                 Condition::all()
@@ -263,18 +311,45 @@ impl MusicAggregator {
                     )
                     .add(Expr::col(playlist_music_junction::Column::PlaylistId).eq(playlist_id)),
             )
-            .one(&db)
+            .one(&txn)
             .await?
             .ok_or(anyhow::anyhow!("Music aggregator not found in db"))?;
 
         let mut active = junction.into_active_model();
         active.order = Set(self.order.ok_or(anyhow::anyhow!("No order"))?);
+        active.updated_at = Set(now_millis());
         playlist_music_junction::Entity::update(active)
-            .exec(&db)
+            .exec(&txn)
             .await?;
+        journal.commit(&txn, "Change music order").await?;
+        txn.commit().await?;
         Ok(())
     }
 
+    /// record one play of this music aggregator, `played_duration` is in seconds
+    pub async fn record_play(
+        &self,
+        server: MusicServer,
+        played_duration: i64,
+        completed: bool,
+    ) -> Result<i64, anyhow::Error> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited"))?;
+        let active = play_history::ActiveModel {
+            id: sea_orm::ActiveValue::NotSet,
+            music_aggregator_id: Set(self.identity()),
+            server: Set(server),
+            played_at: Set(now_millis()),
+            played_duration: Set(played_duration),
+            completed: Set(completed),
+        };
+        Ok(play_history::Entity::insert(active)
+            .exec(&db)
+            .await?
+            .last_insert_id)
+    }
+
     pub async fn del_from_db(&self) -> Result<(), anyhow::Error> {
         if !self.from_db {
             return Err(anyhow::anyhow!("Can't del non-database music aggregator"));
@@ -283,18 +358,78 @@ impl MusicAggregator {
         let db = get_db()
             .await
             .ok_or(anyhow::anyhow!("Database is not inited"))?;
+        let txn = db.begin().await?;
+        let journal = Journal::begin(&txn, JournalScope::music_aggs(vec![self.identity()])).await?;
         music_aggregator::Entity::delete_by_id(&self.identity())
-            .exec(&db)
+            .exec(&txn)
+            .await?;
+        journal.commit(&txn, "Delete music").await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// add this music aggregator to the built-in liked playlist, it is also saved to db
+    pub async fn like(&self) -> anyhow::Result<()> {
+        let liked = Playlist::get_or_create_liked_in_db().await?;
+        liked.add_aggs_to_db(&vec![self.clone()]).await?;
+        Ok(())
+    }
+
+    /// remove this music aggregator from the built-in liked playlist
+    pub async fn unlike(&self) -> anyhow::Result<()> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited"))?;
+        let txn = db.begin().await?;
+        let journal = Journal::begin(&txn, JournalScope::music_aggs(vec![self.identity()])).await?;
+        playlist_music_junction::Entity::update_many()
+            .col_expr(
+                playlist_music_junction::Column::DeletedAt,
+                Expr::value(now_millis()),
+            )
+            .filter(liked_junction_condition())
+            .filter(playlist_music_junction::Column::MusicAggregatorId.eq(self.identity()))
+            .filter(playlist_music_junction::Column::DeletedAt.is_null())
+            .exec(&txn)
             .await?;
+        journal.commit(&txn, "Unlike music").await?;
+        txn.commit().await?;
         Ok(())
     }
 
+    pub async fn is_liked(&self) -> anyhow::Result<bool> {
+        Ok(Self::liked_status(&[self.identity()]).await?[0])
+    }
+
+    /// liked state of each identity, in the same order as `identities`
+    pub async fn liked_status(identities: &[String]) -> anyhow::Result<Vec<bool>> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited"))?;
+        let mut liked = HashSet::new();
+        // chunked to stay below the bind parameter limits
+        for chunk in identities.chunks(500) {
+            let junctions = playlist_music_junction::Entity::find()
+                .filter(liked_junction_condition())
+                .filter(playlist_music_junction::Column::DeletedAt.is_null())
+                .filter(playlist_music_junction::Column::MusicAggregatorId.is_in(chunk.to_vec()))
+                .all(&db)
+                .await?;
+            liked.extend(junctions.into_iter().map(|j| j.music_aggregator_id));
+        }
+        Ok(identities
+            .iter()
+            .map(|identity| liked.contains(identity))
+            .collect())
+    }
+
     pub async fn clear_unused() -> anyhow::Result<()> {
         let db = get_db()
             .await
             .ok_or(anyhow::anyhow!("Database is not inited"))?;
 
-        let junctions = playlist_music_junction::Entity::find().all(&db).await?;
+        let txn = db.begin().await?;
+        let junctions = playlist_music_junction::Entity::find().all(&txn).await?;
 
         let used_music_ids: HashSet<_> = junctions
             .into_iter()
@@ -303,17 +438,86 @@ impl MusicAggregator {
 
         let unused_music_aggs = music_aggregator::Entity::find()
             .filter(music_aggregator::Column::Identity.is_not_in(used_music_ids))
-            .all(&db)
+            .all(&txn)
             .await?;
 
+        let identities = unused_music_aggs
+            .iter()
+            .map(|music_agg| music_agg.identity.clone())
+            .collect();
+        let journal = Journal::begin(&txn, JournalScope::music_aggs(identities)).await?;
         for music_agg in unused_music_aggs {
-            music_agg.delete(&db).await?;
+            music_agg.delete(&txn).await?;
         }
+        journal.commit(&txn, "Clear unused music").await?;
+        txn.commit().await?;
 
         Ok(())
     }
 }
 
+/// junctions that belong to the built-in liked playlist
+fn liked_junction_condition() -> SimpleExpr {
+    Expr::col(playlist_music_junction::Column::PlaylistId).in_subquery(
+        Query::select()
+            .column(playlist::Column::Id)
+            .from(playlist::Entity)
+            .and_where(playlist::Column::SystemType.eq(SystemPlaylistType::Liked))
+            .to_owned(),
+    )
+}
+
+/// a music aggregator of the one music `id` by "artist", for tests
+#[cfg(test)]
+pub(crate) fn music_agg(server: MusicServer, id: &str, name: &str) -> MusicAggregator {
+    music_agg_by(server, id, name, "artist")
+}
+
+#[cfg(test)]
+pub(crate) fn music_agg_by(
+    server: MusicServer,
+    id: &str,
+    name: &str,
+    artist: &str,
+) -> MusicAggregator {
+    MusicAggregator::from_music(Music {
+        from_db: false,
+        server,
+        identity: id.to_string(),
+        name: name.to_string(),
+        duration: Some(215),
+        artists: vec![Artist {
+            name: artist.to_string(),
+            id: None,
+        }],
+        album: Some("album".to_string()),
+        album_id: None,
+        qualities: vec![],
+        cover: Some("cover".to_string()),
+    })
+}
+
+/// a new in-memory database with the collection "test" and its empty playlist "test",
+/// returns the collection id and the playlist, for tests
+#[cfg(test)]
+pub(crate) async fn empty_playlist() -> (i64, Playlist) {
+    use super::{database::set_db, playlist_collection::PlaylistCollection};
+
+    set_db("sqlite::memory:").await.unwrap();
+    let collection_id = PlaylistCollection::new("test".to_string())
+        .insert_to_db()
+        .await
+        .unwrap();
+    let playlist_id = Playlist::new("test".to_string(), None, None, vec![])
+        .insert_to_db(collection_id)
+        .await
+        .unwrap();
+    (
+        collection_id,
+        Playlist::find_in_db(playlist_id).await.unwrap(),
+    )
+}
+
 #[cfg(test)]
 mod test_music_aggregator {
     use sea_orm::EntityTrait;
@@ -331,14 +535,15 @@ mod test_music_aggregator {
 
     async fn re_init_db() {
         let _ = tracing_subscriber::fmt::try_init();
-        let db_file = "./sample_data/test.db";
-        let path = std::path::Path::new(db_file);
+        let path = std::env::temp_dir().join("music_api_test.db");
         if path.exists() {
-            std::fs::remove_file(path).unwrap();
+            std::fs::remove_file(&path).unwrap();
         }
-        std::fs::File::create(path).unwrap();
+        std::fs::File::create(&path).unwrap();
 
-        set_db(&("sqlite://".to_owned() + db_file)).await.unwrap();
+        set_db(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
         Migrator::up(&get_db().await.unwrap(), None).await.unwrap();
     }
 
@@ -443,7 +648,7 @@ mod test_music_aggregator {
         let playlist_collection = PlaylistCollection::new("test".to_string());
         let id = playlist_collection.insert_to_db().await.unwrap();
         let new_playlist_collection = PlaylistCollection::find_in_db(id).await.unwrap();
-        
+
         for playlist in playlists {
             let new_id = playlist.insert_to_db(new_playlist_collection.id).await.unwrap();
             let inserted_playlist = Playlist::find_in_db(new_id).await.unwrap();
@@ -466,4 +671,62 @@ mod test_music_aggregator {
             .unwrap();
         assert!(music_aggs.is_empty());
     }
+
+    fn liked_agg(name: &str) -> MusicAggregator {
+        MusicAggregator::from_music(crate::interface::music_aggregator::Music {
+            from_db: false,
+            server: MusicServer::Kuwo,
+            identity: name.to_string(),
+            name: name.to_string(),
+            duration: Some(200),
+            artists: vec![crate::interface::artist::Artist {
+                name: "米津玄師".to_string(),
+                id: None,
+            }],
+            album: None,
+            album_id: None,
+            qualities: vec![],
+            cover: Some("cover".to_string()),
+        })
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_like() {
+        set_db("sqlite::memory:").await.unwrap();
+
+        let lemon = liked_agg("Lemon");
+        let flamingo = liked_agg("Flamingo");
+        assert!(!lemon.is_liked().await.unwrap());
+
+        lemon.like().await.unwrap();
+        // liking twice is a no-op
+        lemon.like().await.unwrap();
+        assert!(lemon.is_liked().await.unwrap());
+        assert_eq!(
+            MusicAggregator::liked_status(&[flamingo.identity(), lemon.identity()])
+                .await
+                .unwrap(),
+            vec![false, true]
+        );
+
+        let liked = Playlist::get_or_create_liked_in_db().await.unwrap();
+        assert_eq!(
+            liked.type_field,
+            crate::interface::playlist::PlaylistType::Liked
+        );
+        assert_eq!(liked.get_musics_from_db().await.unwrap().len(), 1);
+        // the liked playlist is only created once
+        assert_eq!(Playlist::get_from_db().await.unwrap().len(), 1);
+
+        // system playlists and their collection can't be deleted
+        assert!(liked.clone().del_from_db().await.is_err());
+        let collection = PlaylistCollection::find_in_db(liked.collection_id.unwrap())
+            .await
+            .unwrap();
+        assert!(collection.delete_from_db().await.is_err());
+
+        lemon.unlike().await.unwrap();
+        assert!(!lemon.is_liked().await.unwrap());
+    }
 }
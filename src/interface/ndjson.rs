@@ -0,0 +1,441 @@
+use std::{io::Write as _, path::PathBuf, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use flate2::{
+    write::{GzEncoder, MultiGzDecoder},
+    Compression,
+};
+use sea_orm::{
+    ConnectionTrait, DatabaseConnection, EntityTrait, Iterable as _, PrimaryKeyToColumn as _,
+    QueryOrder as _, QuerySelect as _, TransactionTrait as _,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _, BufWriter};
+
+use crate::{
+    data::models::{
+        music_aggregator, play_history, playlist, playlist_collection, playlist_music_junction,
+    },
+    server::{kuwo, netease},
+};
+
+use super::{
+    database::{clear_library, get_db},
+    json::{DatabaseImporter, MusicDataJson},
+};
+
+/// bump when the line layout changes, older streams are still read
+pub const NDJSON_FORMAT_VERSION: u64 = 1;
+
+const NDJSON_FORMAT: &str = "music_api_ndjson";
+// rows read from the database per query
+const STREAM_BATCH_SIZE: u64 = 1000;
+// bytes read from the file at once
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// the tables of a database backup, in the order they are streamed and inserted
+const TABLES: [&str; 7] = [
+    "play_history",
+    "playlist_collection",
+    "playlists",
+    "music_aggregators",
+    "kuwo_table",
+    "netease_table",
+    "playlist_music_junctions",
+];
+
+/// a line which is not a row: the header, a table or the end of the stream
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Marker {
+    Format { name: String, version: u64 },
+    Table(String),
+    End { rows: u64 },
+}
+
+impl MusicDataJson {
+    /// stream the database to an NDJSON file, one table after another and one row per line,
+    /// so memory stays bounded for large libraries. returns the number of rows.
+    /// the file holds the same data as a `Database` backup from `save_to`.
+    pub async fn save_database_ndjson_to(path: &str, gzip: bool) -> Result<u64> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow!("Database is not initialized"))?;
+        let path = PathBuf::from_str(path)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let mut writer = LineWriter::new(tokio::fs::File::create(path).await?, gzip);
+        writer
+            .write_marker(&Marker::Format {
+                name: NDJSON_FORMAT.to_string(),
+                version: NDJSON_FORMAT_VERSION,
+            })
+            .await?;
+        // one read transaction, every batch of every table is read from the same library
+        let txn = db.begin().await?;
+        let mut rows = 0;
+        for table in TABLES {
+            writer
+                .write_marker(&Marker::Table(table.to_string()))
+                .await?;
+            rows += match table {
+                "play_history" => write_table::<play_history::Entity, _>(&txn, &mut writer).await?,
+                "playlist_collection" => {
+                    write_table::<playlist_collection::Entity, _>(&txn, &mut writer).await?
+                }
+                "playlists" => write_table::<playlist::Entity, _>(&txn, &mut writer).await?,
+                "music_aggregators" => {
+                    write_table::<music_aggregator::Entity, _>(&txn, &mut writer).await?
+                }
+                "kuwo_table" => write_table::<kuwo::model::Entity, _>(&txn, &mut writer).await?,
+                "netease_table" => {
+                    write_table::<netease::model::Entity, _>(&txn, &mut writer).await?
+                }
+                _ => write_table::<playlist_music_junction::Entity, _>(&txn, &mut writer).await?,
+            };
+        }
+        txn.commit().await?;
+        writer.write_marker(&Marker::End { rows }).await?;
+        writer.finish().await?;
+        Ok(rows)
+    }
+
+    /// replace the database with an NDJSON backup from `save_database_ndjson_to`, gzip or not,
+    /// like `apply_to_db` does with a `Database` backup. the file is checked in a first pass
+    /// so a truncated or broken one leaves the library untouched, then the library is cleared
+    /// and rows are inserted in one transaction while it is read again. returns the number of rows.
+    pub async fn apply_ndjson_to_db(path: &str) -> Result<u64> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow!("Database is not initialized"))?;
+        read_stream::<DatabaseConnection>(path, None).await?;
+
+        let conn = db.begin().await?;
+        clear_library(&conn).await?;
+        let rows = read_stream(path, Some(&mut DatabaseImporter::new(&conn))).await?;
+        conn.commit().await?;
+        Ok(rows)
+    }
+}
+
+/// a row of one of the `TABLES`
+enum TableRow {
+    PlayHistory(play_history::Model),
+    PlaylistCollection(playlist_collection::Model),
+    Playlist(playlist::Model),
+    MusicAggregator(music_aggregator::Model),
+    Kuwo(kuwo::model::Model),
+    Netease(netease::model::Model),
+    PlaylistMusicJunction(playlist_music_junction::Model),
+}
+
+impl TableRow {
+    fn parse(table: &str, line: &str) -> Result<Self> {
+        fn row<T: DeserializeOwned>(line: &str) -> Result<T> {
+            serde_json::from_str(line)
+                .map_err(|e| anyhow!("Invalid row in music data ndjson: {}", e))
+        }
+        Ok(match table {
+            "play_history" => TableRow::PlayHistory(row(line)?),
+            "playlist_collection" => TableRow::PlaylistCollection(row(line)?),
+            "playlists" => TableRow::Playlist(row(line)?),
+            "music_aggregators" => TableRow::MusicAggregator(row(line)?),
+            "kuwo_table" => TableRow::Kuwo(row(line)?),
+            "netease_table" => TableRow::Netease(row(line)?),
+            "playlist_music_junctions" => TableRow::PlaylistMusicJunction(row(line)?),
+            _ => return Err(anyhow!("Music data ndjson has a row before any table.")),
+        })
+    }
+
+    async fn insert<C: ConnectionTrait>(
+        self,
+        importer: &mut DatabaseImporter<'_, C>,
+    ) -> Result<()> {
+        match self {
+            TableRow::PlayHistory(model) => importer.insert_play_history(model).await?,
+            TableRow::PlaylistCollection(model) => importer.insert_collection(model).await?,
+            TableRow::Playlist(model) => importer.insert_playlist(model).await?,
+            TableRow::MusicAggregator(model) => importer.insert_music_agg(model).await,
+            TableRow::Kuwo(model) => importer.insert_kuwo(model).await,
+            TableRow::Netease(model) => importer.insert_netease(model).await,
+            TableRow::PlaylistMusicJunction(model) => importer.insert_junction(model).await?,
+        }
+        Ok(())
+    }
+}
+
+/// read every row of a stream, and insert it if there is an importer.
+/// fails unless the stream ends with all of its rows.
+async fn read_stream<C: ConnectionTrait>(
+    path: &str,
+    mut importer: Option<&mut DatabaseImporter<'_, C>>,
+) -> Result<u64> {
+    let mut reader = LineReader::new(tokio::fs::File::open(path).await?);
+    match reader.next_marker().await? {
+        Some(Marker::Format { name, version }) if name == NDJSON_FORMAT => {
+            if version > NDJSON_FORMAT_VERSION {
+                return Err(anyhow!(
+                    "Music data ndjson version {} is newer than the supported version {}.",
+                    version,
+                    NDJSON_FORMAT_VERSION
+                ));
+            }
+        }
+        _ => return Err(anyhow!("Not a music data ndjson file: {}", path)),
+    }
+
+    let mut table = String::new();
+    let mut rows = 0;
+    loop {
+        let line = reader.next_line().await?.ok_or(anyhow!(
+            "Music data ndjson ends before all rows, it is truncated."
+        ))?;
+        match serde_json::from_str::<Marker>(&line) {
+            Ok(Marker::Table(name)) => {
+                if !TABLES.contains(&name.as_str()) {
+                    return Err(anyhow!("Unknown table in music data ndjson: {}", name));
+                }
+                table = name;
+                continue;
+            }
+            Ok(Marker::End { rows: expected }) if expected == rows => return Ok(rows),
+            Ok(Marker::End { rows: expected }) => {
+                return Err(anyhow!(
+                    "Music data ndjson has {} rows, {} expected.",
+                    rows,
+                    expected
+                ))
+            }
+            Ok(Marker::Format { .. }) | Err(_) => {}
+        }
+        let row = TableRow::parse(&table, &line)?;
+        if let Some(importer) = importer.as_mut() {
+            row.insert(importer).await?;
+        }
+        rows += 1;
+    }
+}
+
+/// write every row of a table in primary key order, a batch at a time
+async fn write_table<E, C>(db: &C, writer: &mut LineWriter) -> Result<u64>
+where
+    E: EntityTrait,
+    E::Model: Serialize,
+    C: ConnectionTrait,
+{
+    let mut query = E::find();
+    for key in E::PrimaryKey::iter() {
+        query = query.order_by_asc(key.into_column());
+    }
+    let mut rows = 0;
+    let mut lines = Vec::new();
+    loop {
+        let batch = query
+            .clone()
+            .offset(rows)
+            .limit(STREAM_BATCH_SIZE)
+            .all(db)
+            .await?;
+        for model in batch.iter() {
+            serde_json::to_writer(&mut lines, model)?;
+            lines.push(b'\n');
+        }
+        writer.write(&lines).await?;
+        lines.clear();
+        rows += batch.len() as u64;
+        if (batch.len() as u64) < STREAM_BATCH_SIZE {
+            return Ok(rows);
+        }
+    }
+}
+
+/// lines to a file, gzip output is drained after every write so only a batch is buffered
+struct LineWriter {
+    file: BufWriter<tokio::fs::File>,
+    gzip: Option<GzEncoder<Vec<u8>>>,
+}
+
+impl LineWriter {
+    fn new(file: tokio::fs::File, gzip: bool) -> Self {
+        Self {
+            file: BufWriter::new(file),
+            gzip: gzip.then(|| GzEncoder::new(Vec::new(), Compression::default())),
+        }
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        match &mut self.gzip {
+            Some(encoder) => {
+                encoder.write_all(bytes)?;
+                let compressed = std::mem::take(encoder.get_mut());
+                self.file.write_all(&compressed).await?;
+            }
+            None => self.file.write_all(bytes).await?,
+        }
+        Ok(())
+    }
+
+    async fn write_marker(&mut self, marker: &Marker) -> Result<()> {
+        let mut line = serde_json::to_vec(marker)?;
+        line.push(b'\n');
+        self.write(&line).await
+    }
+
+    async fn finish(mut self) -> Result<()> {
+        if let Some(encoder) = self.gzip.take() {
+            self.file.write_all(&encoder.finish()?).await?;
+        }
+        self.file.flush().await?;
+        Ok(())
+    }
+}
+
+/// lines of a file, gzip is detected from the first bytes
+struct LineReader {
+    file: tokio::fs::File,
+    gzip: Option<MultiGzDecoder<Vec<u8>>>,
+    buffer: Vec<u8>,
+    // start of the unread part of `buffer`
+    pos: usize,
+    started: bool,
+    eof: bool,
+}
+
+impl LineReader {
+    fn new(file: tokio::fs::File) -> Self {
+        Self {
+            file,
+            gzip: None,
+            buffer: Vec::new(),
+            pos: 0,
+            started: false,
+            eof: false,
+        }
+    }
+
+    /// the next non-empty line
+    async fn next_line(&mut self) -> Result<Option<String>> {
+        loop {
+            if let Some(end) = self.buffer[self.pos..].iter().position(|b| *b == b'\n') {
+                let line = &self.buffer[self.pos..self.pos + end];
+                self.pos += end + 1;
+                let line = std::str::from_utf8(line)?.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                return Ok(Some(line.to_string()));
+            }
+            if self.eof {
+                let line = std::str::from_utf8(&self.buffer[self.pos..])?
+                    .trim()
+                    .to_string();
+                self.pos = self.buffer.len();
+                return Ok((!line.is_empty()).then_some(line));
+            }
+            self.read_chunk().await?;
+        }
+    }
+
+    async fn next_marker(&mut self) -> Result<Option<Marker>> {
+        match self.next_line().await? {
+            Some(line) => Ok(serde_json::from_str(&line).ok()),
+            None => Ok(None),
+        }
+    }
+
+    async fn read_chunk(&mut self) -> Result<()> {
+        self.buffer.drain(..self.pos);
+        self.pos = 0;
+        let mut chunk = vec![0; READ_CHUNK_SIZE];
+        let read = self.file.read(&mut chunk).await?;
+        let chunk = &chunk[..read];
+        if !self.started {
+            self.started = true;
+            if chunk.starts_with(&GZIP_MAGIC) {
+                self.gzip = Some(MultiGzDecoder::new(Vec::new()));
+            }
+        }
+        match (&mut self.gzip, read) {
+            (Some(_), 0) => {
+                let decoder = self.gzip.take().unwrap();
+                self.buffer.extend(decoder.finish()?);
+                self.eof = true;
+            }
+            (Some(decoder), _) => {
+                decoder.write_all(chunk)?;
+                self.buffer.append(decoder.get_mut());
+            }
+            (None, 0) => self.eof = true,
+            (None, _) => self.buffer.extend_from_slice(chunk),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serial_test::serial;
+
+    use super::*;
+    use crate::interface::{
+        music_aggregator::{empty_playlist, music_agg},
+        server::MusicServer,
+    };
+
+    #[tokio::test]
+    #[serial]
+    async fn test_ndjson() {
+        empty_playlist()
+            .await
+            .1
+            .add_aggs_to_db(
+                &(0..5)
+                    .map(|i| music_agg(MusicServer::Kuwo, &i.to_string(), &format!("m{}", i)))
+                    .collect::<Vec<_>>(),
+            )
+            .await
+            .unwrap();
+        let MusicDataJson::Database(before) = MusicDataJson::from_database().await.unwrap() else {
+            panic!("not a database");
+        };
+
+        let dir = std::env::temp_dir().join("music_api_test_ndjson");
+        for gzip in [false, true] {
+            let path = dir.join(format!("backup_{}.ndjson", gzip));
+            let path = path.to_str().unwrap();
+            let rows = MusicDataJson::save_database_ndjson_to(path, gzip)
+                .await
+                .unwrap();
+            let bytes = tokio::fs::read(path).await.unwrap();
+            assert_eq!(bytes.starts_with(&GZIP_MAGIC), gzip);
+
+            assert_eq!(MusicDataJson::apply_ndjson_to_db(path).await.unwrap(), rows);
+            let MusicDataJson::Database(after) = MusicDataJson::from_database().await.unwrap()
+            else {
+                panic!("not a database");
+            };
+            assert_eq!(after.music_aggregators, before.music_aggregators);
+            assert_eq!(after.kuwo_table, before.kuwo_table);
+            assert_eq!(
+                after.playlist_music_junctions.len(),
+                before.playlist_music_junctions.len()
+            );
+
+            // a truncated file is refused before the library is replaced
+            if !gzip {
+                let text = String::from_utf8(bytes).unwrap();
+                let truncated = text.rsplit_once("{\"end\"").unwrap().0;
+                tokio::fs::write(path, truncated).await.unwrap();
+                assert!(MusicDataJson::apply_ndjson_to_db(path).await.is_err());
+                assert_eq!(
+                    MusicDataJson::from_database().await.unwrap(),
+                    MusicDataJson::Database(after)
+                );
+            }
+        }
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+}
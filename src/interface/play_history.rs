@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+
+use sea_orm::{
+    prelude::Expr, sea_query::Alias, ColumnTrait as _, ConnectionTrait, DatabaseBackend,
+    EntityTrait, Order, QueryFilter, QueryOrder as _, QuerySelect as _, Select,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    data::models::{music_aggregator, play_history},
+    server::{kuwo, netease},
+};
+
+use super::{database::get_db, server::MusicServer, utils::split_string};
+
+// identities per query, keeps every statement far below the bind parameter limits
+const QUERY_CHUNK_SIZE: usize = 500;
+
+/// Listening statistics of one music aggregator
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackPlayStats {
+    pub identity: String,
+    pub name: String,
+    pub artist: String,
+    pub play_count: i64,
+    /// seconds
+    pub played_duration: i64,
+    /// unix time in milliseconds
+    pub last_played_at: i64,
+}
+
+/// Listening statistics of one artist
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtistPlayStats {
+    pub artist: String,
+    pub play_count: i64,
+    /// seconds
+    pub played_duration: i64,
+}
+
+/// The plays recorded by `MusicAggregator::record_play`.
+/// Time windows are unix milliseconds, `since` is inclusive and `until` exclusive, `None` is unbounded.
+pub struct PlayHistory;
+
+fn in_window(
+    select: Select<play_history::Entity>,
+    since: Option<i64>,
+    until: Option<i64>,
+) -> Select<play_history::Entity> {
+    let mut select = select;
+    if let Some(since) = since {
+        select = select.filter(play_history::Column::PlayedAt.gte(since));
+    }
+    if let Some(until) = until {
+        select = select.filter(play_history::Column::PlayedAt.lt(until));
+    }
+    select
+}
+
+// postgres sums bigints to numeric
+fn integer_type(backend: DatabaseBackend) -> Alias {
+    match backend {
+        DatabaseBackend::MySql => Alias::new("SIGNED"),
+        _ => Alias::new("BIGINT"),
+    }
+}
+
+/// artist names of the saved music aggregators `identities` as their default music spells them
+async fn artists_in_db<C: ConnectionTrait>(
+    db: &C,
+    identities: Vec<String>,
+) -> anyhow::Result<HashMap<String, Vec<String>>> {
+    let mut aggs = Vec::new();
+    for chunk in identities.chunks(QUERY_CHUNK_SIZE) {
+        aggs.extend(
+            music_aggregator::Entity::find()
+                .filter(music_aggregator::Column::Identity.is_in(chunk.to_vec()))
+                .all(db)
+                .await?,
+        );
+    }
+
+    // todo: add more music server
+    let kuwo_ids: Vec<String> = aggs
+        .iter()
+        .filter_map(|a| a.kuwo_music_id.clone())
+        .collect();
+    let netease_ids: Vec<String> = aggs
+        .iter()
+        .filter_map(|a| a.netease_music_id.clone())
+        .collect();
+    let mut kuwo_artists = HashMap::new();
+    for chunk in kuwo_ids.chunks(QUERY_CHUNK_SIZE) {
+        for music in kuwo::model::Entity::find()
+            .filter(kuwo::model::Column::MusicId.is_in(chunk.to_vec()))
+            .all(db)
+            .await?
+        {
+            kuwo_artists.insert(music.music_id, music.artists.0);
+        }
+    }
+    let mut netease_artists = HashMap::new();
+    for chunk in netease_ids.chunks(QUERY_CHUNK_SIZE) {
+        for music in netease::model::Entity::find()
+            .filter(netease::model::Column::MusicId.is_in(chunk.to_vec()))
+            .all(db)
+            .await?
+        {
+            netease_artists.insert(music.music_id, music.artists.0);
+        }
+    }
+
+    let mut artists = HashMap::new();
+    for agg in aggs {
+        let kuwo = agg
+            .kuwo_music_id
+            .as_ref()
+            .and_then(|id| kuwo_artists.get(id));
+        let netease = agg
+            .netease_music_id
+            .as_ref()
+            .and_then(|id| netease_artists.get(id));
+        let found = match agg.default_server {
+            MusicServer::Kuwo => kuwo.or(netease),
+            MusicServer::Netease => netease.or(kuwo),
+        };
+        if let Some(found) = found {
+            artists.insert(
+                agg.identity,
+                found.iter().map(|artist| artist.name.clone()).collect(),
+            );
+        }
+    }
+    Ok(artists)
+}
+
+impl PlayHistory {
+    /// plays per music aggregator, most played first
+    async fn track_stats(
+        since: Option<i64>,
+        until: Option<i64>,
+        order_by_recent: bool,
+        limit: Option<u64>,
+    ) -> anyhow::Result<Vec<TrackPlayStats>> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+        let backend = db.get_database_backend();
+
+        let last_played_at = Expr::col(play_history::Column::PlayedAt).max();
+        // plays recorded in the same millisecond are ordered by insertion
+        let last_id = Expr::col(play_history::Column::Id).max();
+        let play_count = Expr::col(play_history::Column::Id).count();
+        let mut select = in_window(play_history::Entity::find(), since, until)
+            .select_only()
+            .column(play_history::Column::MusicAggregatorId)
+            .expr(play_count.clone())
+            .expr(
+                Expr::expr(Expr::col(play_history::Column::PlayedDuration).sum())
+                    .cast_as(integer_type(backend)),
+            )
+            .expr(last_played_at.clone())
+            .group_by(play_history::Column::MusicAggregatorId);
+        select = if order_by_recent {
+            select
+                .order_by(last_played_at, Order::Desc)
+                .order_by(last_id, Order::Desc)
+        } else {
+            select
+                .order_by(play_count, Order::Desc)
+                .order_by(last_played_at, Order::Desc)
+                .order_by(last_id, Order::Desc)
+        };
+        if let Some(limit) = limit {
+            select = select.limit(limit);
+        }
+        let rows: Vec<(String, i64, Option<i64>, i64)> = select.into_tuple().all(&db).await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(identity, play_count, played_duration, last_played_at)| {
+                let (name, artist) =
+                    split_string(&identity).unwrap_or_else(|_| (identity.clone(), String::new()));
+                TrackPlayStats {
+                    identity,
+                    name,
+                    artist,
+                    play_count,
+                    played_duration: played_duration.unwrap_or(0),
+                    last_played_at,
+                }
+            })
+            .collect())
+    }
+
+    /// the most played music aggregators in the window
+    pub async fn top_tracks(
+        since: Option<i64>,
+        until: Option<i64>,
+        limit: u64,
+    ) -> anyhow::Result<Vec<TrackPlayStats>> {
+        Self::track_stats(since, until, false, Some(limit)).await
+    }
+
+    /// the most played artists in the window.
+    /// the per track sums from the database are split by artist here, a play counts for every
+    /// artist of the track. the artists come from the saved music, a track played without
+    /// being saved only has the lowercased artists of its identity.
+    pub async fn top_artists(
+        since: Option<i64>,
+        until: Option<i64>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<ArtistPlayStats>> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+        let tracks = Self::track_stats(since, until, false, None).await?;
+        let mut saved_artists =
+            artists_in_db(&db, tracks.iter().map(|t| t.identity.clone()).collect()).await?;
+        // keyed by the lowercased name, so saved and unsaved tracks of an artist add up
+        let mut artists: HashMap<String, ArtistPlayStats> = HashMap::new();
+        for track in tracks {
+            let (names, saved) = match saved_artists.remove(&track.identity) {
+                Some(names) => (names, true),
+                None => (
+                    track.artist.split('&').map(|a| a.to_string()).collect(),
+                    false,
+                ),
+            };
+            for artist in names.into_iter().filter(|a| !a.is_empty()) {
+                let stats =
+                    artists
+                        .entry(artist.to_lowercase())
+                        .or_insert_with(|| ArtistPlayStats {
+                            artist: artist.clone(),
+                            play_count: 0,
+                            played_duration: 0,
+                        });
+                if saved {
+                    stats.artist = artist;
+                }
+                stats.play_count += track.play_count;
+                stats.played_duration += track.played_duration;
+            }
+        }
+        let mut artists: Vec<ArtistPlayStats> = artists.into_values().collect();
+        artists.sort_by(|a, b| {
+            b.play_count
+                .cmp(&a.play_count)
+                .then(b.played_duration.cmp(&a.played_duration))
+                .then(a.artist.cmp(&b.artist))
+        });
+        artists.truncate(limit);
+        Ok(artists)
+    }
+
+    /// total seconds listened in the window
+    pub async fn total_listening_time(
+        since: Option<i64>,
+        until: Option<i64>,
+    ) -> anyhow::Result<i64> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+        let backend = db.get_database_backend();
+        let total: Option<Option<i64>> = in_window(play_history::Entity::find(), since, until)
+            .select_only()
+            .expr(
+                Expr::expr(Expr::col(play_history::Column::PlayedDuration).sum())
+                    .cast_as(integer_type(backend)),
+            )
+            .into_tuple()
+            .one(&db)
+            .await?;
+        Ok(total.flatten().unwrap_or(0))
+    }
+
+    /// the last played music aggregators, each one once, most recent first
+    pub async fn recently_played(limit: u64) -> anyhow::Result<Vec<TrackPlayStats>> {
+        Self::track_stats(None, None, true, Some(limit)).await
+    }
+
+    /// the raw plays in the window, most recent first
+    pub async fn get_from_db(
+        since: Option<i64>,
+        until: Option<i64>,
+    ) -> anyhow::Result<Vec<play_history::Model>> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+        Ok(in_window(play_history::Entity::find(), since, until)
+            .order_by_desc(play_history::Column::PlayedAt)
+            .order_by_desc(play_history::Column::Id)
+            .all(&db)
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sea_orm::{ActiveValue::NotSet, Set};
+    use serial_test::serial;
+
+    use crate::interface::{
+        artist::Artist,
+        database::set_db,
+        json::MusicDataJson,
+        music_aggregator::{Music, MusicAggregator},
+        playlist::Playlist,
+        playlist_collection::PlaylistCollection,
+    };
+
+    use super::*;
+
+    fn agg(name: &str, artists: &[&str]) -> MusicAggregator {
+        MusicAggregator::from_music(Music {
+            from_db: false,
+            server: MusicServer::Kuwo,
+            identity: name.to_string(),
+            name: name.to_string(),
+            duration: Some(200),
+            artists: artists
+                .iter()
+                .map(|a| Artist {
+                    name: a.to_string(),
+                    id: None,
+                })
+                .collect(),
+            album: None,
+            album_id: None,
+            qualities: vec![],
+            cover: Some("cover".to_string()),
+        })
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_stats() {
+        set_db("sqlite::memory:").await.unwrap();
+        let db = get_db().await.unwrap();
+
+        // a play from long ago, outside of the recent window
+        play_history::Entity::insert(play_history::ActiveModel {
+            id: NotSet,
+            music_aggregator_id: Set(agg("Lemon", &["米津玄師"]).identity()),
+            server: Set(MusicServer::Netease),
+            played_at: Set(1000),
+            played_duration: Set(100),
+            completed: Set(false),
+        })
+        .exec(&db)
+        .await
+        .unwrap();
+
+        let lemon = agg("Lemon", &["米津玄師"]);
+        let uchiage = agg("打上花火", &["DAOKO", "米津玄師"]);
+        lemon
+            .record_play(MusicServer::Kuwo, 250, true)
+            .await
+            .unwrap();
+        uchiage
+            .record_play(MusicServer::Kuwo, 280, true)
+            .await
+            .unwrap();
+        uchiage
+            .record_play(MusicServer::Kuwo, 30, false)
+            .await
+            .unwrap();
+
+        let top = PlayHistory::top_tracks(None, None, 10).await.unwrap();
+        assert_eq!(
+            top.iter()
+                .map(|t| (t.name.as_str(), t.play_count, t.played_duration))
+                .collect::<Vec<_>>(),
+            vec![("打上花火", 2, 310), ("lemon", 2, 350)]
+        );
+        let recent = PlayHistory::top_tracks(Some(2000), None, 10).await.unwrap();
+        assert_eq!(recent[1].play_count, 1);
+
+        let artists = PlayHistory::top_artists(None, None, 10).await.unwrap();
+        assert_eq!(artists[0].artist, "米津玄師");
+        assert_eq!(artists[0].play_count, 4);
+        assert_eq!(artists[1].artist, "daoko");
+
+        assert_eq!(
+            PlayHistory::total_listening_time(None, None).await.unwrap(),
+            660
+        );
+        assert_eq!(
+            PlayHistory::total_listening_time(None, Some(2000))
+                .await
+                .unwrap(),
+            100
+        );
+        assert_eq!(
+            PlayHistory::total_listening_time(Some(i64::MAX), None)
+                .await
+                .unwrap(),
+            0
+        );
+
+        let recently_played = PlayHistory::recently_played(1).await.unwrap();
+        assert_eq!(recently_played.len(), 1);
+
+        // saved music keeps the artists as they are spelled
+        let boxer = agg("The Boxer", &["Simon & Garfunkel"]);
+        let collection_id = PlaylistCollection::new("c".to_string())
+            .insert_to_db()
+            .await
+            .unwrap();
+        let playlist_id = Playlist::new("p".to_string(), None, None, vec![])
+            .insert_to_db(collection_id)
+            .await
+            .unwrap();
+        Playlist::find_in_db(playlist_id)
+            .await
+            .unwrap()
+            .add_aggs_to_db(&vec![uchiage.clone(), boxer.clone()])
+            .await
+            .unwrap();
+        boxer
+            .record_play(MusicServer::Kuwo, 10, false)
+            .await
+            .unwrap();
+        let artists = PlayHistory::top_artists(None, None, 10).await.unwrap();
+        assert_eq!(
+            artists
+                .iter()
+                .map(|a| (a.artist.as_str(), a.play_count))
+                .collect::<Vec<_>>(),
+            vec![("米津玄師", 4), ("DAOKO", 2), ("Simon & Garfunkel", 1)]
+        );
+
+        // the history survives a database export and import
+        let json = MusicDataJson::from_database().await.unwrap();
+        json.apply_to_db(None, None).await.unwrap();
+        assert_eq!(PlayHistory::get_from_db(None, None).await.unwrap().len(), 5);
+    }
+}
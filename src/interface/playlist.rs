@@ -1,30 +1,343 @@
+use std::collections::{HashMap, HashSet};
+
 use sea_orm::{
     prelude::Expr,
-    sea_query::{Alias, Func, Query},
+    sea_query::{Alias, Func, Query, SimpleExpr, StringLen},
+    ActiveModelTrait as _,
     ActiveValue::NotSet,
-    ColumnTrait as _, Condition, ConnectionTrait, EntityTrait, ModelTrait, PaginatorTrait,
-    QueryFilter, Set,
+    ColumnTrait as _, Condition, ConnectionTrait, DatabaseBackend, DeriveActiveEnum, EntityTrait,
+    EnumIter, IntoActiveModel as _, JoinType, Order, PaginatorTrait as _, QueryFilter,
+    QueryOrder as _, QuerySelect as _, RelationTrait as _, Set, TransactionTrait as _,
+    TryInsertResult, Unchanged,
 };
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    data::models::{music_aggregator, playlist, playlist_music_junction},
-    interface::utils::find_duplicate_music_agg,
+    data::models::{music_aggregator, playlist, playlist_collection, playlist_music_junction},
+    server::{kuwo, netease},
 };
 use anyhow::Result;
 
 use super::{
     database::get_db,
-    music_aggregator::MusicAggregator,
+    journal::{Journal, JournalScope},
+    music_aggregator::{Music, MusicAggregator},
+    playlist_collection::PlaylistCollection,
     playlist_subscription::{PlayListSubscription, PlayListSubscriptionVec},
-    results::PlaylistUpdateSubscriptionResult,
+    results::{PlaylistAddMusicAggsResult, PlaylistUpdateSubscriptionResult},
     server::MusicServer,
+    smart_playlist::SmartPlaylistRules,
+    utils::{move_item, now_millis, reorder_by_keys},
 };
 
+// rows per multi-row insert, keeps every statement far below the bind parameter limits
+const INSERT_CHUNK_SIZE: usize = 500;
+
+fn inserted_rows(result: TryInsertResult<u64>) -> u64 {
+    match result {
+        TryInsertResult::Inserted(rows) => rows,
+        _ => 0,
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PlaylistType {
     UserPlaylist,
     Album,
+    /// the built-in playlist of liked musics
+    Liked,
+}
+
+/// kinds of built-in db playlists, stored in `playlist.system_type`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Eq, Hash, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::N(1))")]
+pub enum SystemPlaylistType {
+    #[sea_orm(string_value = "L")]
+    Liked,
+}
+
+/// where `SavedAggs::match_agg` puts an incoming music aggregator
+pub(crate) enum AggMatch {
+    /// the saved one with the same identity, `updated` when it got a missing server
+    Saved {
+        identity: String,
+        updated: bool,
+    },
+    /// another music aggregator already owns one of its musics
+    Reused(String),
+    Created(String),
+    /// it has no music and can't be saved
+    NoMusic,
+}
+
+/// matches incoming music aggregators against the saved ones, for `Playlist::add_aggs_to_db`
+/// and the import plans. matched and created music aggregators are kept for the later ones.
+#[derive(Default)]
+pub(crate) struct SavedAggs {
+    pub(crate) aggs: HashMap<String, music_aggregator::Model>,
+    kuwo_owners: HashMap<String, String>,
+    netease_owners: HashMap<String, String>,
+}
+
+impl SavedAggs {
+    /// load the saved music aggregators `music_aggs` may collide with
+    pub(crate) async fn load<C: ConnectionTrait>(
+        &mut self,
+        db: &C,
+        music_aggs: &[MusicAggregator],
+    ) -> Result<()> {
+        let server_ids = |server: MusicServer| -> Vec<String> {
+            music_aggs
+                .iter()
+                .flat_map(|agg| agg.musics.iter())
+                .filter(|music| music.server == server)
+                .map(|music| music.identity.clone())
+                .collect()
+        };
+        // todo: add more music server
+        for (column, ids) in [
+            (
+                music_aggregator::Column::Identity,
+                music_aggs.iter().map(|agg| agg.identity()).collect(),
+            ),
+            (
+                music_aggregator::Column::KuwoMusicId,
+                server_ids(MusicServer::Kuwo),
+            ),
+            (
+                music_aggregator::Column::NeteaseMusicId,
+                server_ids(MusicServer::Netease),
+            ),
+        ] {
+            for chunk in ids.chunks(INSERT_CHUNK_SIZE) {
+                for model in music_aggregator::Entity::find()
+                    .filter(column.is_in(chunk.to_vec()))
+                    .all(db)
+                    .await?
+                {
+                    // the kept one may differ from the saved row already
+                    if self.aggs.contains_key(&model.identity) {
+                        continue;
+                    }
+                    if let Some(id) = &model.kuwo_music_id {
+                        self.kuwo_owners
+                            .entry(id.clone())
+                            .or_insert(model.identity.clone());
+                    }
+                    if let Some(id) = &model.netease_music_id {
+                        self.netease_owners
+                            .entry(id.clone())
+                            .or_insert(model.identity.clone());
+                    }
+                    self.aggs.insert(model.identity.clone(), model);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn match_agg(&mut self, music_agg: &MusicAggregator) -> AggMatch {
+        let identity = music_agg.identity();
+        let music_id = |server: MusicServer| {
+            music_agg
+                .musics
+                .iter()
+                .find(|x| x.server == server)
+                .map(|x| x.identity.clone())
+        };
+        let kuwo_id = music_id(MusicServer::Kuwo);
+        let netease_id = music_id(MusicServer::Netease);
+
+        if let Some(agg) = self.aggs.get_mut(&identity) {
+            // fill the servers the saved music aggregator is missing,
+            // unless the music is already owned by another music aggregator
+            let mut updated = false;
+            if let (None, Some(id)) = (&agg.kuwo_music_id, &kuwo_id) {
+                if !self.kuwo_owners.contains_key(id) {
+                    agg.kuwo_music_id = Some(id.clone());
+                    self.kuwo_owners.insert(id.clone(), identity.clone());
+                    updated = true;
+                }
+            }
+            if let (None, Some(id)) = (&agg.netease_music_id, &netease_id) {
+                if !self.netease_owners.contains_key(id) {
+                    agg.netease_music_id = Some(id.clone());
+                    self.netease_owners.insert(id.clone(), identity.clone());
+                    updated = true;
+                }
+            }
+            return AggMatch::Saved { identity, updated };
+        }
+        // 因为某些平台的 不同名称的歌曲公用一个id, 所以可能会出现重复
+        // 此时应该使用已有的MusicAggregator
+        if let Some(found) = kuwo_id
+            .as_ref()
+            .and_then(|id| self.kuwo_owners.get(id))
+            .or_else(|| {
+                netease_id
+                    .as_ref()
+                    .and_then(|id| self.netease_owners.get(id))
+            })
+        {
+            return AggMatch::Reused(found.clone());
+        }
+        let Some(first) = music_agg.musics.first() else {
+            return AggMatch::NoMusic;
+        };
+        if let Some(id) = kuwo_id.as_ref() {
+            self.kuwo_owners.insert(id.clone(), identity.clone());
+        }
+        if let Some(id) = netease_id.as_ref() {
+            self.netease_owners.insert(id.clone(), identity.clone());
+        }
+        self.aggs.insert(
+            identity.clone(),
+            music_aggregator::Model {
+                identity: identity.clone(),
+                default_server: first.server.clone(),
+                kuwo_music_id: kuwo_id,
+                netease_music_id: netease_id,
+                created_at: now_millis(),
+                updated_at: now_millis(),
+            },
+        );
+        AggMatch::Created(identity)
+    }
+
+    /// only musics owned by a music aggregator can be saved
+    pub(crate) fn owns(&self, music: &Music) -> bool {
+        // todo: add more music server
+        match music.server {
+            MusicServer::Kuwo => self.kuwo_owners.contains_key(&music.identity),
+            MusicServer::Netease => self.netease_owners.contains_key(&music.identity),
+        }
+    }
+}
+
+/// the value of a music table column, taken from the default server of each music aggregator
+fn default_music_expr(column: impl Fn(&str) -> SimpleExpr) -> SimpleExpr {
+    // todo: add more music server
+    Expr::case(
+        Expr::col((
+            music_aggregator::Entity,
+            music_aggregator::Column::DefaultServer,
+        ))
+        .eq(MusicServer::Kuwo),
+        column("kuwo_music"),
+    )
+    .case(
+        Expr::col((
+            music_aggregator::Entity,
+            music_aggregator::Column::DefaultServer,
+        ))
+        .eq(MusicServer::Netease),
+        column("netease_music"),
+    )
+    .into()
+}
+
+fn music_column_expr(table: &str, column: &str) -> SimpleExpr {
+    Expr::col((Alias::new(table), Alias::new(column))).into()
+}
+
+/// the music aggregator has a saved music on any server, the music tables must be joined.
+/// aggregators without one can't be shown, they are skipped in sql so pages stay full
+fn has_music_condition() -> Condition {
+    // todo: add more music server
+    Condition::any()
+        .add(Expr::col((Alias::new("kuwo_music"), Alias::new("music_id"))).is_not_null())
+        .add(Expr::col((Alias::new("netease_music"), Alias::new("music_id"))).is_not_null())
+}
+
+/// name of the first artist in the json `artists` column
+fn first_artist_expr(backend: DatabaseBackend, table: &str) -> SimpleExpr {
+    Expr::cust(match backend {
+        DatabaseBackend::Sqlite => format!("json_extract(\"{table}\".\"artists\", '$[0].name')"),
+        DatabaseBackend::Postgres => format!("(\"{table}\".\"artists\" -> 0 ->> 'name')"),
+        DatabaseBackend::MySql => {
+            format!("JSON_UNQUOTE(JSON_EXTRACT(`{table}`.`artists`, '$[0].name'))")
+        }
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortKey {
+    Name,
+    /// the first artist
+    Artist,
+    Album,
+    Duration,
+    /// when the music was added to the playlist
+    AddedTime,
+    DefaultServer,
+}
+
+impl SortKey {
+    fn expr(&self, backend: DatabaseBackend) -> SimpleExpr {
+        match self {
+            SortKey::Name => {
+                Func::lower(default_music_expr(|t| music_column_expr(t, "name"))).into()
+            }
+            SortKey::Artist => {
+                Func::lower(default_music_expr(|t| first_artist_expr(backend, t))).into()
+            }
+            SortKey::Album => {
+                Func::lower(default_music_expr(|t| music_column_expr(t, "album"))).into()
+            }
+            SortKey::Duration => default_music_expr(|t| music_column_expr(t, "duration")),
+            SortKey::AddedTime => Expr::col((
+                playlist_music_junction::Entity,
+                playlist_music_junction::Column::CreatedAt,
+            ))
+            .into(),
+            SortKey::DefaultServer => Expr::col((
+                music_aggregator::Entity,
+                music_aggregator::Column::DefaultServer,
+            ))
+            .into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+impl From<SortDirection> for Order {
+    fn from(value: SortDirection) -> Self {
+        match value {
+            SortDirection::Asc => Order::Asc,
+            SortDirection::Desc => Order::Desc,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GroupKey {
+    /// the first artist
+    Artist,
+    Album,
+}
+
+impl GroupKey {
+    fn expr(&self, backend: DatabaseBackend) -> SimpleExpr {
+        match self {
+            GroupKey::Artist => default_music_expr(|t| first_artist_expr(backend, t)),
+            GroupKey::Album => default_music_expr(|t| music_column_expr(t, "album")),
+        }
+    }
+}
+
+/// One artist or album of a playlist, see `Playlist::get_music_groups_from_db`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlaylistMusicGroup {
+    /// `None` for music without an artist or album
+    pub name: Option<String>,
+    pub count: i64,
+    /// total duration of the group
+    pub duration: Option<i64>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -44,6 +357,14 @@ pub struct Playlist {
     pub play_time: Option<i64>,
     pub music_num: Option<i64>,
     pub subscription: Option<Vec<PlayListSubscription>>,
+    /// unix time in milliseconds, only db playlists have them
+    #[serde(default)]
+    pub created_at: Option<i64>,
+    #[serde(default)]
+    pub updated_at: Option<i64>,
+    /// rules of a smart playlist, its musics come from the rules instead of being added
+    #[serde(default)]
+    pub smart_rules: Option<SmartPlaylistRules>,
 }
 
 impl From<playlist::Model> for Playlist {
@@ -51,7 +372,10 @@ impl From<playlist::Model> for Playlist {
         Self {
             from_db: true,
             server: None,
-            type_field: PlaylistType::UserPlaylist,
+            type_field: match value.system_type {
+                Some(SystemPlaylistType::Liked) => PlaylistType::Liked,
+                None => PlaylistType::UserPlaylist,
+            },
             identity: value.id.to_string(),
             name: value.name,
             summary: value.summary,
@@ -63,6 +387,9 @@ impl From<playlist::Model> for Playlist {
             subscription: value.subscriptions.and_then(|s| Some(s.0)),
             order: Some(value.order),
             collection_id: Some(value.collection_id),
+            created_at: Some(value.created_at),
+            updated_at: Some(value.updated_at),
+            smart_rules: value.smart_rules,
         }
     }
 }
@@ -89,13 +416,63 @@ impl Playlist {
             subscription: Some(subscriptions),
             order: None,
             collection_id: None,
+            created_at: None,
+            updated_at: None,
+            smart_rules: None,
+        }
+    }
+
+    /// a smart playlist, insert it with `insert_to_db`
+    pub fn new_smart(
+        name: String,
+        summary: Option<String>,
+        cover: Option<String>,
+        rules: SmartPlaylistRules,
+    ) -> Self {
+        Self {
+            smart_rules: Some(rules),
+            ..Self::new(name, summary, cover, vec![])
         }
     }
 
+    pub fn is_smart(&self) -> bool {
+        self.smart_rules.is_some()
+    }
+
+    /// replace the rules of a db playlist, `None` turns it back into a normal playlist.
+    /// the musics added before it became smart are kept and show up again.
+    pub async fn set_smart_rules(&mut self, rules: Option<SmartPlaylistRules>) -> Result<()> {
+        if !self.from_db {
+            return Err(anyhow::anyhow!("Can't set rules of non-database playlist."));
+        }
+        if self.type_field == PlaylistType::Liked {
+            return Err(anyhow::anyhow!("Can't set rules of a system playlist."));
+        }
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+        let id = self.identity.parse::<i64>()?;
+        let txn = db.begin().await?;
+        let journal = Journal::begin(&txn, JournalScope::playlist(id)).await?;
+        let active = playlist::ActiveModel {
+            id: Unchanged(id),
+            smart_rules: Set(rules.clone()),
+            updated_at: Set(now_millis()),
+            ..Default::default()
+        };
+        let model = playlist::Entity::update(active).exec(&txn).await?;
+        journal.commit(&txn, "Set smart playlist rules").await?;
+        txn.commit().await?;
+        self.smart_rules = rules;
+        self.updated_at = Some(model.updated_at);
+        Ok(())
+    }
+
     /// find db playlist by primary key `id`
     pub async fn find_in_db(id: i64) -> Option<Self> {
         let db = get_db().await.expect("Database is not inited.");
         let model: Option<playlist::Model> = playlist::Entity::find_by_id(id)
+            .filter(playlist::Column::DeletedAt.is_null())
             .one(&db)
             .await
             .expect("Failed to find playlist by id.");
@@ -125,8 +502,17 @@ impl Playlist {
                     .clone()
                     .and_then(|s| Some(PlayListSubscriptionVec(s)))),
                 collection_id: Set(self.collection_id.unwrap()),
+                created_at: NotSet,
+                updated_at: Set(now_millis()),
+                system_type: NotSet,
+                smart_rules: Set(self.smart_rules.clone()),
+                deleted_at: NotSet,
             };
-            let model = playlist::Entity::update(playlist).exec(&db).await?;
+            let txn = db.begin().await?;
+            let journal = Journal::begin(&txn, JournalScope::playlist(id)).await?;
+            let model = playlist::Entity::update(playlist).exec(&txn).await?;
+            journal.commit(&txn, "Update playlist").await?;
+            txn.commit().await?;
             Ok(model.into())
         } else {
             return Err(anyhow::anyhow!("Invalid playlist id of Database playlist."));
@@ -139,60 +525,247 @@ impl Playlist {
             .await
             .ok_or(anyhow::anyhow!("Database is not inited."))?;
 
+        let txn = db.begin().await?;
+        let journal = Journal::begin(&txn, JournalScope::default()).await?;
+        // the new playlist goes last in its collection
         let statement = Query::select()
             .expr(Func::max(Expr::col((
                 Alias::new("playlist"),
-                playlist::Column::Id,
+                playlist::Column::Order,
             ))))
             .from(playlist::Entity)
+            .and_where(playlist::Column::CollectionId.eq(collection_id))
             .to_owned();
-        let query_result = db
+        let query_result = txn
             .query_one(sea_orm::StatementBuilder::build(
                 &statement,
                 &db.get_database_backend(),
             ))
             .await?
-            .ok_or(anyhow::anyhow!("Failed to get max id from playlist table."))?;
-        let max_id: i64 = query_result.try_get_by_index(0).ok().unwrap_or(0);
-        let playlist = playlist::ActiveModel::new(
+            .ok_or(anyhow::anyhow!("Failed to get max order from playlist table."))?;
+        let max_order: i64 = query_result.try_get_by_index(0).ok().unwrap_or(0);
+        let mut playlist = playlist::ActiveModel::new(
             self.name.clone(),
             self.summary.clone(),
             self.cover.clone(),
-            max_id + 1,
+            max_order + 1,
             collection_id,
             self.subscription
                 .clone()
                 .and_then(|s| Some(PlayListSubscriptionVec(s))),
         );
+        playlist.smart_rules = Set(self.smart_rules.clone());
 
-        let result = playlist::Entity::insert(playlist).exec(&db).await?;
+        let result = playlist::Entity::insert(playlist).exec(&txn).await?;
         let last_id = result.last_insert_id;
+        journal
+            .created(JournalScope::playlist(last_id))
+            .commit(&txn, "Create playlist")
+            .await?;
+        txn.commit().await?;
         Ok(last_id)
     }
 
-    /// delete a playlist from db
-    /// this will also delete all junctions between the playlist and music
+    /// move a db playlist to the trash, its music stays with it until the trash is purged.
+    /// see `Trash` for restoring it.
     pub async fn del_from_db(self) -> Result<()> {
         if !self.from_db {
             return Err(anyhow::anyhow!(
                 "Can't delete playlist from non-database server."
             ));
         }
+        if self.type_field == PlaylistType::Liked {
+            return Err(anyhow::anyhow!("Can't delete a system playlist."));
+        }
         let db = get_db()
             .await
             .ok_or(anyhow::anyhow!("Database is not inited."))?;
-        playlist::Entity::delete_by_id(self.identity.parse::<i64>()?)
-            .exec(&db)
+        let id = self.identity.parse::<i64>()?;
+        if let Some(model) = playlist::Entity::find_by_id(id).one(&db).await? {
+            if model.system_type.is_some() {
+                return Err(anyhow::anyhow!("Can't delete a system playlist."));
+            }
+        }
+        let txn = db.begin().await?;
+        let journal = Journal::begin(&txn, JournalScope::playlist(id)).await?;
+        playlist::Entity::update_many()
+            .col_expr(playlist::Column::DeletedAt, Expr::value(now_millis()))
+            .filter(playlist::Column::Id.eq(id))
+            .filter(playlist::Column::DeletedAt.is_null())
+            .exec(&txn)
             .await?;
+        journal.commit(&txn, "Delete playlist").await?;
+        txn.commit().await?;
         Ok(())
     }
 
-    /// get playlists from db
+    /// move a db playlist to position `position` of another collection, or of its own one.
+    /// both collections are renumbered to `1..=n`.
+    pub async fn move_to_collection(&mut self, collection_id: i64, position: usize) -> Result<()> {
+        if !self.from_db {
+            return Err(anyhow::anyhow!("Can't move non-database playlist."));
+        }
+        let id = self.identity.parse::<i64>()?;
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+
+        let txn = db.begin().await?;
+        let model = playlist::Entity::find_by_id(id)
+            .filter(playlist::Column::DeletedAt.is_null())
+            .one(&txn)
+            .await?
+            .ok_or(anyhow::anyhow!("Playlist not found in db."))?;
+        playlist_collection::Entity::find_by_id(collection_id)
+            .filter(playlist_collection::Column::DeletedAt.is_null())
+            .one(&txn)
+            .await?
+            .ok_or(anyhow::anyhow!("PlaylistCollection not found."))?;
+        let source_id = model.collection_id;
+        let journal = Journal::begin(
+            &txn,
+            JournalScope::playlist(id)
+                .and(JournalScope::collection(source_id))
+                .and(JournalScope::collection(collection_id)),
+        )
+        .await?;
+
+        let mut playlists = PlaylistCollection::get_playlist_models(&txn, collection_id).await?;
+        playlists.retain(|playlist| playlist.id != id);
+        if position > playlists.len() {
+            return Err(anyhow::anyhow!(
+                "Can't move playlist to {}, there are only {} playlists.",
+                position,
+                playlists.len()
+            ));
+        }
+        if source_id != collection_id {
+            playlist::Entity::update_many()
+                .col_expr(playlist::Column::CollectionId, Expr::value(collection_id))
+                .col_expr(playlist::Column::UpdatedAt, Expr::value(now_millis()))
+                .filter(playlist::Column::Id.eq(id))
+                .exec(&txn)
+                .await?;
+            let source = PlaylistCollection::get_playlist_models(&txn, source_id).await?;
+            PlaylistCollection::save_playlist_order(&txn, source).await?;
+        }
+        playlists.insert(position, model);
+        PlaylistCollection::save_playlist_order(&txn, playlists).await?;
+        journal.commit(&txn, "Move playlist").await?;
+        txn.commit().await?;
+
+        self.collection_id = Some(collection_id);
+        self.order = Some(position as i64 + 1);
+        Ok(())
+    }
+
+    /// get the built-in "Liked songs" playlist, it is created on first use.
+    /// a new one is put into the first playlist collection, a collection is created if there is none.
+    pub async fn get_or_create_liked_in_db() -> Result<Self> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+        if let Some(model) = Self::find_liked_model(&db).await? {
+            return Ok(model.into());
+        }
+
+        let txn = db.begin().await?;
+        match Self::create_liked_model(&txn).await {
+            Ok(model) => {
+                txn.commit().await?;
+                Ok(model.into())
+            }
+            Err(e) => {
+                // someone else created it first, the unique index rejected ours
+                txn.rollback().await?;
+                Self::find_liked_model(&db)
+                    .await?
+                    .map(|m| m.into())
+                    .ok_or(e)
+            }
+        }
+    }
+
+    /// insert the liked playlist with `db`, the caller made sure there is none yet.
+    /// it goes into the first playlist collection, a collection is created if there is none.
+    pub(crate) async fn create_liked_model<C: ConnectionTrait>(db: &C) -> Result<playlist::Model> {
+        let collection_id = match playlist_collection::Entity::find()
+            .filter(playlist_collection::Column::DeletedAt.is_null())
+            .order_by_asc(playlist_collection::Column::Order)
+            .one(db)
+            .await?
+        {
+            Some(collection) => collection.id,
+            None => {
+                let collection = playlist_collection::ActiveModel {
+                    id: NotSet,
+                    order: Set(1),
+                    name: Set("Default".to_string()),
+                    created_at: Set(now_millis()),
+                    updated_at: Set(now_millis()),
+                    deleted_at: Set(None),
+                };
+                playlist_collection::Entity::insert(collection)
+                    .exec(db)
+                    .await?
+                    .last_insert_id
+            }
+        };
+        // keep the liked playlist in front of the user playlists
+        let min_order = playlist::Entity::find()
+            .filter(playlist::Column::CollectionId.eq(collection_id))
+            .order_by_asc(playlist::Column::Order)
+            .one(db)
+            .await?
+            .map(|m| m.order)
+            .unwrap_or(1);
+        let mut active = playlist::ActiveModel::new(
+            "Liked songs".to_string(),
+            None,
+            None,
+            min_order - 1,
+            collection_id,
+            None,
+        );
+        active.system_type = Set(Some(SystemPlaylistType::Liked));
+
+        let id = playlist::Entity::insert(active)
+            .exec(db)
+            .await?
+            .last_insert_id;
+        playlist::Entity::find_by_id(id)
+            .one(db)
+            .await?
+            .ok_or(anyhow::anyhow!("Failed to create liked playlist."))
+    }
+
+    pub(crate) async fn find_liked_model<C: ConnectionTrait>(
+        db: &C,
+    ) -> Result<Option<playlist::Model>> {
+        Ok(playlist::Entity::find()
+            .filter(playlist::Column::SystemType.eq(SystemPlaylistType::Liked))
+            .one(db)
+            .await?)
+    }
+
+    /// get playlists from db, the ones in the trash or in a trashed collection are left out
     pub async fn get_from_db() -> Result<Vec<Self>> {
         let db = get_db()
             .await
             .ok_or(anyhow::anyhow!("Database is not inited."))?;
-        let models = playlist::Entity::find().all(&db).await?;
+        let models = playlist::Entity::find()
+            .filter(playlist::Column::DeletedAt.is_null())
+            .filter(
+                playlist::Column::CollectionId.not_in_subquery(
+                    Query::select()
+                        .column(playlist_collection::Column::Id)
+                        .from(playlist_collection::Entity)
+                        .and_where(playlist_collection::Column::DeletedAt.is_not_null())
+                        .to_owned(),
+                ),
+            )
+            .all(&db)
+            .await?;
         let mut playlists = models.into_iter().map(|m| m.into()).collect::<Vec<Self>>();
         playlists.sort_by(|a, b| {
             a.order
@@ -204,123 +777,251 @@ impl Playlist {
     }
 
     /// add playlist music aggregator junction to db
-    /// this will also add the music and music aggregators to the db
-    pub async fn add_aggs_to_db(&self, music_aggs: &Vec<MusicAggregator>) -> Result<()> {
+    /// this will also add the music and music aggregators to the db.
+    /// everything is written in one transaction with multi-row inserts, so a failure leaves nothing behind.
+    pub async fn add_aggs_to_db(
+        &self,
+        music_aggs: &Vec<MusicAggregator>,
+    ) -> Result<PlaylistAddMusicAggsResult> {
         if !self.from_db {
             return Err(anyhow::anyhow!(
                 "Can't add music aggregators to non-database playlist"
             ));
         }
+        if self.is_smart() {
+            return Err(anyhow::anyhow!(
+                "Can't add music aggregators to smart playlist"
+            ));
+        }
 
+        let playlist_id = self.identity.parse::<i64>()?;
         let db = get_db()
             .await
             .ok_or(anyhow::anyhow!("Database is not inited."))?;
-        let count =
-            playlist_music_junction::Entity::find()
-                .filter(Condition::all().add(
-                    playlist_music_junction::Column::PlaylistId.eq(self.identity.parse::<i64>()?),
-                ))
-                .count(&db)
-                .await?;
+        let txn = db.begin().await?;
+        let mut result = PlaylistAddMusicAggsResult::default();
 
-        let mut order = count as i64;
-        for music_agg in music_aggs {
-            match music_agg.save_to_db().await {
-                Ok(duplicate) => {
-                    let junction = playlist_music_junction::ActiveModel::new(
-                        self.identity.parse::<i64>()?,
-                        duplicate.unwrap_or(music_agg.identity()),
-                        order,
-                    );
-                    order += 1;
+        // load every saved music aggregator the new ones may collide with
+        let mut saved_aggs = SavedAggs::default();
+        saved_aggs.load(&txn, music_aggs).await?;
+
+        let junctions = playlist_music_junction::Entity::find()
+            .filter(playlist_music_junction::Column::PlaylistId.eq(playlist_id))
+            .all(&txn)
+            .await?;
+        let mut order = junctions
+            .iter()
+            .map(|junction| junction.order)
+            .max()
+            .map_or(0, |max| max + 1);
+        // music in the trash is restored when it is added again
+        let mut in_playlist: HashSet<String> = HashSet::new();
+        let mut trashed: HashSet<String> = HashSet::new();
+        for junction in junctions {
+            match junction.deleted_at {
+                Some(_) => trashed.insert(junction.music_aggregator_id),
+                None => in_playlist.insert(junction.music_aggregator_id),
+            };
+        }
+        let mut restored_junctions = Vec::new();
 
-                    match playlist_music_junction::Entity::insert(junction)
-                        .exec_without_returning(&db)
-                        .await
+        let mut new_identities: Vec<String> = Vec::new();
+        let mut updated_identities: Vec<String> = Vec::new();
+        let mut kuwo_musics: Vec<kuwo::model::Model> = Vec::new();
+        let mut netease_musics: Vec<netease::model::Model> = Vec::new();
+        let mut seen_musics: HashSet<(MusicServer, String)> = HashSet::new();
+        let mut new_junctions = Vec::new();
+        let mut touched_junctions: Vec<String> = Vec::new();
+
+        for music_agg in music_aggs {
+            let identity = music_agg.identity();
+            let target = match saved_aggs.match_agg(music_agg) {
+                AggMatch::Saved { identity, updated } => {
+                    if updated
+                        && !new_identities.contains(&identity)
+                        && !updated_identities.contains(&identity)
                     {
-                        Ok(_) => {}
-                        Err(e) => {
-                            if let sea_orm::DbErr::Exec(sea_orm::RuntimeErr::SqlxError(
-                                sea_orm::SqlxError::Database(database_error),
-                            )) = &e
-                            {
-                                let database_error_str = database_error.to_string();
-                                // Sqlite Unique error
-                                if database_error_str.contains("UNIQUE")
-                                    // MySql Unique error
-                                    ||database_error_str.contains("1062")
-                                    // PgSql Unique error
-                                    || database_error_str.contains("duplicate")
-                                {
-                                    continue;
-                                } else if database_error_str.contains("1452")
-                                    || database_error_str.contains("violates foreign key")
-                                {
-                                    // 因为某些平台的 不同名称的歌曲公用一个id, 所以可能会出现重复
-                                    // 因此导致名称不同，但是内容相同的MusicAggregator插入失败
-                                    // 此时应该根据id查找到已有的MusicAggregator，然后插入junction
-                                    if let Some(found_music_agg_id) =
-                                        find_duplicate_music_agg(&db, music_agg).await
-                                    {
-                                        let junction = playlist_music_junction::ActiveModel::new(
-                                            self.identity.parse::<i64>()?,
-                                            found_music_agg_id,
-                                            order,
-                                        );
-                                        order += 1;
-                                        if let Err(e) =
-                                            playlist_music_junction::Entity::insert(junction)
-                                                .exec_without_returning(&db)
-                                                .await
-                                        {
-                                            log::error!(
-                                                "Failed to try fix save playlist music junction for music agg: [{}]({}) and playlist: [{}]({}), error: ({})",
-                                                music_agg.identity(),
-                                                music_agg.name,
-                                                self.identity,
-                                                self.name,
-                                                e.to_string()
-                                            );
-                                        } else {
-                                            continue;
-                                        }
-                                    } else {
-                                        log::error!(
-                                            "Failed to try fix save playlist music junction for music agg: [{}]({}) and playlist: [{}]({}), error: Can't find the depulicate music agg.",
-                                            music_agg.identity(),
-                                            music_agg.name,
-                                            self.identity,
-                                            self.name,
-                                        );
-                                        continue;
-                                    }
-                                }
-                            }
-                            log::error!(
-                                "Failed to save playlist music junction for music agg: [{}]({}) and playlist: [{}]({}), error: ({})",
-                                music_agg.identity(),
-                                music_agg.name,
-                                self.identity,
-                                self.name,
-                                e.to_string()
-                            );
-                        }
-                    };
+                        updated_identities.push(identity.clone());
+                    }
+                    identity
+                }
+                AggMatch::Reused(found) => {
+                    result.reused_music_aggs += 1;
+                    found
+                }
+                AggMatch::Created(identity) => {
+                    new_identities.push(identity.clone());
+                    identity
+                }
+                AggMatch::NoMusic => {
+                    result
+                        .errors
+                        .push((identity, "Music aggregator has no music".to_string()));
+                    continue;
+                }
+            };
+
+            // only musics owned by a music aggregator can be saved
+            for music in &music_agg.musics {
+                if !seen_musics.insert((music.server.clone(), music.identity.clone())) {
+                    continue;
+                }
+                // the music tables require a cover, such musics could never be saved
+                if music.cover.is_none() {
+                    result.errors.push((
+                        identity.clone(),
+                        format!(
+                            "{} music {} has no cover",
+                            music.server.to_string(),
+                            music.identity
+                        ),
+                    ));
+                    continue;
+                }
+                if !saved_aggs.owns(music) {
+                    continue;
+                }
+                match music.server {
+                    MusicServer::Kuwo => kuwo_musics.push(music.clone().into()),
+                    MusicServer::Netease => netease_musics.push(music.clone().into()),
                 }
-                Err(e) => log::error!(
-                    "Failed to save music agg: [{}]({}), error: {}",
-                    music_agg.identity(),
-                    music_agg.name,
-                    e.to_string()
-                ),
+            }
+
+            if in_playlist.insert(target.clone()) {
+                touched_junctions.push(target.clone());
+                if trashed.remove(&target) {
+                    restored_junctions.push((target, order));
+                } else {
+                    new_junctions.push(playlist_music_junction::ActiveModel::new(
+                        playlist_id,
+                        target,
+                        order,
+                    ));
+                }
+                order += 1;
+            } else {
+                result.skipped.push(identity);
             }
         }
 
-        Ok(())
+        // only the rows written below are journaled, reused music aggregators included
+        let journal = Journal::begin(
+            &txn,
+            JournalScope::music_agg_rows(
+                new_identities
+                    .iter()
+                    .chain(updated_identities.iter())
+                    .cloned()
+                    .collect(),
+            )
+            .and(JournalScope::junctions(playlist_id, touched_junctions)),
+        )
+        .await?;
+
+        for identity in &updated_identities {
+            let agg = &saved_aggs.aggs[identity];
+            let active = music_aggregator::ActiveModel {
+                identity: Unchanged(agg.identity.clone()),
+                default_server: NotSet,
+                kuwo_music_id: Set(agg.kuwo_music_id.clone()),
+                netease_music_id: Set(agg.netease_music_id.clone()),
+                created_at: NotSet,
+                updated_at: Set(now_millis()),
+            };
+            music_aggregator::Entity::update(active).exec(&txn).await?;
+            result.updated_music_aggs += 1;
+        }
+
+        let new_aggs: Vec<music_aggregator::ActiveModel> = new_identities
+            .iter()
+            .map(|identity| {
+                saved_aggs.aggs[identity]
+                    .clone()
+                    .into_active_model()
+                    .reset_all()
+            })
+            .collect();
+        for chunk in new_aggs.chunks(INSERT_CHUNK_SIZE) {
+            result.inserted_music_aggs += inserted_rows(
+                music_aggregator::Entity::insert_many(chunk.to_vec())
+                    .on_conflict_do_nothing()
+                    .exec_without_returning(&txn)
+                    .await?,
+            );
+        }
+
+        for chunk in kuwo_musics.chunks(INSERT_CHUNK_SIZE) {
+            result.inserted_musics += inserted_rows(
+                kuwo::model::Entity::insert_many(
+                    chunk
+                        .iter()
+                        .map(|m| m.clone().into_active_model().reset_all()),
+                )
+                .on_conflict_do_nothing()
+                .exec_without_returning(&txn)
+                .await?,
+            );
+        }
+        for chunk in netease_musics.chunks(INSERT_CHUNK_SIZE) {
+            result.inserted_musics += inserted_rows(
+                netease::model::Entity::insert_many(
+                    chunk
+                        .iter()
+                        .map(|m| m.clone().into_active_model().reset_all()),
+                )
+                .on_conflict_do_nothing()
+                .exec_without_returning(&txn)
+                .await?,
+            );
+        }
+
+        for chunk in new_junctions.chunks(INSERT_CHUNK_SIZE) {
+            result.inserted_junctions += inserted_rows(
+                playlist_music_junction::Entity::insert_many(chunk.to_vec())
+                    .on_conflict_do_nothing()
+                    .exec_without_returning(&txn)
+                    .await?,
+            );
+        }
+        for (identity, order) in restored_junctions {
+            playlist_music_junction::Entity::update_many()
+                .col_expr(playlist_music_junction::Column::Order, Expr::value(order))
+                .col_expr(
+                    playlist_music_junction::Column::DeletedAt,
+                    Expr::value(Option::<i64>::None),
+                )
+                .col_expr(
+                    playlist_music_junction::Column::CreatedAt,
+                    Expr::value(now_millis()),
+                )
+                .col_expr(
+                    playlist_music_junction::Column::UpdatedAt,
+                    Expr::value(now_millis()),
+                )
+                .filter(playlist_music_junction::Column::PlaylistId.eq(playlist_id))
+                .filter(playlist_music_junction::Column::MusicAggregatorId.eq(identity))
+                .exec(&txn)
+                .await?;
+            result.inserted_junctions += 1;
+        }
+
+        journal.commit(&txn, "Add music to playlist").await?;
+        txn.commit().await?;
+        Ok(result)
     }
 
     /// get all music aggregators from db
     pub async fn get_musics_from_db(&self) -> Result<Vec<MusicAggregator>> {
+        self.get_musics_from_db_paginated(0, None).await
+    }
+
+    /// get music aggregators from db in playlist order, skipping `offset` and returning at most `limit`.
+    /// this runs one join query for the page and one `IN` query per music server.
+    pub async fn get_musics_from_db_paginated(
+        &self,
+        offset: u64,
+        limit: Option<u64>,
+    ) -> Result<Vec<MusicAggregator>> {
         if !self.from_db {
             return Err(anyhow::anyhow!(
                 "Can't get music from non-database playlist"
@@ -330,28 +1031,422 @@ impl Playlist {
         let db = get_db()
             .await
             .ok_or(anyhow::anyhow!("Database is not inited."))?;
+        if let Some(rules) = self.smart_rules.as_ref() {
+            let mut select = rules
+                .select(db.get_database_backend())
+                .filter(has_music_condition());
+            if offset > 0 || limit.is_some() {
+                select = select
+                    .offset(offset)
+                    .limit(limit.unwrap_or(i64::MAX as u64));
+            }
+            let models = select
+                .all(&db)
+                .await?
+                .into_iter()
+                .enumerate()
+                .map(|(order, model)| (model, offset as i64 + order as i64))
+                .collect();
+            return music_aggregator::Model::get_music_aggregators(&db, models).await;
+        }
         let id = self.identity.parse::<i64>()?;
-        let junctions = playlist_music_junction::Entity::find()
-            .filter(
-                Condition::all().add(Expr::col(playlist_music_junction::Column::PlaylistId).eq(id)),
+        let mut query = playlist_music_junction::Entity::find()
+            .filter(playlist_music_junction::Column::PlaylistId.eq(id))
+            .filter(playlist_music_junction::Column::DeletedAt.is_null())
+            .find_also_related(music_aggregator::Entity)
+            // todo: add more music server
+            .join(
+                JoinType::LeftJoin,
+                music_aggregator::Relation::KuwoMusic.def(),
+            )
+            .join(
+                JoinType::LeftJoin,
+                music_aggregator::Relation::NeteaseMusicId.def(),
+            )
+            .filter(has_music_condition())
+            .order_by_asc(playlist_music_junction::Column::Order)
+            .order_by_asc(playlist_music_junction::Column::MusicAggregatorId);
+        if offset > 0 || limit.is_some() {
+            // sqlite and mysql don't accept an `OFFSET` without a `LIMIT`
+            query = query.offset(offset).limit(limit.unwrap_or(i64::MAX as u64));
+        }
+        let rows = query.all(&db).await?;
+        Self::load_junction_rows(&db, rows).await
+    }
+
+    /// build the music aggregators of `(junction, music aggregator)` rows, keeping their order.
+    /// the queries filter on `has_music_condition`, so every aggregator has a music
+    async fn load_junction_rows<C: ConnectionTrait>(
+        db: &C,
+        rows: Vec<(
+            playlist_music_junction::Model,
+            Option<music_aggregator::Model>,
+        )>,
+    ) -> Result<Vec<MusicAggregator>> {
+        let mut models = Vec::with_capacity(rows.len());
+        let mut added_at = Vec::with_capacity(rows.len());
+        for (junction, agg) in rows {
+            let agg = agg.ok_or(anyhow::anyhow!("Can't find music aggregator in db"))?;
+            models.push((agg, junction.order));
+            added_at.push(junction.created_at);
+        }
+
+        let mut aggs = music_aggregator::Model::get_music_aggregators(db, models).await?;
+        for (agg, added_at) in aggs.iter_mut().zip(added_at) {
+            agg.added_at = Some(added_at);
+        }
+        Ok(aggs)
+    }
+
+    /// get music aggregators from db sorted by `key`, the values of the default server are used.
+    /// ties keep the playlist order.
+    pub async fn get_musics_from_db_sorted(
+        &self,
+        key: SortKey,
+        direction: SortDirection,
+    ) -> Result<Vec<MusicAggregator>> {
+        if !self.from_db {
+            return Err(anyhow::anyhow!(
+                "Can't get music from non-database playlist"
+            ));
+        }
+
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+        // music added in one batch shares a timestamp, the playlist order follows the direction then
+        let tie_direction = match key {
+            SortKey::AddedTime => direction,
+            _ => SortDirection::Asc,
+        };
+        if let Some(rules) = self.smart_rules.as_ref() {
+            // a smart playlist has no junctions, its music is added when saved to the library
+            let key_expr = match key {
+                SortKey::AddedTime => Expr::col((
+                    music_aggregator::Entity,
+                    music_aggregator::Column::CreatedAt,
+                ))
+                .into(),
+                _ => key.expr(db.get_database_backend()),
+            };
+            let models = rules
+                .select_unordered(db.get_database_backend())
+                .filter(has_music_condition())
+                .order_by(key_expr, direction.into())
+                .order_by(music_aggregator::Column::CreatedAt, tie_direction.into())
+                .order_by_asc(music_aggregator::Column::Identity)
+                .all(&db)
+                .await?
+                .into_iter()
+                .enumerate()
+                .map(|(order, model)| (model, order as i64))
+                .collect();
+            return music_aggregator::Model::get_music_aggregators(&db, models).await;
+        }
+        let id = self.identity.parse::<i64>()?;
+        let rows = playlist_music_junction::Entity::find()
+            .filter(playlist_music_junction::Column::PlaylistId.eq(id))
+            .filter(playlist_music_junction::Column::DeletedAt.is_null())
+            .find_also_related(music_aggregator::Entity)
+            // todo: add more music server
+            .join(
+                JoinType::LeftJoin,
+                music_aggregator::Relation::KuwoMusic.def(),
+            )
+            .join(
+                JoinType::LeftJoin,
+                music_aggregator::Relation::NeteaseMusicId.def(),
             )
+            .filter(has_music_condition())
+            .order_by(key.expr(db.get_database_backend()), direction.into())
+            .order_by(playlist_music_junction::Column::Order, tie_direction.into())
+            .order_by_asc(playlist_music_junction::Column::MusicAggregatorId)
             .all(&db)
             .await?;
+        Self::load_junction_rows(&db, rows).await
+    }
 
-        let mut aggs = Vec::with_capacity(junctions.len());
-        for junction in junctions {
-            let agg = junction
-                .find_related(music_aggregator::Entity)
-                .one(&db)
+    /// group the music of a db playlist by artist or album, sorted by group name.
+    /// only the group summaries are loaded, see `get_musics_in_group_from_db` for the music.
+    pub async fn get_music_groups_from_db(&self, key: GroupKey) -> Result<Vec<PlaylistMusicGroup>> {
+        if !self.from_db {
+            return Err(anyhow::anyhow!(
+                "Can't get music from non-database playlist"
+            ));
+        }
+
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+        let backend = db.get_database_backend();
+        let id = self.identity.parse::<i64>()?;
+        let select = match self.smart_rules.as_ref() {
+            Some(rules) => rules.select_unordered(backend),
+            None => music_aggregator::Entity::find()
+                .join(
+                    JoinType::InnerJoin,
+                    music_aggregator::Relation::PlaylistMusicJunction.def(),
+                )
+                // todo: add more music server
+                .join(
+                    JoinType::LeftJoin,
+                    music_aggregator::Relation::KuwoMusic.def(),
+                )
+                .join(
+                    JoinType::LeftJoin,
+                    music_aggregator::Relation::NeteaseMusicId.def(),
+                )
+                .filter(playlist_music_junction::Column::PlaylistId.eq(id))
+                .filter(playlist_music_junction::Column::DeletedAt.is_null()),
+        };
+        let integer_type = match backend {
+            DatabaseBackend::MySql => "SIGNED",
+            _ => "BIGINT",
+        };
+        let group_name = Alias::new("group_name");
+        let rows: Vec<(Option<String>, i64, Option<i64>)> = select
+            .select_only()
+            .expr_as(key.expr(backend), "group_name")
+            .expr(Expr::col((music_aggregator::Entity, music_aggregator::Column::Identity)).count())
+            // postgres sums bigints to numeric
+            .expr(Func::cast_as(
+                Func::sum(default_music_expr(|t| music_column_expr(t, "duration"))),
+                Alias::new(integer_type),
+            ))
+            .filter(has_music_condition())
+            .group_by(Expr::col(group_name.clone()))
+            .order_by_asc(Expr::col(group_name))
+            .into_tuple()
+            .all(&db)
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(name, count, duration)| PlaylistMusicGroup {
+                name,
+                count,
+                duration,
+            })
+            .collect())
+    }
+
+    /// get the music aggregators of one group in playlist order, `None` is the group without name
+    pub async fn get_musics_in_group_from_db(
+        &self,
+        key: GroupKey,
+        name: Option<&str>,
+    ) -> Result<Vec<MusicAggregator>> {
+        if !self.from_db {
+            return Err(anyhow::anyhow!(
+                "Can't get music from non-database playlist"
+            ));
+        }
+
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+        let group_expr = Expr::expr(key.expr(db.get_database_backend()));
+        let group_condition = match name {
+            Some(name) => group_expr.eq(name),
+            None => group_expr.is_null(),
+        };
+        if let Some(rules) = self.smart_rules.as_ref() {
+            let models = rules
+                .select(db.get_database_backend())
+                .filter(has_music_condition())
+                .filter(group_condition)
+                .all(&db)
                 .await?
-                .ok_or(anyhow::anyhow!("Can't find music aggregator in db"))?;
-            let agg = agg.get_music_aggregator(&db, junction.order).await?;
-            if !agg.musics.is_empty() {
-                aggs.push(agg);
-            }
+                .into_iter()
+                .enumerate()
+                .map(|(order, model)| (model, order as i64))
+                .collect();
+            return music_aggregator::Model::get_music_aggregators(&db, models).await;
         }
-        aggs.sort_by(|a, b| a.order.cmp(&b.order));
-        Ok(aggs)
+        let id = self.identity.parse::<i64>()?;
+        let rows = playlist_music_junction::Entity::find()
+            .filter(playlist_music_junction::Column::PlaylistId.eq(id))
+            .filter(playlist_music_junction::Column::DeletedAt.is_null())
+            .find_also_related(music_aggregator::Entity)
+            // todo: add more music server
+            .join(
+                JoinType::LeftJoin,
+                music_aggregator::Relation::KuwoMusic.def(),
+            )
+            .join(
+                JoinType::LeftJoin,
+                music_aggregator::Relation::NeteaseMusicId.def(),
+            )
+            .filter(has_music_condition())
+            .filter(group_condition)
+            .order_by_asc(playlist_music_junction::Column::Order)
+            .order_by_asc(playlist_music_junction::Column::MusicAggregatorId)
+            .all(&db)
+            .await?;
+        Self::load_junction_rows(&db, rows).await
+    }
+
+    /// count the music aggregators in a db playlist
+    pub async fn count_musics_in_db(&self) -> Result<u64> {
+        if !self.from_db {
+            return Err(anyhow::anyhow!(
+                "Can't count music of non-database playlist"
+            ));
+        }
+
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+        if let Some(rules) = self.smart_rules.as_ref() {
+            return Ok(rules
+                .select(db.get_database_backend())
+                .filter(has_music_condition())
+                .count(&db)
+                .await?);
+        }
+        Ok(playlist_music_junction::Entity::find()
+            .join(
+                JoinType::InnerJoin,
+                playlist_music_junction::Relation::MusicAggregator.def(),
+            )
+            // todo: add more music server
+            .join(
+                JoinType::LeftJoin,
+                music_aggregator::Relation::KuwoMusic.def(),
+            )
+            .join(
+                JoinType::LeftJoin,
+                music_aggregator::Relation::NeteaseMusicId.def(),
+            )
+            .filter(playlist_music_junction::Column::PlaylistId.eq(self.identity.parse::<i64>()?))
+            .filter(playlist_music_junction::Column::DeletedAt.is_null())
+            .filter(has_music_condition())
+            .count(&db)
+            .await?)
+    }
+
+    /// junctions of a db playlist in playlist order, without the ones in the trash
+    async fn get_junctions<C: ConnectionTrait>(
+        db: &C,
+        playlist_id: i64,
+    ) -> Result<Vec<playlist_music_junction::Model>> {
+        Ok(playlist_music_junction::Entity::find()
+            .filter(playlist_music_junction::Column::PlaylistId.eq(playlist_id))
+            .filter(playlist_music_junction::Column::DeletedAt.is_null())
+            .order_by_asc(playlist_music_junction::Column::Order)
+            .order_by_asc(playlist_music_junction::Column::MusicAggregatorId)
+            .all(db)
+            .await?)
+    }
+
+    /// number the junctions `0..n` in the given order, only changed rows are written
+    /// and journaled with `label`
+    async fn save_junction_order<C: ConnectionTrait>(
+        db: &C,
+        playlist_id: i64,
+        junctions: Vec<playlist_music_junction::Model>,
+        label: &str,
+    ) -> Result<()> {
+        let changed: Vec<(String, i64)> = junctions
+            .into_iter()
+            .enumerate()
+            .filter(|(order, junction)| junction.order != *order as i64)
+            .map(|(order, junction)| (junction.music_aggregator_id, order as i64))
+            .collect();
+        let journal = Journal::begin(
+            db,
+            JournalScope::junctions(
+                playlist_id,
+                changed
+                    .iter()
+                    .map(|(identity, _)| identity.clone())
+                    .collect(),
+            ),
+        )
+        .await?;
+        for (identity, order) in changed {
+            playlist_music_junction::Entity::update_many()
+                .col_expr(playlist_music_junction::Column::Order, Expr::value(order))
+                .col_expr(
+                    playlist_music_junction::Column::UpdatedAt,
+                    Expr::value(now_millis()),
+                )
+                .filter(playlist_music_junction::Column::PlaylistId.eq(playlist_id))
+                .filter(playlist_music_junction::Column::MusicAggregatorId.eq(identity))
+                .exec(db)
+                .await?;
+        }
+        journal.commit(db, label).await
+    }
+
+    /// move the music aggregator at position `from` to position `to` in a db playlist
+    pub async fn move_item(&self, from: usize, to: usize) -> Result<()> {
+        if !self.from_db {
+            return Err(anyhow::anyhow!(
+                "Can't reorder music of non-database playlist"
+            ));
+        }
+        if self.is_smart() {
+            return Err(anyhow::anyhow!("Can't reorder music of smart playlist"));
+        }
+        let playlist_id = self.identity.parse::<i64>()?;
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+
+        let txn = db.begin().await?;
+        let mut junctions = Self::get_junctions(&txn, playlist_id).await?;
+        move_item(&mut junctions, from, to)?;
+        Self::save_junction_order(&txn, playlist_id, junctions, "Move music in playlist").await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// reorder a db playlist, `identities` must list every music aggregator of the playlist once
+    pub async fn reorder(&self, identities: &[String]) -> Result<()> {
+        if !self.from_db {
+            return Err(anyhow::anyhow!(
+                "Can't reorder music of non-database playlist"
+            ));
+        }
+        if self.is_smart() {
+            return Err(anyhow::anyhow!("Can't reorder music of smart playlist"));
+        }
+        let playlist_id = self.identity.parse::<i64>()?;
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+
+        let txn = db.begin().await?;
+        let junctions = Self::get_junctions(&txn, playlist_id).await?;
+        let junctions = reorder_by_keys(junctions, identities, |junction| {
+            junction.music_aggregator_id.clone()
+        })?;
+        Self::save_junction_order(&txn, playlist_id, junctions, "Reorder playlist").await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// renumber the music of a db playlist to `0..n`, removing gaps and duplicate orders
+    pub async fn compact_order(&self) -> Result<()> {
+        if !self.from_db {
+            return Err(anyhow::anyhow!(
+                "Can't reorder music of non-database playlist"
+            ));
+        }
+        if self.is_smart() {
+            return Err(anyhow::anyhow!("Can't reorder music of smart playlist"));
+        }
+        let playlist_id = self.identity.parse::<i64>()?;
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+
+        let txn = db.begin().await?;
+        let junctions = Self::get_junctions(&txn, playlist_id).await?;
+        Self::save_junction_order(&txn, playlist_id, junctions, "Compact playlist order").await?;
+        txn.commit().await?;
+        Ok(())
     }
 
     pub async fn update_subscription(&self) -> Result<PlaylistUpdateSubscriptionResult> {
@@ -409,18 +1504,30 @@ impl Playlist {
         Ok(result)
     }
 
+    /// move a music aggregator of this playlist to the trash
     pub async fn del_music_agg(&self, music_agg_identity: String) -> anyhow::Result<()> {
         let db = get_db()
             .await
             .ok_or(anyhow::anyhow!("Database is not inited."))?;
-        let active = playlist_music_junction::ActiveModel {
-            playlist_id: Set(self.identity.parse()?),
-            music_aggregator_id: Set(music_agg_identity),
-            order: NotSet,
-        };
-        playlist_music_junction::Entity::delete(active)
-            .exec(&db)
+        let playlist_id = self.identity.parse::<i64>()?;
+        let txn = db.begin().await?;
+        let journal = Journal::begin(
+            &txn,
+            JournalScope::junctions(playlist_id, vec![music_agg_identity.clone()]),
+        )
+        .await?;
+        playlist_music_junction::Entity::update_many()
+            .col_expr(
+                playlist_music_junction::Column::DeletedAt,
+                Expr::value(now_millis()),
+            )
+            .filter(playlist_music_junction::Column::PlaylistId.eq(playlist_id))
+            .filter(playlist_music_junction::Column::MusicAggregatorId.eq(music_agg_identity))
+            .filter(playlist_music_junction::Column::DeletedAt.is_null())
+            .exec(&txn)
             .await?;
+        journal.commit(&txn, "Remove music from playlist").await?;
+        txn.commit().await?;
         Ok(())
     }
 }
@@ -432,7 +1539,9 @@ mod test_playlist {
 
     use crate::{
         data::migrations::Migrator,
-        interface::{database::set_db, playlist_collection::PlaylistCollection},
+        interface::{
+            database::set_db, music_aggregator::music_agg, playlist_collection::PlaylistCollection,
+        },
     };
 
     use super::*;
@@ -440,14 +1549,15 @@ mod test_playlist {
         // 初始化log
         let _ = tracing_subscriber::fmt::try_init();
 
-        let db_file = "./sample_data/test.db";
-        let path = std::path::Path::new(db_file);
+        let path = std::env::temp_dir().join("music_api_test.db");
         if path.exists() {
-            std::fs::remove_file(path).unwrap();
+            std::fs::remove_file(&path).unwrap();
         }
-        std::fs::File::create(path).unwrap();
+        std::fs::File::create(&path).unwrap();
 
-        set_db(&("sqlite://".to_owned() + db_file)).await.unwrap();
+        set_db(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
         Migrator::up(&get_db().await.unwrap(), None).await.unwrap();
     }
 
@@ -512,6 +1622,110 @@ mod test_playlist {
         assert!(Playlist::get_from_db().await.unwrap().len() == 0);
     }
 
+    #[tokio::test]
+    #[serial]
+    async fn test_add_aggs_to_db_batched() {
+        set_db("sqlite::memory:").await.unwrap();
+        let collection_id = PlaylistCollection::new("test".to_string())
+            .insert_to_db()
+            .await
+            .unwrap();
+        let playlist_id = Playlist::new("test".to_string(), None, None, vec![])
+            .insert_to_db(collection_id)
+            .await
+            .unwrap();
+        let playlist = Playlist::find_in_db(playlist_id).await.unwrap();
+
+        let mut both = music_agg(MusicServer::Kuwo, "k1", "song1");
+        both.musics
+            .extend(music_agg(MusicServer::Netease, "n1", "song1").musics);
+        let aggs = vec![
+            both,
+            music_agg(MusicServer::Kuwo, "k2", "song2"),
+            // same kuwo id under another name, the saved song2 is reused
+            music_agg(MusicServer::Kuwo, "k2", "song2 alias"),
+            music_agg(MusicServer::Netease, "n3", "song3"),
+        ];
+        let result = playlist.add_aggs_to_db(&aggs).await.unwrap();
+        assert_eq!(result.inserted_music_aggs, 3);
+        assert_eq!(result.reused_music_aggs, 1);
+        assert_eq!(result.inserted_musics, 4);
+        assert_eq!(result.inserted_junctions, 3);
+        assert_eq!(result.skipped, vec!["song2 alias#+#artist".to_string()]);
+
+        // adding again only fills the missing server of song3
+        let mut song3 = music_agg(MusicServer::Netease, "n3", "song3");
+        song3
+            .musics
+            .extend(music_agg(MusicServer::Kuwo, "k3", "song3").musics);
+        let result = playlist.add_aggs_to_db(&vec![song3]).await.unwrap();
+        assert_eq!(result.inserted_music_aggs, 0);
+        assert_eq!(result.updated_music_aggs, 1);
+        assert_eq!(result.inserted_musics, 1);
+        assert_eq!(result.inserted_junctions, 0);
+
+        let musics = playlist.get_musics_from_db().await.unwrap();
+        assert_eq!(
+            musics.iter().map(|m| m.name.as_str()).collect::<Vec<_>>(),
+            vec!["song1", "song2", "song3"]
+        );
+        assert_eq!(musics[2].musics.len(), 2);
+        assert_eq!(musics[2].order, Some(2));
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_get_musics_from_db_paginated() {
+        set_db("sqlite::memory:").await.unwrap();
+        let collection_id = PlaylistCollection::new("test".to_string())
+            .insert_to_db()
+            .await
+            .unwrap();
+        let playlist_id = Playlist::new("test".to_string(), None, None, vec![])
+            .insert_to_db(collection_id)
+            .await
+            .unwrap();
+        let playlist = Playlist::find_in_db(playlist_id).await.unwrap();
+        playlist
+            .add_aggs_to_db(&vec![
+                music_agg(MusicServer::Kuwo, "k1", "song1"),
+                music_agg(MusicServer::Kuwo, "k2", "song2"),
+                music_agg(MusicServer::Netease, "n3", "song3"),
+            ])
+            .await
+            .unwrap();
+
+        let page = playlist
+            .get_musics_from_db_paginated(1, Some(1))
+            .await
+            .unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].name, "song2");
+        assert_eq!(
+            playlist
+                .get_musics_from_db_paginated(2, None)
+                .await
+                .unwrap()[0]
+                .name,
+            "song3"
+        );
+        assert_eq!(playlist.count_musics_in_db().await.unwrap(), 3);
+
+        // song2 loses its only music, it is skipped before the page is cut
+        kuwo::model::Entity::delete_by_id("k2")
+            .exec(&get_db().await.unwrap())
+            .await
+            .unwrap();
+        let page = playlist
+            .get_musics_from_db_paginated(1, Some(1))
+            .await
+            .unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].name, "song3");
+        assert_eq!(playlist.count_musics_in_db().await.unwrap(), 2);
+        assert_eq!(playlist.get_musics_from_db().await.unwrap().len(), 2);
+    }
+
     #[tokio::test]
     #[serial]
     async fn test_add_aggs_to_db1() {
@@ -668,4 +1882,445 @@ mod test_playlist {
             .await
             .unwrap();
     }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_reorder() {
+        set_db("sqlite::memory:").await.unwrap();
+        let collection_id = PlaylistCollection::new("test".to_string())
+            .insert_to_db()
+            .await
+            .unwrap();
+        let playlist_id = Playlist::new("test".to_string(), None, None, vec![])
+            .insert_to_db(collection_id)
+            .await
+            .unwrap();
+        let playlist = Playlist::find_in_db(playlist_id).await.unwrap();
+        playlist
+            .add_aggs_to_db(&vec![
+                music_agg(MusicServer::Kuwo, "k1", "song1"),
+                music_agg(MusicServer::Kuwo, "k2", "song2"),
+                music_agg(MusicServer::Kuwo, "k3", "song3"),
+                music_agg(MusicServer::Kuwo, "k4", "song4"),
+            ])
+            .await
+            .unwrap();
+        let names = |aggs: Vec<MusicAggregator>| {
+            aggs.into_iter()
+                .map(|agg| (agg.name, agg.order.unwrap()))
+                .collect::<Vec<_>>()
+        };
+
+        playlist.move_item(3, 0).await.unwrap();
+        assert_eq!(
+            names(playlist.get_musics_from_db().await.unwrap()),
+            vec![
+                ("song4".to_string(), 0),
+                ("song1".to_string(), 1),
+                ("song2".to_string(), 2),
+                ("song3".to_string(), 3)
+            ]
+        );
+        assert!(playlist.move_item(0, 4).await.is_err());
+
+        // deleting leaves a gap, compacting removes it
+        let identities = playlist
+            .get_musics_from_db()
+            .await
+            .unwrap()
+            .iter()
+            .map(|agg| agg.identity())
+            .collect::<Vec<_>>();
+        playlist.del_music_agg(identities[1].clone()).await.unwrap();
+        playlist.compact_order().await.unwrap();
+        let orders = names(playlist.get_musics_from_db().await.unwrap())
+            .into_iter()
+            .map(|(_, order)| order)
+            .collect::<Vec<_>>();
+        assert_eq!(orders, vec![0, 1, 2]);
+
+        playlist
+            .reorder(&[
+                identities[3].clone(),
+                identities[2].clone(),
+                identities[0].clone(),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(
+            names(playlist.get_musics_from_db().await.unwrap())
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>(),
+            vec!["song3", "song2", "song4"]
+        );
+        assert!(playlist.reorder(&[identities[3].clone()]).await.is_err());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_sort_and_group() {
+        set_db("sqlite::memory:").await.unwrap();
+        let collection_id = PlaylistCollection::new("test".to_string())
+            .insert_to_db()
+            .await
+            .unwrap();
+        let playlist_id = Playlist::new("test".to_string(), None, None, vec![])
+            .insert_to_db(collection_id)
+            .await
+            .unwrap();
+        let playlist = Playlist::find_in_db(playlist_id).await.unwrap();
+
+        let music =
+            |server: MusicServer, name: &str, artist: &str, album: Option<&str>, duration| {
+                let mut agg = music_agg(server, name, name);
+                agg.musics[0].artists[0].name = artist.to_string();
+                agg.musics[0].album = album.map(|a| a.to_string());
+                agg.musics[0].duration = Some(duration);
+                agg
+            };
+        playlist
+            .add_aggs_to_db(&vec![
+                music(MusicServer::Kuwo, "b", "Yorushika", Some("Elma"), 300),
+                music(MusicServer::Netease, "C", "Aimer", Some("Walpurgis"), 100),
+                music(MusicServer::Kuwo, "a", "Yorushika", None, 200),
+            ])
+            .await
+            .unwrap();
+
+        let names =
+            |aggs: Vec<MusicAggregator>| aggs.into_iter().map(|agg| agg.name).collect::<Vec<_>>();
+        let sorted = |key, direction| playlist.get_musics_from_db_sorted(key, direction);
+        assert_eq!(
+            names(sorted(SortKey::Name, SortDirection::Asc).await.unwrap()),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(
+            names(
+                sorted(SortKey::Duration, SortDirection::Desc)
+                    .await
+                    .unwrap()
+            ),
+            vec!["b", "a", "c"]
+        );
+        assert_eq!(
+            names(sorted(SortKey::Artist, SortDirection::Asc).await.unwrap()),
+            vec!["c", "b", "a"]
+        );
+        assert_eq!(
+            names(
+                sorted(SortKey::AddedTime, SortDirection::Desc)
+                    .await
+                    .unwrap()
+            ),
+            vec!["a", "c", "b"]
+        );
+
+        let groups = playlist
+            .get_music_groups_from_db(GroupKey::Artist)
+            .await
+            .unwrap();
+        assert_eq!(
+            groups,
+            vec![
+                PlaylistMusicGroup {
+                    name: Some("Aimer".to_string()),
+                    count: 1,
+                    duration: Some(100),
+                },
+                PlaylistMusicGroup {
+                    name: Some("Yorushika".to_string()),
+                    count: 2,
+                    duration: Some(500),
+                },
+            ]
+        );
+        assert_eq!(
+            names(
+                playlist
+                    .get_musics_in_group_from_db(GroupKey::Artist, Some("Yorushika"))
+                    .await
+                    .unwrap()
+            ),
+            vec!["b", "a"]
+        );
+
+        let groups = playlist
+            .get_music_groups_from_db(GroupKey::Album)
+            .await
+            .unwrap();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0].name, None);
+        assert_eq!(
+            names(
+                playlist
+                    .get_musics_in_group_from_db(GroupKey::Album, None)
+                    .await
+                    .unwrap()
+            ),
+            vec!["a"]
+        );
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_timestamps() {
+        set_db("sqlite::memory:").await.unwrap();
+        let collection_id = PlaylistCollection::new("test".to_string())
+            .insert_to_db()
+            .await
+            .unwrap();
+        let playlist_id = Playlist::new("test".to_string(), None, None, vec![])
+            .insert_to_db(collection_id)
+            .await
+            .unwrap();
+        let mut playlist = Playlist::find_in_db(playlist_id).await.unwrap();
+        let created_at = playlist.created_at.unwrap();
+        assert!(created_at > 0);
+        assert_eq!(playlist.updated_at, Some(created_at));
+
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        playlist.name = "renamed".to_string();
+        let playlist = playlist.update_to_db().await.unwrap();
+        assert_eq!(playlist.created_at, Some(created_at));
+        assert!(playlist.updated_at.unwrap() > created_at);
+
+        playlist
+            .add_aggs_to_db(&vec![music_agg(MusicServer::Kuwo, "k1", "song1")])
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        playlist
+            .add_aggs_to_db(&vec![music_agg(MusicServer::Kuwo, "k2", "song2")])
+            .await
+            .unwrap();
+        let musics = playlist
+            .get_musics_from_db_sorted(SortKey::AddedTime, SortDirection::Desc)
+            .await
+            .unwrap();
+        assert_eq!(musics[0].name, "song2");
+        assert!(musics[0].added_at.unwrap() > musics[1].added_at.unwrap());
+        assert!(musics[1].created_at.unwrap() >= created_at);
+
+        // the timestamp columns can be dropped and added again
+        crate::interface::database::reinit_db().await.unwrap();
+        assert!(Playlist::get_from_db().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_smart_playlist() {
+        use crate::interface::{
+            quality::{Quality, QualityTier},
+            smart_playlist::SmartPlaylistRule,
+        };
+
+        set_db("sqlite::memory:").await.unwrap();
+        let collection_id = PlaylistCollection::new("test".to_string())
+            .insert_to_db()
+            .await
+            .unwrap();
+        let playlist_id = Playlist::new("test".to_string(), None, None, vec![])
+            .insert_to_db(collection_id)
+            .await
+            .unwrap();
+        let playlist = Playlist::find_in_db(playlist_id).await.unwrap();
+
+        let mut flac = music_agg(MusicServer::Kuwo, "1", "Lemon");
+        flac.artist = "Kenshi Yonezu".to_string();
+        flac.musics[0].artists[0].name = "Kenshi Yonezu".to_string();
+        flac.musics[0].qualities = vec![Quality {
+            summary: "2000kflac".to_string(),
+            bitrate: Some("2000".to_string()),
+            format: Some("flac".to_string()),
+            size: None,
+        }];
+        let mut mp3 = music_agg(MusicServer::Netease, "2", "100%");
+        mp3.musics[0].album = Some("Stray Sheep".to_string());
+        mp3.musics[0].qualities = vec![Quality {
+            summary: "exhigh".to_string(),
+            bitrate: Some("320".to_string()),
+            format: Some("mp3".to_string()),
+            size: None,
+        }];
+        playlist
+            .add_aggs_to_db(&vec![flac.clone(), mp3.clone()])
+            .await
+            .unwrap();
+        mp3.record_play(MusicServer::Netease, 100, true)
+            .await
+            .unwrap();
+        mp3.record_play(MusicServer::Netease, 100, true)
+            .await
+            .unwrap();
+
+        let smart_id = Playlist::new_smart(
+            "smart".to_string(),
+            None,
+            None,
+            SmartPlaylistRules::new(
+                true,
+                vec![SmartPlaylistRule::MinQuality(QualityTier::Lossless)],
+            ),
+        )
+        .insert_to_db(collection_id)
+        .await
+        .unwrap();
+        let mut smart = Playlist::find_in_db(smart_id).await.unwrap();
+        assert!(smart.is_smart());
+        assert!(smart.add_aggs_to_db(&vec![mp3.clone()]).await.is_err());
+
+        let names =
+            |aggs: Vec<MusicAggregator>| aggs.into_iter().map(|agg| agg.name).collect::<Vec<_>>();
+        assert_eq!(
+            names(smart.get_musics_from_db().await.unwrap()),
+            vec!["lemon"]
+        );
+
+        let cases = vec![
+            (
+                SmartPlaylistRule::ArtistContains("YONEZU".to_string()),
+                vec!["lemon"],
+            ),
+            (
+                SmartPlaylistRule::NameContains("0%".to_string()),
+                vec!["100%"],
+            ),
+            (SmartPlaylistRule::NameContains("_".to_string()), vec![]),
+            (
+                SmartPlaylistRule::AlbumContains("sheep".to_string()),
+                vec!["100%"],
+            ),
+            (
+                SmartPlaylistRule::AddedWithinDays(30),
+                vec!["lemon", "100%"],
+            ),
+            (SmartPlaylistRule::PlayedMoreThan(1), vec!["100%"]),
+            (SmartPlaylistRule::PlayedMoreThan(2), vec![]),
+            (
+                SmartPlaylistRule::Server(MusicServer::Netease),
+                vec!["100%"],
+            ),
+            (
+                SmartPlaylistRule::MinQuality(QualityTier::High),
+                vec!["lemon", "100%"],
+            ),
+        ];
+        for (rule, expected) in cases {
+            smart
+                .set_smart_rules(Some(SmartPlaylistRules::new(true, vec![rule.clone()])))
+                .await
+                .unwrap();
+            let smart = Playlist::find_in_db(smart_id).await.unwrap();
+            let mut found = names(smart.get_musics_from_db().await.unwrap());
+            found.sort();
+            let mut expected = expected;
+            expected.sort();
+            assert_eq!(found, expected, "{:?}", rule);
+            assert_eq!(
+                smart.count_musics_in_db().await.unwrap(),
+                expected.len() as u64
+            );
+        }
+
+        // the sorted and grouped views follow the rules too
+        let current = Playlist::find_in_db(smart_id).await.unwrap();
+        assert_eq!(
+            names(
+                current
+                    .get_musics_from_db_sorted(SortKey::Name, SortDirection::Desc)
+                    .await
+                    .unwrap()
+            ),
+            vec!["lemon", "100%"]
+        );
+        let groups = current
+            .get_music_groups_from_db(GroupKey::Artist)
+            .await
+            .unwrap();
+        assert_eq!(
+            groups
+                .iter()
+                .map(|group| (group.name.clone().unwrap(), group.count))
+                .collect::<Vec<_>>(),
+            vec![("Kenshi Yonezu".to_string(), 1), ("artist".to_string(), 1)]
+        );
+        assert_eq!(
+            names(
+                current
+                    .get_musics_in_group_from_db(GroupKey::Artist, Some("artist"))
+                    .await
+                    .unwrap()
+            ),
+            vec!["100%"]
+        );
+        smart
+            .set_smart_rules(Some(SmartPlaylistRules::new(
+                true,
+                vec![SmartPlaylistRule::Server(MusicServer::Kuwo)],
+            )))
+            .await
+            .unwrap();
+        let current = Playlist::find_in_db(smart_id).await.unwrap();
+        assert_eq!(
+            names(
+                current
+                    .get_musics_from_db_sorted(SortKey::AddedTime, SortDirection::Asc)
+                    .await
+                    .unwrap()
+            ),
+            vec!["lemon"]
+        );
+        assert_eq!(
+            current
+                .get_music_groups_from_db(GroupKey::Album)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // the order of a smart playlist comes from the rules
+        assert!(current.move_item(0, 0).await.is_err());
+        assert!(current.reorder(&[flac.identity()]).await.is_err());
+        assert!(current.compact_order().await.is_err());
+
+        // any rule is enough unless `match_all`
+        let rules = vec![
+            SmartPlaylistRule::Server(MusicServer::Kuwo),
+            SmartPlaylistRule::PlayedMoreThan(1),
+        ];
+        smart
+            .set_smart_rules(Some(SmartPlaylistRules::new(false, rules.clone())))
+            .await
+            .unwrap();
+        assert_eq!(smart.count_musics_in_db().await.unwrap(), 2);
+        smart
+            .set_smart_rules(Some(SmartPlaylistRules::new(true, rules)))
+            .await
+            .unwrap();
+        assert_eq!(smart.count_musics_in_db().await.unwrap(), 0);
+
+        // music which is only in the trash doesn't match
+        smart
+            .set_smart_rules(Some(SmartPlaylistRules::new(
+                true,
+                vec![SmartPlaylistRule::AddedWithinDays(30)],
+            )))
+            .await
+            .unwrap();
+        playlist.del_music_agg(flac.identity()).await.unwrap();
+        let current = Playlist::find_in_db(smart_id).await.unwrap();
+        assert_eq!(
+            names(current.get_musics_from_db().await.unwrap()),
+            vec!["100%"]
+        );
+        assert_eq!(current.count_musics_in_db().await.unwrap(), 1);
+
+        // without rules it is a normal, empty playlist again
+        smart.set_smart_rules(None).await.unwrap();
+        let smart = Playlist::find_in_db(smart_id).await.unwrap();
+        assert!(!smart.is_smart());
+        assert!(smart.get_musics_from_db().await.unwrap().is_empty());
+    }
 }
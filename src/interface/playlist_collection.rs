@@ -3,13 +3,19 @@ use sea_orm::{
     prelude::Expr,
     sea_query::{Alias, Func, Query},
     ActiveValue::NotSet,
-    ConnectionTrait, EntityTrait, QueryFilter, Set, Unchanged,
+    ColumnTrait as _, ConnectionTrait, EntityTrait, PaginatorTrait as _, QueryFilter,
+    QueryOrder as _, Set, TransactionTrait as _, Unchanged,
 };
 use serde::{Deserialize, Serialize};
 
 use crate::data::models::{playlist, playlist_collection};
 
-use super::{database::get_db, playlist::Playlist};
+use super::{
+    database::get_db,
+    journal::{Journal, JournalScope},
+    playlist::Playlist,
+    utils::{move_item, now_millis, reorder_by_keys},
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct PlaylistCollection {
@@ -42,7 +48,10 @@ impl PlaylistCollection {
             .await
             .ok_or(anyhow::anyhow!("Database is not inited."))?;
 
-        let models = playlist_collection::Entity::find().all(&db).await?;
+        let models = playlist_collection::Entity::find()
+            .filter(playlist_collection::Column::DeletedAt.is_null())
+            .all(&db)
+            .await?;
         let mut collections = models
             .into_iter()
             .map(|p| p.into())
@@ -58,6 +67,7 @@ impl PlaylistCollection {
 
         let models = playlist::Entity::find()
             .filter(Expr::col(playlist::Column::CollectionId).eq(self.id))
+            .filter(playlist::Column::DeletedAt.is_null())
             .all(&db)
             .await?;
 
@@ -75,31 +85,44 @@ impl PlaylistCollection {
             .await
             .ok_or(anyhow::anyhow!("Database is not inited."))?;
 
+        let txn = db.begin().await?;
+        let journal = Journal::begin(&txn, JournalScope::default()).await?;
+        // the new collection goes last
         let statement = Query::select()
             .expr(Func::max(Expr::col((
                 Alias::new("playlist_collection"),
-                playlist_collection::Column::Id,
+                playlist_collection::Column::Order,
             ))))
             .from(playlist_collection::Entity)
             .to_owned();
-        let query_result = db
+        let query_result = txn
             .query_one(sea_orm::StatementBuilder::build(
                 &statement,
                 &db.get_database_backend(),
             ))
             .await?
-            .ok_or(anyhow::anyhow!("Failed to get max id from playlist table."))?;
-        let max_id: i64 = query_result.try_get_by_index(0).ok().unwrap_or(0);
+            .ok_or(anyhow::anyhow!(
+                "Failed to get max order from playlist collection table."
+            ))?;
+        let max_order: i64 = query_result.try_get_by_index(0).ok().unwrap_or(0);
         let playlist = playlist_collection::ActiveModel {
             id: NotSet,
-            order: Set(max_id + 1),
+            order: Set(max_order + 1),
             name: Set(self.name.clone()),
+            created_at: Set(now_millis()),
+            updated_at: Set(now_millis()),
+            deleted_at: Set(None),
         };
 
         let result = playlist_collection::Entity::insert(playlist)
-            .exec(&db)
+            .exec(&txn)
             .await?;
         let last_id = result.last_insert_id;
+        journal
+            .created(JournalScope::collection(last_id))
+            .commit(&txn, "Create playlist collection")
+            .await?;
+        txn.commit().await?;
         Ok(last_id)
     }
 
@@ -109,6 +132,7 @@ impl PlaylistCollection {
             .ok_or(anyhow::anyhow!("Database is not inited."))?;
 
         let result = playlist_collection::Entity::find_by_id(id)
+            .filter(playlist_collection::Column::DeletedAt.is_null())
             .one(&db)
             .await?
             .ok_or(anyhow!("PlaylistCollection not found."))?;
@@ -124,16 +148,24 @@ impl PlaylistCollection {
             id: Unchanged(self.id),
             order: Set(self.order),
             name: Set(self.name.clone()),
+            created_at: NotSet,
+            updated_at: Set(now_millis()),
+            deleted_at: NotSet,
         };
         let db: sea_orm::DatabaseConnection = get_db()
             .await
             .ok_or(anyhow::anyhow!("Database is not inited."))?;
+        let txn = db.begin().await?;
+        let journal = Journal::begin(&txn, JournalScope::collection(self.id)).await?;
         let result = playlist_collection::Entity::update(active)
-            .exec(&db)
+            .exec(&txn)
             .await?;
+        journal.commit(&txn, "Update playlist collection").await?;
+        txn.commit().await?;
         Ok(result.into())
     }
 
+    /// move the collection with its playlists to the trash, see `Trash` for restoring it
     pub async fn delete_from_db(&self) -> anyhow::Result<()> {
         if self.id == -1 {
             return Err(anyhow!("PlaylistCollection id is not set."));
@@ -142,15 +174,216 @@ impl PlaylistCollection {
             .await
             .ok_or(anyhow::anyhow!("Database is not inited."))?;
 
-        playlist_collection::Entity::delete_by_id(self.id)
-            .exec(&db)
+        // purging the collection would cascade to the system playlists in it
+        let system_playlists = playlist::Entity::find()
+            .filter(playlist::Column::CollectionId.eq(self.id))
+            .filter(playlist::Column::SystemType.is_not_null())
+            .count(&db)
+            .await?;
+        if system_playlists > 0 {
+            return Err(anyhow!(
+                "Can't delete a PlaylistCollection containing a system playlist."
+            ));
+        }
+
+        let txn = db.begin().await?;
+        let journal = Journal::begin(&txn, JournalScope::collection(self.id)).await?;
+        playlist_collection::Entity::update_many()
+            .col_expr(
+                playlist_collection::Column::DeletedAt,
+                Expr::value(now_millis()),
+            )
+            .filter(playlist_collection::Column::Id.eq(self.id))
+            .filter(playlist_collection::Column::DeletedAt.is_null())
+            .exec(&txn)
+            .await?;
+        journal.commit(&txn, "Delete playlist collection").await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// number the collections `1..=n` in the given order, only changed rows are written
+    async fn save_order<C: ConnectionTrait>(
+        db: &C,
+        collections: Vec<playlist_collection::Model>,
+    ) -> anyhow::Result<()> {
+        for (index, collection) in collections.into_iter().enumerate() {
+            let order = index as i64 + 1;
+            if collection.order == order {
+                continue;
+            }
+            playlist_collection::Entity::update_many()
+                .col_expr(playlist_collection::Column::Order, Expr::value(order))
+                .col_expr(
+                    playlist_collection::Column::UpdatedAt,
+                    Expr::value(now_millis()),
+                )
+                .filter(playlist_collection::Column::Id.eq(collection.id))
+                .exec(db)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// move the collection at position `from` to position `to`
+    pub async fn move_item(from: usize, to: usize) -> anyhow::Result<()> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+
+        let txn = db.begin().await?;
+        let journal = Journal::begin(&txn, JournalScope::all_collections()).await?;
+        let mut collections = playlist_collection::Entity::find()
+            .filter(playlist_collection::Column::DeletedAt.is_null())
+            .order_by_asc(playlist_collection::Column::Order)
+            .order_by_asc(playlist_collection::Column::Id)
+            .all(&txn)
+            .await?;
+        move_item(&mut collections, from, to)?;
+        Self::save_order(&txn, collections).await?;
+        journal.commit(&txn, "Move playlist collection").await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// reorder the collections, `ids` must list every collection once
+    pub async fn reorder(ids: &[i64]) -> anyhow::Result<()> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+
+        let txn = db.begin().await?;
+        let journal = Journal::begin(&txn, JournalScope::all_collections()).await?;
+        let collections = playlist_collection::Entity::find()
+            .filter(playlist_collection::Column::DeletedAt.is_null())
+            .order_by_asc(playlist_collection::Column::Order)
+            .order_by_asc(playlist_collection::Column::Id)
+            .all(&txn)
             .await?;
+        let collections = reorder_by_keys(collections, ids, |collection| collection.id)?;
+        Self::save_order(&txn, collections).await?;
+        journal.commit(&txn, "Reorder playlist collections").await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// renumber the collections to `1..=n`, removing gaps and duplicate orders
+    pub async fn compact_order() -> anyhow::Result<()> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+
+        let txn = db.begin().await?;
+        let journal = Journal::begin(&txn, JournalScope::all_collections()).await?;
+        let collections = playlist_collection::Entity::find()
+            .filter(playlist_collection::Column::DeletedAt.is_null())
+            .order_by_asc(playlist_collection::Column::Order)
+            .order_by_asc(playlist_collection::Column::Id)
+            .all(&txn)
+            .await?;
+        Self::save_order(&txn, collections).await?;
+        journal
+            .commit(&txn, "Compact playlist collection order")
+            .await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// playlists of a collection in collection order, without the ones in the trash
+    pub(crate) async fn get_playlist_models<C: ConnectionTrait>(
+        db: &C,
+        collection_id: i64,
+    ) -> anyhow::Result<Vec<playlist::Model>> {
+        Ok(playlist::Entity::find()
+            .filter(playlist::Column::CollectionId.eq(collection_id))
+            .filter(playlist::Column::DeletedAt.is_null())
+            .order_by_asc(playlist::Column::Order)
+            .order_by_asc(playlist::Column::Id)
+            .all(db)
+            .await?)
+    }
+
+    /// number the playlists `1..=n` in the given order, only changed rows are written
+    pub(crate) async fn save_playlist_order<C: ConnectionTrait>(
+        db: &C,
+        playlists: Vec<playlist::Model>,
+    ) -> anyhow::Result<()> {
+        for (index, playlist) in playlists.into_iter().enumerate() {
+            let order = index as i64 + 1;
+            if playlist.order == order {
+                continue;
+            }
+            playlist::Entity::update_many()
+                .col_expr(playlist::Column::Order, Expr::value(order))
+                .col_expr(playlist::Column::UpdatedAt, Expr::value(now_millis()))
+                .filter(playlist::Column::Id.eq(playlist.id))
+                .exec(db)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// move the playlist at position `from` to position `to` in this collection
+    pub async fn move_playlist(&self, from: usize, to: usize) -> anyhow::Result<()> {
+        if self.id == -1 {
+            return Err(anyhow!("PlaylistCollection id is not set."));
+        }
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+
+        let txn = db.begin().await?;
+        let journal = Journal::begin(&txn, JournalScope::collection(self.id)).await?;
+        let mut playlists = Self::get_playlist_models(&txn, self.id).await?;
+        move_item(&mut playlists, from, to)?;
+        Self::save_playlist_order(&txn, playlists).await?;
+        journal.commit(&txn, "Move playlist").await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// reorder the playlists of this collection, `ids` must list every playlist of it once
+    pub async fn reorder_playlists(&self, ids: &[i64]) -> anyhow::Result<()> {
+        if self.id == -1 {
+            return Err(anyhow!("PlaylistCollection id is not set."));
+        }
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+
+        let txn = db.begin().await?;
+        let journal = Journal::begin(&txn, JournalScope::collection(self.id)).await?;
+        let playlists = Self::get_playlist_models(&txn, self.id).await?;
+        let playlists = reorder_by_keys(playlists, ids, |playlist| playlist.id)?;
+        Self::save_playlist_order(&txn, playlists).await?;
+        journal.commit(&txn, "Reorder playlists").await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// renumber the playlists of this collection to `1..=n`
+    pub async fn compact_playlist_order(&self) -> anyhow::Result<()> {
+        if self.id == -1 {
+            return Err(anyhow!("PlaylistCollection id is not set."));
+        }
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+
+        let txn = db.begin().await?;
+        let journal = Journal::begin(&txn, JournalScope::collection(self.id)).await?;
+        let playlists = Self::get_playlist_models(&txn, self.id).await?;
+        Self::save_playlist_order(&txn, playlists).await?;
+        journal.commit(&txn, "Compact playlist order").await?;
+        txn.commit().await?;
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
+    use serial_test::serial;
+
     use crate::interface::{database::set_db, playlist::Playlist};
 
     #[tokio::test]
@@ -192,4 +425,142 @@ mod test {
         let playlists = Playlist::get_from_db().await.unwrap();
         assert_eq!(playlists.len(), 0);
     }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_reorder() {
+        set_db("sqlite::memory:").await.unwrap();
+
+        let mut collection_ids = vec![];
+        for name in ["a", "b", "c"] {
+            collection_ids.push(
+                super::PlaylistCollection::new(name.to_string())
+                    .insert_to_db()
+                    .await
+                    .unwrap(),
+            );
+        }
+        super::PlaylistCollection::move_item(0, 2).await.unwrap();
+        super::PlaylistCollection::compact_order().await.unwrap();
+        let collections = super::PlaylistCollection::get_form_db().await.unwrap();
+        assert_eq!(
+            collections
+                .iter()
+                .map(|c| (c.name.as_str(), c.order))
+                .collect::<Vec<_>>(),
+            vec![("b", 1), ("c", 2), ("a", 3)]
+        );
+
+        let collection = super::PlaylistCollection::find_in_db(collection_ids[0])
+            .await
+            .unwrap();
+        let mut playlist_ids = vec![];
+        for name in ["p1", "p2", "p3"] {
+            playlist_ids.push(
+                Playlist::new(name.to_string(), None, None, vec![])
+                    .insert_to_db(collection.id)
+                    .await
+                    .unwrap(),
+            );
+        }
+        collection.move_playlist(2, 0).await.unwrap();
+        let names =
+            |playlists: Vec<Playlist>| playlists.into_iter().map(|p| p.name).collect::<Vec<_>>();
+        assert_eq!(
+            names(collection.get_playlists_from_db().await.unwrap()),
+            vec!["p3", "p1", "p2"]
+        );
+
+        collection
+            .reorder_playlists(&[playlist_ids[1], playlist_ids[0], playlist_ids[2]])
+            .await
+            .unwrap();
+        collection.compact_playlist_order().await.unwrap();
+        let playlists = collection.get_playlists_from_db().await.unwrap();
+        assert_eq!(
+            playlists.iter().map(|p| p.order).collect::<Vec<_>>(),
+            vec![Some(1), Some(2), Some(3)]
+        );
+        assert_eq!(names(playlists), vec!["p2", "p1", "p3"]);
+        assert!(collection
+            .reorder_playlists(&[playlist_ids[0], playlist_ids[0], playlist_ids[2]])
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn test_move_to_collection() {
+        set_db("sqlite::memory:").await.unwrap();
+
+        let a = super::PlaylistCollection::new("a".to_string())
+            .insert_to_db()
+            .await
+            .unwrap();
+        let b = super::PlaylistCollection::new("b".to_string())
+            .insert_to_db()
+            .await
+            .unwrap();
+        // orders follow the existing ones, not the ids
+        super::PlaylistCollection::reorder(&[b, a]).await.unwrap();
+        let c = super::PlaylistCollection::new("c".to_string())
+            .insert_to_db()
+            .await
+            .unwrap();
+        let collections = super::PlaylistCollection::get_form_db().await.unwrap();
+        assert_eq!(
+            collections
+                .iter()
+                .map(|c| (c.name.as_str(), c.order))
+                .collect::<Vec<_>>(),
+            vec![("b", 1), ("a", 2), ("c", 3)]
+        );
+        assert!(super::PlaylistCollection::reorder(&[a, b]).await.is_err());
+
+        let mut playlists = vec![];
+        for (name, collection) in [("a1", a), ("a2", a), ("b1", b), ("b2", b)] {
+            let id = Playlist::new(name.to_string(), None, None, vec![])
+                .insert_to_db(collection)
+                .await
+                .unwrap();
+            playlists.push(Playlist::find_in_db(id).await.unwrap());
+        }
+        assert_eq!(playlists[3].order, Some(2));
+
+        let names_orders = |playlists: Vec<Playlist>| {
+            playlists
+                .into_iter()
+                .map(|p| (p.name, p.order.unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let collection_a = super::PlaylistCollection::find_in_db(a).await.unwrap();
+        let collection_b = super::PlaylistCollection::find_in_db(b).await.unwrap();
+        playlists[0].move_to_collection(b, 1).await.unwrap();
+        assert_eq!(playlists[0].collection_id, Some(b));
+        assert_eq!(
+            names_orders(collection_a.get_playlists_from_db().await.unwrap()),
+            vec![("a2".to_string(), 1)]
+        );
+        assert_eq!(
+            names_orders(collection_b.get_playlists_from_db().await.unwrap()),
+            vec![
+                ("b1".to_string(), 1),
+                ("a1".to_string(), 2),
+                ("b2".to_string(), 3)
+            ]
+        );
+
+        // inside the same collection
+        playlists[3].move_to_collection(b, 0).await.unwrap();
+        assert_eq!(
+            names_orders(collection_b.get_playlists_from_db().await.unwrap()),
+            vec![
+                ("b2".to_string(), 1),
+                ("b1".to_string(), 2),
+                ("a1".to_string(), 3)
+            ]
+        );
+        assert!(playlists[1].move_to_collection(b, 4).await.is_err());
+        assert!(playlists[1].move_to_collection(c + 1, 0).await.is_err());
+    }
 }
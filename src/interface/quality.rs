@@ -27,3 +27,42 @@ impl Deref for QualityVec {
         &self.0
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum QualityTier {
+    Standard,
+    High,
+    Lossless,
+    HiRes,
+}
+
+impl Quality {
+    /// the tier of the quality, from the netease level name or the kuwo bitrate and format
+    pub fn tier(&self) -> QualityTier {
+        match self.summary.to_lowercase().as_str() {
+            "hires" => return QualityTier::HiRes,
+            "lossless" => return QualityTier::Lossless,
+            "exhigh" => return QualityTier::High,
+            "higher" | "standard" => return QualityTier::Standard,
+            _ => {}
+        }
+
+        let bitrate = self
+            .bitrate
+            .as_deref()
+            .and_then(|b| b.parse::<u32>().ok())
+            .unwrap_or(0);
+        let lossless_format = self.format.as_deref().is_some_and(|f| {
+            matches!(f.to_lowercase().as_str(), "flac" | "ape" | "wav" | "alac")
+        });
+        if bitrate >= 4000 {
+            QualityTier::HiRes
+        } else if bitrate >= 1000 || lossless_format {
+            QualityTier::Lossless
+        } else if bitrate >= 320 {
+            QualityTier::High
+        } else {
+            QualityTier::Standard
+        }
+    }
+}
@@ -1,6 +1,108 @@
 use serde::{Deserialize, Serialize};
 
+use super::{
+    json::PlaylistJson, music_aggregator::MusicAggregator, server::MusicServer,
+    tracklist::TrackEntry,
+};
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PlaylistUpdateSubscriptionResult {
     pub errors: Vec<(String, String)>,
 }
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlaylistAddMusicAggsResult {
+    pub inserted_music_aggs: u64,
+    pub updated_music_aggs: u64,
+    /// music aggregators whose musics are already saved under another identity,
+    /// the saved music aggregator is added to the playlist instead
+    pub reused_music_aggs: u64,
+    pub inserted_musics: u64,
+    pub inserted_junctions: u64,
+    /// identities of the music aggregators already in the playlist
+    pub skipped: Vec<String>,
+    /// identities of the music aggregators which can't be saved, and the reason
+    pub errors: Vec<(String, String)>,
+}
+
+/// what `DatabaseJson::merge_to_db` did, matched rows count as merged
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DatabaseMergeResult {
+    pub inserted_collections: u64,
+    pub merged_collections: u64,
+    pub inserted_playlists: u64,
+    pub merged_playlists: u64,
+    pub inserted_music_aggs: u64,
+    pub merged_music_aggs: u64,
+    pub inserted_junctions: u64,
+    pub inserted_play_history: u64,
+}
+
+/// what applying music data would change, see `MusicDataJson::plan_apply`.
+/// collections and playlists are listed by name, music aggregators by identity.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApplyDiff {
+    /// the library is wiped before the import
+    pub replaces_library: bool,
+    pub created_collections: Vec<String>,
+    /// incoming collections merged into a local one
+    pub duplicate_collections: Vec<String>,
+    pub created_playlists: Vec<String>,
+    /// incoming playlists merged into a local one
+    pub duplicate_playlists: Vec<String>,
+    pub created_music_aggs: Vec<String>,
+    /// incoming identity and the identity of the saved music aggregator it merges into
+    pub duplicate_music_aggs: Vec<(String, String)>,
+    /// music aggregators which can't be saved, their musics belong to other ones
+    pub skipped_music_aggs: Vec<String>,
+    pub created_junctions: u64,
+    /// playlist name and music aggregator identity of music which won't be added,
+    /// it is already in the playlist or its music aggregator isn't saved
+    pub skipped_junctions: Vec<(String, String)>,
+    /// server and id of incoming musics saved with other info, the conflict policy picks one
+    pub conflicting_musics: Vec<(MusicServer, String)>,
+    /// server and id of incoming musics which can't be saved
+    pub skipped_musics: Vec<(MusicServer, String)>,
+}
+
+/// music aggregators matched to the tracks of a playlist file, in track order
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackImportResult {
+    pub music_aggs: Vec<MusicAggregator>,
+    /// `artist - title` of the tracks nothing matched
+    pub unresolved: Vec<String>,
+}
+
+/// a line of a pasted tracklist and the search result picked for it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackMatch {
+    pub line: String,
+    pub track: TrackEntry,
+    /// the best search result, in the playlist if `confidence` reaches `MIN_CONFIDENCE`
+    pub candidate: Option<MusicAggregator>,
+    /// 0 to 1, see `tracklist::confidence`
+    pub confidence: f64,
+}
+
+/// a tracklist to review before it is saved, see `resolve_tracklist`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TracklistImport {
+    pub playlist: PlaylistJson,
+    /// one per parsed line
+    pub matches: Vec<TrackMatch>,
+}
+
+/// sent by `migrate_database` after every batch of a table
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationProgress {
+    pub table: String,
+    pub copied: u64,
+    /// rows of the table in the source
+    pub total: u64,
+}
+
+/// rows copied by `migrate_database`, per table in copy order
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DatabaseMigrationResult {
+    pub tables: Vec<(String, u64)>,
+}
@@ -0,0 +1,222 @@
+use sea_orm::{
+    sea_query::{Alias, Expr, Func, LikeExpr, Query, SimpleExpr},
+    ColumnTrait as _, Condition, DatabaseBackend, EntityTrait, FromJsonQueryResult, JoinType,
+    QueryFilter, QueryOrder as _, QuerySelect as _, RelationTrait as _, Select,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::data::models::{
+    music_aggregator, play_history, playlist, playlist_collection, playlist_music_junction,
+};
+
+use super::{library::server_table, quality::QualityTier, server::MusicServer, utils::now_millis};
+
+/// a rule of a smart playlist, text rules are case-insensitive
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SmartPlaylistRule {
+    NameContains(String),
+    /// the artist of the music aggregator contains the text
+    ArtistContains(String),
+    AlbumContains(String),
+    /// saved to the library in the last n days, by `music_aggregator.created_at`.
+    /// adding an already saved music to another playlist doesn't make it new
+    AddedWithinDays(u32),
+    /// at least one music has a quality of this tier or better
+    MinQuality(QualityTier),
+    /// recorded in the play history more than n times
+    PlayedMoreThan(u64),
+    /// has a music on the server
+    Server(MusicServer),
+}
+
+/// rules of a smart playlist, stored as json in `playlist.smart_rules`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+pub struct SmartPlaylistRules {
+    /// `true` if every rule must match, otherwise any rule is enough
+    pub match_all: bool,
+    pub rules: Vec<SmartPlaylistRule>,
+}
+
+/// lower case `text` with the `LIKE` wildcards escaped by `!`
+pub(crate) fn escape_like(text: &str) -> String {
+    text.to_lowercase()
+        .replace('!', "!!")
+        .replace('%', "!%")
+        .replace('_', "!_")
+}
+
+/// `LIKE` pattern matching the lower case `text` anywhere, with its `ESCAPE` clause
+pub(crate) fn contains_pattern(text: &str) -> LikeExpr {
+    LikeExpr::new(format!("%{}%", escape_like(text))).escape('!')
+}
+
+/// `identity` is `name#+#artist` in lower case, so name and artist rules need no join
+fn identity_like(pattern: String) -> SimpleExpr {
+    Expr::col((music_aggregator::Entity, music_aggregator::Column::Identity))
+        .like(LikeExpr::new(pattern).escape('!'))
+}
+
+/// the tier of one quality in sql, mirrors `Quality::tier`
+fn quality_tier_sql(summary: &str, bitrate: &str, format: &str) -> String {
+    format!(
+        "CASE {summary} WHEN 'hires' THEN 3 WHEN 'lossless' THEN 2 WHEN 'exhigh' THEN 1 \
+         WHEN 'higher' THEN 0 WHEN 'standard' THEN 0 ELSE CASE WHEN {bitrate} >= 4000 THEN 3 \
+         WHEN {bitrate} >= 1000 OR {format} IN ('flac', 'ape', 'wav', 'alac') THEN 2 \
+         WHEN {bitrate} >= 320 THEN 1 ELSE 0 END END"
+    )
+}
+
+/// whether a quality in the json `qualities` column of `table` reaches `tier`
+fn quality_exists_expr(backend: DatabaseBackend, table: &str, tier: QualityTier) -> SimpleExpr {
+    let rank = tier as i64;
+    Expr::cust(match backend {
+        DatabaseBackend::Sqlite => format!(
+            "EXISTS (SELECT 1 FROM json_each(\"{table}\".\"qualities\") AS q WHERE {} >= {rank})",
+            quality_tier_sql(
+                "lower(json_extract(q.value, '$.summary'))",
+                "CAST(json_extract(q.value, '$.bitrate') AS INTEGER)",
+                "lower(json_extract(q.value, '$.format'))",
+            )
+        ),
+        DatabaseBackend::Postgres => format!(
+            "EXISTS (SELECT 1 FROM json_array_elements(\"{table}\".\"qualities\"::json) AS q(value) \
+             WHERE {} >= {rank})",
+            quality_tier_sql(
+                "lower(q.value ->> 'summary')",
+                "(CASE WHEN (q.value ->> 'bitrate') ~ '^[0-9]+$' \
+                 THEN (q.value ->> 'bitrate')::bigint ELSE 0 END)",
+                "lower(q.value ->> 'format')",
+            )
+        ),
+        DatabaseBackend::MySql => format!(
+            "EXISTS (SELECT 1 FROM JSON_TABLE(`{table}`.`qualities`, '$[*]' COLUMNS (\
+             summary VARCHAR(64) PATH '$.summary', bitrate VARCHAR(64) PATH '$.bitrate', \
+             format VARCHAR(64) PATH '$.format')) AS q WHERE {} >= {rank})",
+            quality_tier_sql(
+                "lower(q.summary)",
+                "CAST(q.bitrate AS UNSIGNED)",
+                "lower(q.format)",
+            )
+        ),
+    })
+}
+
+impl SmartPlaylistRule {
+    fn condition(&self, backend: DatabaseBackend) -> Condition {
+        match self {
+            SmartPlaylistRule::NameContains(text) => {
+                Condition::all().add(identity_like(format!("%{}%#+#%", escape_like(text))))
+            }
+            SmartPlaylistRule::ArtistContains(text) => {
+                Condition::all().add(identity_like(format!("%#+#%{}%", escape_like(text))))
+            }
+            SmartPlaylistRule::AlbumContains(text) => {
+                MusicServer::all()
+                    .iter()
+                    .fold(Condition::any(), |condition, server| {
+                        let (table, _) = server_table(server);
+                        condition.add(
+                            Expr::expr(Func::lower(Expr::col((
+                                Alias::new(table),
+                                Alias::new("album"),
+                            ))))
+                            .like(contains_pattern(text)),
+                        )
+                    })
+            }
+            SmartPlaylistRule::AddedWithinDays(days) => Condition::all().add(
+                music_aggregator::Column::CreatedAt
+                    .gte(now_millis() - *days as i64 * 24 * 60 * 60 * 1000),
+            ),
+            SmartPlaylistRule::MinQuality(tier) => {
+                MusicServer::all()
+                    .iter()
+                    .fold(Condition::any(), |condition, server| {
+                        let (table, _) = server_table(server);
+                        condition.add(quality_exists_expr(backend, table, *tier))
+                    })
+            }
+            SmartPlaylistRule::PlayedMoreThan(count) => Condition::all().add(
+                music_aggregator::Column::Identity.in_subquery(
+                    Query::select()
+                        .column(play_history::Column::MusicAggregatorId)
+                        .from(play_history::Entity)
+                        .group_by_col(play_history::Column::MusicAggregatorId)
+                        .and_having(
+                            Expr::expr(Expr::col(play_history::Column::Id).count()).gt(*count),
+                        )
+                        .to_owned(),
+                ),
+            ),
+            // todo: add more music server
+            SmartPlaylistRule::Server(server) => Condition::all().add(match server {
+                MusicServer::Kuwo => music_aggregator::Column::KuwoMusicId.is_not_null(),
+                MusicServer::Netease => music_aggregator::Column::NeteaseMusicId.is_not_null(),
+            }),
+        }
+    }
+}
+
+impl SmartPlaylistRules {
+    pub fn new(match_all: bool, rules: Vec<SmartPlaylistRule>) -> Self {
+        Self { match_all, rules }
+    }
+
+    /// music aggregators matching the rules, oldest saved first.
+    /// the music tables are left joined, so rules can use their columns.
+    pub(crate) fn select(&self, backend: DatabaseBackend) -> Select<music_aggregator::Entity> {
+        self.select_unordered(backend)
+            .order_by_asc(music_aggregator::Column::CreatedAt)
+            .order_by_asc(music_aggregator::Column::Identity)
+    }
+
+    /// `select` without its order, for callers sorting by something else first.
+    /// only music in a playlist counts, music which is only in the trash doesn't.
+    pub(crate) fn select_unordered(
+        &self,
+        backend: DatabaseBackend,
+    ) -> Select<music_aggregator::Entity> {
+        let live_playlists = Query::select()
+            .column(playlist::Column::Id)
+            .from(playlist::Entity)
+            .and_where(playlist::Column::DeletedAt.is_null())
+            .and_where(
+                playlist::Column::CollectionId.not_in_subquery(
+                    Query::select()
+                        .column(playlist_collection::Column::Id)
+                        .from(playlist_collection::Entity)
+                        .and_where(playlist_collection::Column::DeletedAt.is_not_null())
+                        .to_owned(),
+                ),
+            )
+            .to_owned();
+        let mut select = music_aggregator::Entity::find().filter(
+            music_aggregator::Column::Identity.in_subquery(
+                Query::select()
+                    .column(playlist_music_junction::Column::MusicAggregatorId)
+                    .from(playlist_music_junction::Entity)
+                    .and_where(playlist_music_junction::Column::DeletedAt.is_null())
+                    .and_where(
+                        playlist_music_junction::Column::PlaylistId.in_subquery(live_playlists),
+                    )
+                    .to_owned(),
+            ),
+        );
+        for server in MusicServer::all() {
+            let (_, relation) = server_table(&server);
+            select = select.join(JoinType::LeftJoin, relation.def());
+        }
+        // no rules matches everything
+        if !self.rules.is_empty() {
+            let condition = if self.match_all {
+                Condition::all()
+            } else {
+                Condition::any()
+            };
+            select = select.filter(self.rules.iter().fold(condition, |condition, rule| {
+                condition.add(rule.condition(backend))
+            }));
+        }
+        select
+    }
+}
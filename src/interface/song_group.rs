@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::{music_aggregator::MusicAggregator, utils::normalize_name};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum VariantType {
+    Original,
+    Live,
+    Remix,
+    Instrumental,
+    TvSize,
+    Acoustic,
+    Remaster,
+    Demo,
+}
+
+// checked in order, the first hit wins, so "Live 伴奏" is an instrumental
+const VARIANT_KEYWORDS: [(VariantType, &[&str]); 7] = [
+    (
+        VariantType::Instrumental,
+        &[
+            "伴奏",
+            "instrumental",
+            "inst.",
+            "off vocal",
+            "karaoke",
+            "纯音乐",
+            "消音",
+        ],
+    ),
+    (
+        VariantType::TvSize,
+        &["tv size", "tvsize", "tv ver", "tv edit", "tv版"],
+    ),
+    (
+        VariantType::Remix,
+        &["remix", "rmx", "混音", "dj版", "club mix", "extended mix", "radio mix"],
+    ),
+    (
+        VariantType::Live,
+        &["live", "现场", "演唱会", "concert"],
+    ),
+    (VariantType::Acoustic, &["acoustic", "unplugged", "不插电"]),
+    (VariantType::Remaster, &["remaster", "remastered", "重制"]),
+    (VariantType::Demo, &["demo", "小样"]),
+];
+
+pub(crate) const BRACKETS: [(char, char); 4] = [('(', ')'), ('（', '）'), ('[', ']'), ('【', '】')];
+
+/// whether the keyword is a whole word of the note, so "alive" is not live.
+/// keywords with cjk characters match anywhere, those words aren't separated by spaces.
+fn contains_word(note: &str, keyword: &str) -> bool {
+    if !keyword.is_ascii() {
+        return note.contains(keyword);
+    }
+    note.match_indices(keyword).any(|(start, _)| {
+        let before = note[..start].chars().next_back();
+        let after = note[start + keyword.len()..].chars().next();
+        !before.is_some_and(|c| c.is_ascii_alphanumeric())
+            && !after.is_some_and(|c| c.is_ascii_alphanumeric())
+    })
+}
+
+impl VariantType {
+    /// detect the variant type from a single title note, e.g. the `Live` in `Lemon (Live)`
+    pub fn from_note(note: &str) -> Option<Self> {
+        let note = normalize_name(note);
+        VARIANT_KEYWORDS
+            .iter()
+            .find(|(_, keywords)| keywords.iter().any(|k| contains_word(&note, k)))
+            .map(|(variant_type, _)| variant_type.clone())
+    }
+
+    /// detect the variant type from an album name
+    /// only live and instrumental albums are trusted, other album names are too noisy
+    pub fn from_album(album: &str) -> Option<Self> {
+        match Self::from_note(album) {
+            Some(VariantType::Live) => Some(VariantType::Live),
+            Some(VariantType::Instrumental) => Some(VariantType::Instrumental),
+            _ => None,
+        }
+    }
+
+    /// split a title into the base title and its variant type,
+    /// e.g. `Lemon (Live)` -> (`Lemon`, Live), `晴天 - 伴奏` -> (`晴天`, Instrumental)
+    /// bracketed notes which are not variant markers are kept in the base title
+    pub fn split_title(title: &str) -> (String, Self) {
+        let mut variant_type = VariantType::Original;
+        let mut base = String::with_capacity(title.len());
+        let mut rest = title;
+
+        while let Some((start, open)) = rest
+            .char_indices()
+            .find(|(_, c)| BRACKETS.iter().any(|(open, _)| open == c))
+        {
+            let close = BRACKETS
+                .iter()
+                .find(|(o, _)| *o == open)
+                .map(|(_, c)| *c)
+                .unwrap_or(open);
+            let after = &rest[start + open.len_utf8()..];
+            let Some(end) = after.find(close) else {
+                break;
+            };
+            match Self::from_note(&after[..end]) {
+                Some(found) => {
+                    if variant_type == VariantType::Original {
+                        variant_type = found;
+                    }
+                    base.push_str(&rest[..start]);
+                }
+                None => base.push_str(&rest[..start + open.len_utf8() + end + close.len_utf8()]),
+            }
+            rest = &after[end + close.len_utf8()..];
+        }
+        base.push_str(rest);
+
+        if let Some(pos) = base.rfind(" - ") {
+            if let Some(found) = Self::from_note(&base[pos + 3..]) {
+                if variant_type == VariantType::Original {
+                    variant_type = found;
+                }
+                base.truncate(pos);
+            }
+        }
+
+        (base.trim().to_string(), variant_type)
+    }
+
+    /// detect the variant of a music aggregator from its title, then from its albums
+    pub fn detect(music_agg: &MusicAggregator) -> (String, Self) {
+        let (base, variant_type) = Self::split_title(&music_agg.name);
+        if variant_type != VariantType::Original {
+            return (base, variant_type);
+        }
+        let from_album = music_agg
+            .musics
+            .iter()
+            .filter_map(|music| music.album.as_deref())
+            .find_map(Self::from_album);
+        (base, from_album.unwrap_or(VariantType::Original))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SongVariant {
+    pub variant_type: VariantType,
+    pub music_aggregator: MusicAggregator,
+}
+
+/// A song with all of its versions (live, remix, instrumental...) clustered together
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SongGroup {
+    /// title without the variant notes
+    pub name: String,
+    pub artist: String,
+    pub original: Option<MusicAggregator>,
+    pub variants: Vec<SongVariant>,
+}
+
+impl SongGroup {
+    /// cluster music aggregators by base title and artist, keeping the order of first appearance
+    pub fn from_aggs(music_aggs: Vec<MusicAggregator>) -> Vec<Self> {
+        let mut index_map: HashMap<(String, String), usize> = HashMap::new();
+        let mut groups: Vec<Self> = Vec::new();
+
+        for music_agg in music_aggs {
+            let (base, variant_type) = VariantType::detect(&music_agg);
+            let key = (normalize_name(&base), normalize_name(&music_agg.artist));
+            let index = *index_map.entry(key).or_insert_with(|| {
+                groups.push(SongGroup {
+                    name: base,
+                    artist: music_agg.artist.clone(),
+                    original: None,
+                    variants: Vec::new(),
+                });
+                groups.len() - 1
+            });
+
+            let group = &mut groups[index];
+            if variant_type == VariantType::Original && group.original.is_none() {
+                group.original = Some(music_agg);
+            } else {
+                group.variants.push(SongVariant {
+                    variant_type,
+                    music_aggregator: music_agg,
+                });
+            }
+        }
+        groups
+    }
+
+    /// the aggregator to show for the collapsed group, the original if there is one
+    pub fn main(&self) -> Option<&MusicAggregator> {
+        self.original
+            .as_ref()
+            .or_else(|| self.variants.first().map(|v| &v.music_aggregator))
+    }
+
+    pub fn len(&self) -> usize {
+        self.original.iter().count() + self.variants.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// flatten the group back to music aggregators, original first
+    pub fn into_aggs(self) -> Vec<MusicAggregator> {
+        self.original
+            .into_iter()
+            .chain(self.variants.into_iter().map(|v| v.music_aggregator))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interface::{
+        artist::Artist,
+        music_aggregator::{Music, MusicAggregator},
+        server::MusicServer,
+    };
+
+    use super::*;
+
+    fn agg(name: &str, artist: &str, album: Option<&str>) -> MusicAggregator {
+        MusicAggregator::from_music(Music {
+            from_db: false,
+            server: MusicServer::Kuwo,
+            identity: format!("{}-{}", name, artist),
+            name: name.to_string(),
+            duration: None,
+            artists: vec![Artist {
+                name: artist.to_string(),
+                id: None,
+            }],
+            album: album.map(|a| a.to_string()),
+            album_id: None,
+            qualities: vec![],
+            cover: None,
+        })
+    }
+
+    #[test]
+    fn test_split_title() {
+        assert_eq!(
+            VariantType::split_title("Lemon (Live)"),
+            ("Lemon".to_string(), VariantType::Live)
+        );
+        assert_eq!(
+            VariantType::split_title("晴天 - 伴奏"),
+            ("晴天".to_string(), VariantType::Instrumental)
+        );
+        assert_eq!(
+            VariantType::split_title("打上花火（TV size）"),
+            ("打上花火".to_string(), VariantType::TvSize)
+        );
+        assert_eq!(
+            VariantType::split_title("夜曲 [Live] (Remix)"),
+            ("夜曲".to_string(), VariantType::Live)
+        );
+        assert_eq!(
+            VariantType::split_title("告白气球 (电视剧《xx》插曲)"),
+            ("告白气球 (电视剧《xx》插曲)".to_string(), VariantType::Original)
+        );
+        assert_eq!(
+            VariantType::split_title("Stayin' Alive (2011 Remastered)"),
+            ("Stayin' Alive".to_string(), VariantType::Remaster)
+        );
+
+        // keywords inside other words
+        for note in ["Alive", "Olive", "Demon", "Delivered", "Instrumentality"] {
+            assert_eq!(VariantType::from_note(note), None, "{}", note);
+        }
+        assert_eq!(VariantType::from_album("Alive 2007"), None);
+        assert_eq!(
+            VariantType::from_note("Live at Wembley"),
+            Some(VariantType::Live)
+        );
+        assert_eq!(
+            VariantType::from_note("2015演唱会live版"),
+            Some(VariantType::Live)
+        );
+    }
+
+    #[test]
+    fn test_group() {
+        let groups = SongGroup::from_aggs(vec![
+            agg("Lemon (Live)", "米津玄師", None),
+            agg("Lemon", "米津玄師", Some("Lemon")),
+            agg("Lemon - Instrumental", "米津玄師", None),
+            agg("Lemon", "米津玄師", Some("2019 TOUR 脊椎がオパールになる頃 Live")),
+            agg("馬と鹿", "米津玄師", None),
+        ]);
+        assert_eq!(groups.len(), 2);
+
+        let lemon = &groups[0];
+        assert_eq!(lemon.name, "Lemon");
+        assert_eq!(lemon.len(), 4);
+        assert_eq!(
+            lemon.main().unwrap().musics[0].album.as_deref(),
+            Some("Lemon")
+        );
+        assert_eq!(
+            lemon
+                .variants
+                .iter()
+                .map(|v| v.variant_type.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                VariantType::Live,
+                VariantType::Instrumental,
+                VariantType::Live
+            ]
+        );
+        assert!(groups[1].variants.is_empty());
+        assert_eq!(groups[1].clone().into_aggs().len(), 1);
+    }
+}
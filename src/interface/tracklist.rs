@@ -0,0 +1,510 @@
+use std::collections::HashSet;
+
+use sea_orm::{ColumnTrait as _, Condition, DatabaseConnection, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+
+use crate::data::models::music_aggregator;
+
+use super::{
+    database::get_db,
+    json::PlaylistJson,
+    music_aggregator::MusicAggregator,
+    playlist::Playlist,
+    results::{TrackImportResult, TrackMatch, TracklistImport},
+    server::MusicServer,
+    song_group::{VariantType, BRACKETS},
+    utils::normalize_name,
+};
+
+// search results checked for a track without a saved match
+const SEARCH_SIZE: u16 = 10;
+
+/// search results with a lower `confidence` are not picked
+pub const MIN_CONFIDENCE: f64 = 0.6;
+
+const FEAT_MARKERS: [&str; 4] = ["feat.", "ft.", "featuring ", "with "];
+// `/` separates title and artist in japanese tracklists
+const SEPARATORS: [(&str, bool); 6] = [
+    (" - ", false),
+    (" – ", false),
+    (" — ", false),
+    (" / ", true),
+    (" ／ ", true),
+    (" | ", true),
+];
+/// `artist-title` without spaces, only tried when no spaced separator is found
+const BARE_SEPARATORS: [&str; 2] = ["-", "—"];
+
+/// `artist` and `title` around a bare separator. one inside a latin word,
+/// like in "Anti-Hero" or "Spider-Man", doesn't separate anything.
+fn split_bare(text: &str) -> Option<(&str, &str)> {
+    BARE_SEPARATORS.iter().find_map(|separator| {
+        text.match_indices(separator).find_map(|(index, _)| {
+            let (left, right) = (&text[..index], &text[index + separator.len()..]);
+            let word = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric());
+            let joins_word = word(left.chars().next_back()) && word(right.chars().next());
+            (!joins_word && !left.trim().is_empty() && !right.trim().is_empty())
+                .then_some((left, right))
+        })
+    })
+}
+
+/// a track read from a playlist file, `resolve_tracks` matches it to a music aggregator
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackEntry {
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    /// seconds, like `Music::duration`
+    pub duration: Option<i64>,
+    pub cover: Option<String>,
+    /// ids of the track on music servers
+    pub music_ids: Vec<(MusicServer, String)>,
+}
+
+impl TrackEntry {
+    /// `artist - title`, the way playlist files and tracklists name a track
+    pub fn label(&self) -> String {
+        match self.artists.is_empty() {
+            true => self.title.clone(),
+            false => format!("{} - {}", self.artists.join("&"), self.title),
+        }
+    }
+
+    /// identity of the music aggregator the track would be saved as
+    fn identity(&self) -> String {
+        let mut artists = self.artists.clone();
+        artists.sort();
+        format!("{}#+#{}", self.title, artists.join("&")).to_lowercase()
+    }
+
+    fn has_music(&self, agg: &MusicAggregator) -> bool {
+        agg.musics.iter().any(|music| {
+            self.music_ids
+                .iter()
+                .any(|(server, id)| music.server == *server && music.identity == *id)
+        })
+    }
+}
+
+/// 0 to 1, how well a music aggregator fits the track, mostly by title and then by artists.
+/// a single artist may be the title, tracklists don't agree on the order.
+pub fn confidence(track: &TrackEntry, agg: &MusicAggregator) -> f64 {
+    let (agg_title, agg_variant) = VariantType::detect(agg);
+    let agg_title = normalize_name(&agg_title);
+    let agg_artists: HashSet<String> = agg
+        .musics
+        .iter()
+        .flat_map(|music| music.artists.iter().map(|artist| artist.name.clone()))
+        .chain(split_artists(&agg.artist))
+        .map(|artist| normalize_name(&artist))
+        .collect();
+    let score = |title: &str, artists: &[String]| {
+        let (title, variant) = VariantType::split_title(title);
+        let mut title_score = similarity(&normalize_name(&title), &agg_title);
+        if variant != agg_variant {
+            title_score *= 0.7;
+        }
+        let artist_score = match artists.is_empty() {
+            true => 0.5,
+            false => {
+                artists
+                    .iter()
+                    .map(|artist| {
+                        let artist = normalize_name(artist);
+                        agg_artists
+                            .iter()
+                            .map(|agg_artist| similarity(&artist, agg_artist))
+                            .fold(0.0, f64::max)
+                    })
+                    .sum::<f64>()
+                    / artists.len() as f64
+            }
+        };
+        0.6 * title_score + 0.4 * artist_score
+    };
+    let mut best = score(&track.title, &track.artists);
+    if let [artist] = track.artists.as_slice() {
+        best = best.max(score(artist, std::slice::from_ref(&track.title)));
+    }
+    best
+}
+
+/// dice coefficient of the character bigrams, 1 for equal names
+fn similarity(a: &str, b: &str) -> f64 {
+    if a == b {
+        return 1.0;
+    }
+    let bigrams = |name: &str| -> Vec<(char, char)> {
+        let chars: Vec<char> = name.chars().filter(|c| !c.is_whitespace()).collect();
+        match chars.len() {
+            1 => vec![(chars[0], ' ')],
+            _ => chars.windows(2).map(|w| (w[0], w[1])).collect(),
+        }
+    };
+    let (a, mut b) = (bigrams(a), bigrams(b));
+    let len = a.len() + b.len();
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let mut common = 0;
+    for bigram in a {
+        if let Some(index) = b.iter().position(|other| *other == bigram) {
+            b.swap_remove(index);
+            common += 1;
+        }
+    }
+    2.0 * common as f64 / len as f64
+}
+
+/// a line of a pasted tracklist like `周杰伦 - 晴天` or `1. Lemon / 米津玄師`.
+/// numbering is dropped, `feat.` artists are added and bracketed notes are dropped
+/// unless they mark a variant like `(Live)`.
+pub fn parse_tracklist_line(line: &str) -> Option<TrackEntry> {
+    let line = strip_numbering(line.trim());
+    let mut featured = Vec::new();
+    let mut rest = line;
+    let mut text = String::with_capacity(line.len());
+    while let Some((start, open)) = rest
+        .char_indices()
+        .find(|(_, c)| BRACKETS.iter().any(|(open, _)| open == c))
+    {
+        let close = BRACKETS
+            .iter()
+            .find(|(o, _)| *o == open)
+            .map(|(_, c)| *c)
+            .unwrap_or(open);
+        let after = &rest[start + open.len_utf8()..];
+        let Some(end) = after.find(close) else {
+            break;
+        };
+        let note = &after[..end];
+        text.push_str(&rest[..start]);
+        if let Some(artists) = strip_feat(note) {
+            featured.extend(split_artists(artists));
+        } else if VariantType::from_note(note).is_some() {
+            text.push_str(&rest[start..start + open.len_utf8() + end + close.len_utf8()]);
+        }
+        rest = &after[end + close.len_utf8()..];
+    }
+    text.push_str(rest);
+
+    let (title, artists) = match SEPARATORS
+        .iter()
+        .find_map(|(separator, title_first)| Some((text.split_once(separator)?, title_first)))
+    {
+        Some(((left, right), true)) => (left, right),
+        Some(((left, right), false)) => (right, left),
+        None => match split_bare(&text) {
+            Some((left, right)) => (right, left),
+            None => (text.as_str(), ""),
+        },
+    };
+    let (title, title_featured) = split_feat(title);
+    let (artists, artists_featured) = split_feat(artists);
+    let mut artists = split_artists(artists);
+    for artist in featured
+        .into_iter()
+        .chain(title_featured)
+        .chain(artists_featured)
+    {
+        if !artists.contains(&artist) {
+            artists.push(artist);
+        }
+    }
+    let title = title
+        .trim()
+        .trim_matches(['《', '》', '"', '“', '”'])
+        .trim();
+    (!title.is_empty()).then(|| TrackEntry {
+        title: title.to_string(),
+        artists,
+        ..Default::default()
+    })
+}
+
+/// tracks of a pasted tracklist, one per non-empty line
+pub fn parse_tracklist(text: &str) -> Vec<TrackEntry> {
+    text.lines().filter_map(parse_tracklist_line).collect()
+}
+
+/// `1.`, `01)`, `3、`, `#4`, bullets and `03:25` timestamps
+fn strip_numbering(line: &str) -> &str {
+    let hash = line.starts_with('#');
+    let line = line
+        .trim_start_matches(['-', '*', '•', '·', '#'])
+        .trim_start();
+    let number = line.trim_start_matches(|c: char| c.is_ascii_digit() || c == ':');
+    if number.len() == line.len() {
+        return line;
+    }
+    if let Some(rest) = number.strip_prefix(['.', ')', '、', '．', '）']) {
+        return rest.trim_start();
+    }
+    let timestamp = &line[..line.len() - number.len()];
+    match (hash || timestamp.contains(':')) && number.starts_with(char::is_whitespace) {
+        true => number.trim_start(),
+        false => line,
+    }
+}
+
+/// the artists of a `feat. A & B` note
+fn strip_feat(note: &str) -> Option<&str> {
+    let lower = note.trim().to_lowercase();
+    FEAT_MARKERS
+        .iter()
+        .find(|marker| lower.starts_with(*marker))
+        .map(|marker| note.trim()[marker.len()..].trim())
+}
+
+/// `A feat. B` into `A` and the featured artists
+fn split_feat(text: &str) -> (&str, Vec<String>) {
+    let lower = text.to_lowercase();
+    FEAT_MARKERS
+        .iter()
+        .filter(|marker| !marker.starts_with("with"))
+        .find_map(|marker| lower.find(&format!(" {}", marker)))
+        // lowercasing keeps the byte offsets of ascii markers only if the text before is unchanged
+        .filter(|pos| text.is_char_boundary(*pos) && lower.len() == text.len())
+        .map(|pos| {
+            let featured = strip_feat(&text[pos..]).unwrap_or_default();
+            (&text[..pos], split_artists(featured))
+        })
+        .unwrap_or((text, Vec::new()))
+}
+
+/// `kuwo:music:<id>`, locates a music in exported playlist files
+pub fn music_uri(server: &MusicServer, id: &str) -> String {
+    format!("{}:music:{}", server.to_string().to_lowercase(), id)
+}
+
+/// the server and id of a `music_uri`
+pub fn parse_music_uri(uri: &str) -> Option<(MusicServer, String)> {
+    let (server, id) = uri.trim().split_once(":music:")?;
+    let server = MusicServer::all()
+        .into_iter()
+        .find(|s| s.to_string().eq_ignore_ascii_case(server))?;
+    (!id.is_empty()).then(|| (server, id.to_string()))
+}
+
+/// split an artist field like `A & B, C`, music aggregators join their artists with `&`
+pub(crate) fn split_artists(artists: &str) -> Vec<String> {
+    artists
+        .split(['&', ',', '、', ';'])
+        .map(str::trim)
+        .filter(|artist| !artist.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// match tracks to music aggregators. saved ones are found by music id or identity,
+/// others are searched online: a result with one of the music ids wins, then the one with
+/// the best `confidence`. the music aggregators keep the order of the tracks.
+pub async fn resolve_tracks(tracks: Vec<TrackEntry>) -> anyhow::Result<TrackImportResult> {
+    let db = get_db()
+        .await
+        .ok_or(anyhow::anyhow!("Database is not inited."))?;
+    let mut result = TrackImportResult::default();
+    for track in tracks {
+        let agg = match find_saved(&db, &track).await? {
+            Some(agg) => Some(agg),
+            None => search(&track)
+                .await
+                .filter(|(_, confidence)| *confidence >= MIN_CONFIDENCE)
+                .map(|(agg, _)| agg),
+        };
+        match agg {
+            Some(agg) => result.music_aggs.push(agg),
+            None => result.unresolved.push(track.label()),
+        }
+    }
+    Ok(result)
+}
+
+async fn find_saved(
+    db: &DatabaseConnection,
+    track: &TrackEntry,
+) -> anyhow::Result<Option<MusicAggregator>> {
+    let mut by_music_id = Condition::any();
+    for (server, id) in &track.music_ids {
+        // todo: add more music server
+        by_music_id = by_music_id.add(match server {
+            MusicServer::Kuwo => music_aggregator::Column::KuwoMusicId.eq(id),
+            MusicServer::Netease => music_aggregator::Column::NeteaseMusicId.eq(id),
+        });
+    }
+    let mut model = None;
+    if !track.music_ids.is_empty() {
+        model = music_aggregator::Entity::find()
+            .filter(by_music_id)
+            .one(db)
+            .await?;
+    }
+    if model.is_none() && !track.title.is_empty() {
+        model = music_aggregator::Entity::find_by_id(track.identity())
+            .one(db)
+            .await?;
+    }
+    let Some(model) = model else {
+        return Ok(None);
+    };
+    Ok(
+        music_aggregator::Model::get_music_aggregators(db, vec![(model, 0)])
+            .await?
+            .pop()
+            .filter(|agg| !agg.musics.is_empty()),
+    )
+}
+
+/// search a tracklist online, the best result of each line is picked if its `confidence`
+/// reaches `MIN_CONFIDENCE`. nothing is saved: review the matches, then apply the playlist
+/// with `MusicDataJson::Playlists`.
+pub async fn resolve_tracklist(text: &str, name: String) -> anyhow::Result<TracklistImport> {
+    let mut music_aggregators: Vec<MusicAggregator> = Vec::new();
+    let mut matches = Vec::new();
+    for line in text.lines() {
+        let Some(track) = parse_tracklist_line(line) else {
+            continue;
+        };
+        let (candidate, confidence) = match search(&track).await {
+            Some((agg, confidence)) => (Some(agg), confidence),
+            None => (None, 0.0),
+        };
+        if let Some(agg) = candidate.as_ref().filter(|_| confidence >= MIN_CONFIDENCE) {
+            if !music_aggregators
+                .iter()
+                .any(|picked| picked.identity() == agg.identity())
+            {
+                music_aggregators.push(agg.clone());
+            }
+        }
+        matches.push(TrackMatch {
+            line: line.trim().to_string(),
+            track,
+            candidate,
+            confidence,
+        });
+    }
+    Ok(TracklistImport {
+        playlist: PlaylistJson {
+            playlist: Playlist::new(name, None, None, vec![]),
+            music_aggregators,
+        },
+        matches,
+    })
+}
+
+/// the best search result and its confidence, a result with one of the music ids wins
+async fn search(track: &TrackEntry) -> Option<(MusicAggregator, f64)> {
+    if track.title.is_empty() {
+        return None;
+    }
+    let content = format!("{} {}", track.title, track.artists.join(" "));
+    let mut aggs = match MusicAggregator::search_online(
+        vec![],
+        MusicServer::all(),
+        content.trim().to_string(),
+        1,
+        SEARCH_SIZE,
+    )
+    .await
+    {
+        Ok(aggs) => aggs,
+        Err(e) => {
+            log::error!("Failed to search {}: {}", track.label(), e);
+            return None;
+        }
+    };
+    let (index, confidence) = best_match(track, &aggs)?;
+    Some((aggs.swap_remove(index), confidence))
+}
+
+/// index and confidence of the music aggregator fitting the track best, the first one on ties.
+/// one with a music id of the track always wins.
+fn best_match(track: &TrackEntry, aggs: &[MusicAggregator]) -> Option<(usize, f64)> {
+    if let Some(index) = aggs.iter().position(|agg| track.has_music(agg)) {
+        return Some((index, 1.0));
+    }
+    let mut best: Option<(usize, f64)> = None;
+    for (index, agg) in aggs.iter().enumerate() {
+        let confidence = confidence(track, agg);
+        if best.is_none_or(|(_, best)| confidence > best) {
+            best = Some((index, confidence));
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interface::music_aggregator::music_agg_by;
+
+    fn music_agg(name: &str, artist: &str) -> MusicAggregator {
+        music_agg_by(MusicServer::Kuwo, name, name, artist)
+    }
+
+    #[test]
+    fn test_parse_tracklist() {
+        let tracks = parse_tracklist(
+            "1. 周杰伦 - 晴天\n\n02) Lemon / 米津玄師 [MV]\n#3 Artist feat. Guest – Song (Live)\n\
+             03:25 A & B - 《Title》 (ft. C)\n• 7 rings\n",
+        );
+        assert_eq!(
+            tracks
+                .iter()
+                .map(|track| (track.title.as_str(), track.artists.join("&")))
+                .collect::<Vec<_>>(),
+            vec![
+                ("晴天", "周杰伦".to_string()),
+                ("Lemon", "米津玄師".to_string()),
+                ("Song (Live)", "Artist&Guest".to_string()),
+                ("Title", "A&B&C".to_string()),
+                ("7 rings", String::new()),
+            ]
+        );
+
+        // a hyphen inside a word is part of the title
+        let tracks =
+            parse_tracklist("Anti-Hero\nTaylor Swift - Anti-Hero\nSpider-Man\n周杰伦-晴天\n");
+        assert_eq!(
+            tracks
+                .iter()
+                .map(|track| (track.title.as_str(), track.artists.join("&")))
+                .collect::<Vec<_>>(),
+            vec![
+                ("Anti-Hero", String::new()),
+                ("Anti-Hero", "Taylor Swift".to_string()),
+                ("Spider-Man", String::new()),
+                ("晴天", "周杰伦".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_best_match() {
+        let aggs = vec![
+            music_agg("晴天 (Live)", "周杰伦"),
+            music_agg("晴天", "周杰伦"),
+            music_agg("雨天", "孙燕姿"),
+        ];
+        let track = parse_tracklist_line("周杰伦 - 晴天").unwrap();
+        assert_eq!(best_match(&track, &aggs).map(|(index, _)| index), Some(1));
+        assert_eq!(best_match(&track, &aggs).unwrap().1, 1.0);
+
+        // artist and title in the other order
+        let track = parse_tracklist_line("晴天 - 周杰伦").unwrap();
+        assert_eq!(best_match(&track, &aggs).unwrap(), (1, 1.0));
+
+        // a music id beats a better name
+        let mut track = parse_tracklist_line("周杰伦 - 晴天").unwrap();
+        track
+            .music_ids
+            .push((MusicServer::Kuwo, "雨天".to_string()));
+        assert_eq!(best_match(&track, &aggs).unwrap(), (2, 1.0));
+
+        let track = parse_tracklist_line("Unknown - Nothing").unwrap();
+        assert!(best_match(&track, &aggs).unwrap().1 < MIN_CONFIDENCE);
+        assert!(best_match(&track, &[]).is_none());
+    }
+}
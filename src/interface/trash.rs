@@ -0,0 +1,371 @@
+use std::collections::BTreeSet;
+
+use sea_orm::{
+    prelude::Expr, sea_query::Query, ColumnTrait as _, Condition, EntityTrait, QueryFilter,
+    QueryOrder as _, TransactionTrait as _,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::data::models::{
+    music_aggregator, playlist, playlist_collection, playlist_music_junction,
+};
+
+use super::{
+    database::get_db,
+    journal::{forget_rows, junction_key, Journal, JournalScope, JournalTable},
+    music_aggregator::MusicAggregator,
+    playlist::Playlist,
+    playlist_collection::PlaylistCollection,
+    utils::now_millis,
+};
+
+/// something deleted from the db, `deleted_at` is unix time in milliseconds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrashItem {
+    /// a collection comes back with the playlists it had when it was deleted
+    PlaylistCollection {
+        collection: PlaylistCollection,
+        deleted_at: i64,
+    },
+    Playlist {
+        playlist: Playlist,
+        deleted_at: i64,
+    },
+    /// a music aggregator removed from a playlist
+    MusicAggregator {
+        playlist_id: i64,
+        music_agg: MusicAggregator,
+        deleted_at: i64,
+    },
+}
+
+impl TrashItem {
+    pub fn deleted_at(&self) -> i64 {
+        match self {
+            TrashItem::PlaylistCollection { deleted_at, .. }
+            | TrashItem::Playlist { deleted_at, .. }
+            | TrashItem::MusicAggregator { deleted_at, .. } => *deleted_at,
+        }
+    }
+}
+
+/// Deleted playlist collections, playlists and playlist music stay here until they are purged
+pub struct Trash;
+
+impl Trash {
+    /// everything in the trash, the most recently deleted first
+    pub async fn get_from_db() -> anyhow::Result<Vec<TrashItem>> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+
+        let mut items = Vec::new();
+        for model in playlist_collection::Entity::find()
+            .filter(playlist_collection::Column::DeletedAt.is_not_null())
+            .all(&db)
+            .await?
+        {
+            let deleted_at = model.deleted_at.unwrap_or_default();
+            items.push(TrashItem::PlaylistCollection {
+                collection: model.into(),
+                deleted_at,
+            });
+        }
+        for model in playlist::Entity::find()
+            .filter(playlist::Column::DeletedAt.is_not_null())
+            .all(&db)
+            .await?
+        {
+            let deleted_at = model.deleted_at.unwrap_or_default();
+            items.push(TrashItem::Playlist {
+                playlist: model.into(),
+                deleted_at,
+            });
+        }
+
+        let rows = playlist_music_junction::Entity::find()
+            .filter(playlist_music_junction::Column::DeletedAt.is_not_null())
+            .order_by_asc(playlist_music_junction::Column::PlaylistId)
+            .order_by_asc(playlist_music_junction::Column::Order)
+            .find_also_related(music_aggregator::Entity)
+            .all(&db)
+            .await?;
+        let mut junctions = Vec::with_capacity(rows.len());
+        let mut models = Vec::with_capacity(rows.len());
+        for (junction, agg) in rows {
+            let agg = agg.ok_or(anyhow::anyhow!("Can't find music aggregator in db"))?;
+            models.push((agg, junction.order));
+            junctions.push(junction);
+        }
+        let aggs = music_aggregator::Model::get_music_aggregators(&db, models).await?;
+        for (junction, music_agg) in junctions.into_iter().zip(aggs) {
+            items.push(TrashItem::MusicAggregator {
+                playlist_id: junction.playlist_id,
+                music_agg,
+                deleted_at: junction.deleted_at.unwrap_or_default(),
+            });
+        }
+
+        items.sort_by_key(|item| std::cmp::Reverse(item.deleted_at()));
+        Ok(items)
+    }
+
+    /// take an item out of the trash.
+    /// a playlist in a deleted collection can only come back after the collection.
+    pub async fn restore(item: &TrashItem) -> anyhow::Result<()> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+
+        let txn = db.begin().await?;
+        let scope = match item {
+            TrashItem::PlaylistCollection { collection, .. } => {
+                JournalScope::collection(collection.id)
+            }
+            TrashItem::Playlist { playlist, .. } => {
+                JournalScope::playlist(playlist.identity.parse::<i64>()?)
+            }
+            TrashItem::MusicAggregator { playlist_id, .. } => JournalScope::playlist(*playlist_id),
+        };
+        let journal = Journal::begin(&txn, scope).await?;
+        match item {
+            TrashItem::PlaylistCollection { collection, .. } => {
+                playlist_collection::Entity::update_many()
+                    .col_expr(
+                        playlist_collection::Column::DeletedAt,
+                        Expr::value(Option::<i64>::None),
+                    )
+                    .col_expr(
+                        playlist_collection::Column::UpdatedAt,
+                        Expr::value(now_millis()),
+                    )
+                    .filter(playlist_collection::Column::Id.eq(collection.id))
+                    .exec(&txn)
+                    .await?;
+            }
+            TrashItem::Playlist { playlist, .. } => {
+                let id = playlist.identity.parse::<i64>()?;
+                let collection_id = playlist
+                    .collection_id
+                    .ok_or(anyhow::anyhow!("Playlist has no collection."))?;
+                let collection_deleted = playlist_collection::Entity::find_by_id(collection_id)
+                    .filter(playlist_collection::Column::DeletedAt.is_not_null())
+                    .one(&txn)
+                    .await?
+                    .is_some();
+                if collection_deleted {
+                    return Err(anyhow::anyhow!(
+                        "Restore the playlist collection of this playlist first."
+                    ));
+                }
+                playlist::Entity::update_many()
+                    .col_expr(
+                        playlist::Column::DeletedAt,
+                        Expr::value(Option::<i64>::None),
+                    )
+                    .col_expr(playlist::Column::UpdatedAt, Expr::value(now_millis()))
+                    .filter(playlist::Column::Id.eq(id))
+                    .exec(&txn)
+                    .await?;
+            }
+            TrashItem::MusicAggregator {
+                playlist_id,
+                music_agg,
+                ..
+            } => {
+                playlist_music_junction::Entity::update_many()
+                    .col_expr(
+                        playlist_music_junction::Column::DeletedAt,
+                        Expr::value(Option::<i64>::None),
+                    )
+                    .col_expr(
+                        playlist_music_junction::Column::UpdatedAt,
+                        Expr::value(now_millis()),
+                    )
+                    .filter(playlist_music_junction::Column::PlaylistId.eq(*playlist_id))
+                    .filter(
+                        playlist_music_junction::Column::MusicAggregatorId.eq(music_agg.identity()),
+                    )
+                    .exec(&txn)
+                    .await?;
+            }
+        }
+        journal.commit(&txn, "Restore from trash").await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// permanently delete everything moved to the trash before `older_than` (unix time in milliseconds),
+    /// returns the number of purged items.
+    /// purging a collection or playlist also deletes what is in it.
+    /// it can't be undone, the undo history of the purged rows is dropped.
+    pub async fn purge_trash(older_than: i64) -> anyhow::Result<u64> {
+        let db = get_db()
+            .await
+            .ok_or(anyhow::anyhow!("Database is not inited."))?;
+
+        let txn = db.begin().await?;
+        // the rows going away, with the ones the deletes cascade to
+        let purged_collections = Query::select()
+            .column(playlist_collection::Column::Id)
+            .from(playlist_collection::Entity)
+            .and_where(playlist_collection::Column::DeletedAt.lt(older_than))
+            .to_owned();
+        let purged_playlists = Query::select()
+            .column(playlist::Column::Id)
+            .from(playlist::Entity)
+            .cond_where(
+                Condition::any()
+                    .add(playlist::Column::DeletedAt.lt(older_than))
+                    .add(playlist::Column::CollectionId.in_subquery(purged_collections.clone())),
+            )
+            .to_owned();
+        let mut rows = BTreeSet::new();
+        for model in playlist_collection::Entity::find()
+            .filter(playlist_collection::Column::Id.in_subquery(purged_collections))
+            .all(&txn)
+            .await?
+        {
+            rows.insert((JournalTable::PlaylistCollection, model.id.to_string()));
+        }
+        for model in playlist::Entity::find()
+            .filter(playlist::Column::Id.in_subquery(purged_playlists.clone()))
+            .all(&txn)
+            .await?
+        {
+            rows.insert((JournalTable::Playlist, model.id.to_string()));
+        }
+        for model in playlist_music_junction::Entity::find()
+            .filter(
+                Condition::any()
+                    .add(playlist_music_junction::Column::DeletedAt.lt(older_than))
+                    .add(playlist_music_junction::Column::PlaylistId.in_subquery(purged_playlists)),
+            )
+            .all(&txn)
+            .await?
+        {
+            rows.insert((
+                JournalTable::PlaylistMusicJunction,
+                junction_key(model.playlist_id, &model.music_aggregator_id),
+            ));
+        }
+        forget_rows(&txn, &rows).await?;
+
+        let mut purged = playlist_music_junction::Entity::delete_many()
+            .filter(playlist_music_junction::Column::DeletedAt.lt(older_than))
+            .exec(&txn)
+            .await?
+            .rows_affected;
+        purged += playlist::Entity::delete_many()
+            .filter(playlist::Column::DeletedAt.lt(older_than))
+            .exec(&txn)
+            .await?
+            .rows_affected;
+        purged += playlist_collection::Entity::delete_many()
+            .filter(playlist_collection::Column::DeletedAt.lt(older_than))
+            .exec(&txn)
+            .await?
+            .rows_affected;
+        txn.commit().await?;
+        Ok(purged)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serial_test::serial;
+
+    use crate::interface::{
+        library::Library,
+        music_aggregator::{empty_playlist, music_agg},
+        server::MusicServer,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    #[serial]
+    async fn test_trash() {
+        let (collection_id, playlist) = empty_playlist().await;
+        let playlist_id = playlist.identity.parse::<i64>().unwrap();
+        playlist
+            .add_aggs_to_db(&vec![
+                music_agg(MusicServer::Kuwo, "a", "a"),
+                music_agg(MusicServer::Kuwo, "b", "b"),
+            ])
+            .await
+            .unwrap();
+
+        // music
+        playlist
+            .del_music_agg(music_agg(MusicServer::Kuwo, "a", "a").identity())
+            .await
+            .unwrap();
+        assert_eq!(playlist.count_musics_in_db().await.unwrap(), 1);
+        let trash = Trash::get_from_db().await.unwrap();
+        assert_eq!(trash.len(), 1);
+        Trash::restore(&trash[0]).await.unwrap();
+        assert_eq!(playlist.count_musics_in_db().await.unwrap(), 2);
+        // adding trashed music again takes it out of the trash
+        playlist
+            .del_music_agg(music_agg(MusicServer::Kuwo, "a", "a").identity())
+            .await
+            .unwrap();
+        playlist
+            .add_aggs_to_db(&vec![music_agg(MusicServer::Kuwo, "a", "a")])
+            .await
+            .unwrap();
+        assert_eq!(playlist.count_musics_in_db().await.unwrap(), 2);
+        assert!(Trash::get_from_db().await.unwrap().is_empty());
+
+        // playlist in a collection
+        playlist.clone().del_from_db().await.unwrap();
+        assert!(Playlist::find_in_db(playlist_id).await.is_none());
+        let collection = PlaylistCollection::find_in_db(collection_id).await.unwrap();
+        collection.delete_from_db().await.unwrap();
+        assert!(PlaylistCollection::get_form_db().await.unwrap().is_empty());
+
+        let trash = Trash::get_from_db().await.unwrap();
+        assert_eq!(trash.len(), 2);
+        let playlist_item = trash
+            .iter()
+            .find(|item| matches!(item, TrashItem::Playlist { .. }))
+            .unwrap();
+        let collection_item = trash
+            .iter()
+            .find(|item| matches!(item, TrashItem::PlaylistCollection { .. }))
+            .unwrap();
+        assert!(Trash::restore(playlist_item).await.is_err());
+        Trash::restore(collection_item).await.unwrap();
+        assert!(Playlist::get_from_db().await.unwrap().is_empty());
+        Trash::restore(playlist_item).await.unwrap();
+        assert_eq!(Playlist::get_from_db().await.unwrap().len(), 1);
+        assert_eq!(playlist.get_musics_from_db().await.unwrap().len(), 2);
+
+        // purge
+        playlist
+            .del_music_agg(music_agg(MusicServer::Kuwo, "b", "b").identity())
+            .await
+            .unwrap();
+        playlist.clone().del_from_db().await.unwrap();
+        assert_eq!(Trash::purge_trash(0).await.unwrap(), 0);
+        assert_eq!(Trash::purge_trash(now_millis() + 1).await.unwrap(), 2);
+        assert!(Trash::get_from_db().await.unwrap().is_empty());
+        assert_eq!(
+            playlist_music_junction::Entity::find()
+                .all(&get_db().await.unwrap())
+                .await
+                .unwrap()
+                .len(),
+            0
+        );
+        // purged rows don't come back with undo
+        while Library::undo().await.unwrap().is_some() {}
+        assert!(Playlist::find_in_db(playlist_id).await.is_none());
+        assert!(playlist_music_junction::Entity::find()
+            .all(&get_db().await.unwrap())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
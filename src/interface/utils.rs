@@ -1,4 +1,10 @@
-use sea_orm::{prelude::Expr, Condition, DatabaseConnection, EntityTrait, QueryFilter};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use sea_orm::{prelude::Expr, Condition, ConnectionTrait, EntityTrait, QueryFilter};
 
 use crate::data::models::music_aggregator;
 
@@ -14,8 +20,76 @@ pub(crate) fn split_string(input: &str) -> anyhow::Result<(String, String)> {
     Ok((parts[0].to_string(), parts[1].to_string()))
 }
 
-pub(crate) async fn find_duplicate_music_agg(
-    db: &DatabaseConnection,
+/// current unix time in milliseconds, the unit of every `created_at`/`updated_at` column
+pub(crate) fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// normalize a song/artist/album name for fuzzy comparison:
+/// full-width chars are folded to half-width, case is ignored and whitespace is collapsed
+pub(crate) fn normalize_name(input: &str) -> String {
+    input
+        .chars()
+        .map(|c| match c {
+            '\u{3000}' => ' ',
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            _ => c,
+        })
+        .collect::<String>()
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// move the item at `from` to `to`, the items in between shift by one
+pub(crate) fn move_item<T>(items: &mut Vec<T>, from: usize, to: usize) -> anyhow::Result<()> {
+    if from >= items.len() || to >= items.len() {
+        return Err(anyhow::anyhow!(
+            "Can't move item from {} to {}, there are only {} items.",
+            from,
+            to,
+            items.len()
+        ));
+    }
+    let item = items.remove(from);
+    items.insert(to, item);
+    Ok(())
+}
+
+/// sort `items` by the position of their key in `keys`,
+/// `keys` must contain the key of every item exactly once
+pub(crate) fn reorder_by_keys<T, K: Eq + Hash>(
+    items: Vec<T>,
+    keys: &[K],
+    key: impl Fn(&T) -> K,
+) -> anyhow::Result<Vec<T>> {
+    if keys.len() != items.len() {
+        return Err(anyhow::anyhow!(
+            "Expected {} items to reorder, got {}.",
+            items.len(),
+            keys.len()
+        ));
+    }
+    let positions: HashMap<&K, usize> = keys.iter().enumerate().map(|(i, k)| (k, i)).collect();
+    let mut slots: Vec<Option<T>> = (0..items.len()).map(|_| None).collect();
+    for item in items {
+        let position = *positions
+            .get(&key(&item))
+            .ok_or(anyhow::anyhow!("Reorder keys don't match the items."))?;
+        slots[position] = Some(item);
+    }
+    slots
+        .into_iter()
+        .collect::<Option<Vec<T>>>()
+        .ok_or(anyhow::anyhow!("Reorder keys contain duplicates."))
+}
+
+pub(crate) async fn find_duplicate_music_agg<C: ConnectionTrait>(
+    db: &C,
     music_agg: &MusicAggregator,
 ) -> Option<String> {
     if music_agg.musics.is_empty() {
@@ -0,0 +1,526 @@
+use anyhow::{anyhow, Result};
+
+use super::{
+    artist::Artist,
+    json::{MusicDataJson, PlaylistJson, PlaylistJsonVec},
+    music_aggregator::{Music, MusicAggregator},
+    playlist::Playlist,
+    server::MusicServer,
+    tracklist::{music_uri, parse_music_uri, split_artists},
+};
+
+// `rel` of the `<meta>` elements, a track has one music meta per server
+const DEFAULT_SERVER_REL: &str = "music_api:default_server";
+const MUSIC_REL: &str = "music_api:music";
+// nesting of xml elements, deeper files are refused instead of overflowing the stack
+const MAX_XML_DEPTH: usize = 64;
+
+impl Playlist {
+    /// export as XSPF, db playlists use their saved music and online ones are fetched
+    pub async fn to_xspf(&self) -> Result<String> {
+        let music_aggregators = match self.from_db {
+            true => self.get_musics_from_db().await?,
+            false => self.fetch_musics_online(1, 2333).await?,
+        };
+        Ok(PlaylistJson {
+            playlist: self.clone(),
+            music_aggregators,
+        }
+        .to_xspf())
+    }
+}
+
+impl PlaylistJson {
+    /// the default music of a track fills the XSPF elements, every music is kept as an
+    /// `<identifier>` and a `<meta>` holding it as json
+    pub fn to_xspf(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+        let playlist = &self.playlist;
+        push_element(&mut xml, 1, "title", &playlist.name);
+        if let Some(creator) = &playlist.creator {
+            push_element(&mut xml, 1, "creator", creator);
+        }
+        if let Some(summary) = &playlist.summary {
+            push_element(&mut xml, 1, "annotation", summary);
+        }
+        if let Some(cover) = &playlist.cover {
+            push_element(&mut xml, 1, "image", cover);
+        }
+        xml.push_str("  <trackList>\n");
+        for agg in &self.music_aggregators {
+            let Some(music) = agg
+                .musics
+                .iter()
+                .find(|music| music.server == agg.default_server)
+                .or(agg.musics.first())
+            else {
+                continue;
+            };
+            xml.push_str("    <track>\n");
+            push_element(
+                &mut xml,
+                3,
+                "location",
+                &music_uri(&music.server, &music.identity),
+            );
+            for music in &agg.musics {
+                push_element(
+                    &mut xml,
+                    3,
+                    "identifier",
+                    &music_uri(&music.server, &music.identity),
+                );
+            }
+            push_element(&mut xml, 3, "title", &agg.name);
+            push_element(&mut xml, 3, "creator", &agg.artist);
+            if let Some(album) = &music.album {
+                push_element(&mut xml, 3, "album", album);
+            }
+            if let Some(duration) = music.duration {
+                push_element(&mut xml, 3, "duration", &(duration * 1000).to_string());
+            }
+            if let Some(cover) = &music.cover {
+                push_element(&mut xml, 3, "image", cover);
+            }
+            xml.push_str(&format!(
+                "      <meta rel=\"{}\">{}</meta>\n",
+                DEFAULT_SERVER_REL,
+                escape(&agg.default_server.to_string())
+            ));
+            for music in &agg.musics {
+                xml.push_str(&format!(
+                    "      <meta rel=\"{}\">{}</meta>\n",
+                    MUSIC_REL,
+                    escape(&serde_json::to_string(music).unwrap_or_default())
+                ));
+            }
+            xml.push_str("    </track>\n");
+        }
+        xml.push_str("  </trackList>\n</playlist>\n");
+        xml
+    }
+
+    /// rebuild a playlist from XSPF, apply it with `MusicDataJson::Playlists`.
+    /// tracks of other players only carry their identifiers, their music is made of
+    /// the track elements. tracks without a music id are left out.
+    pub fn from_xspf(xml: &str) -> Result<Self> {
+        let root = XmlReader { xml, pos: 0 }.document()?;
+        if root.name != "playlist" {
+            return Err(anyhow!("XSPF root element is {}, not playlist.", root.name));
+        }
+        let mut playlist = Playlist::new(
+            root.child_text("title").unwrap_or_default(),
+            root.child_text("annotation"),
+            root.child_text("image"),
+            vec![],
+        );
+        playlist.creator = root.child_text("creator");
+
+        let tracks = root
+            .child("trackList")
+            .map(|list| list.children.iter().filter(|e| e.name == "track"))
+            .into_iter()
+            .flatten();
+        let mut music_aggregators = Vec::new();
+        for track in tracks {
+            if let Some(agg) = track_to_music_agg(track)? {
+                music_aggregators.push(agg);
+            }
+        }
+        Ok(PlaylistJson {
+            playlist,
+            music_aggregators,
+        })
+    }
+}
+
+impl MusicDataJson {
+    /// a playlist read from XSPF, see `PlaylistJson::from_xspf`
+    pub fn from_xspf(xml: &str) -> Result<Self> {
+        Ok(MusicDataJson::Playlists(PlaylistJsonVec(vec![
+            PlaylistJson::from_xspf(xml)?,
+        ])))
+    }
+}
+
+fn track_to_music_agg(track: &XmlElement) -> Result<Option<MusicAggregator>> {
+    let mut musics: Vec<Music> = Vec::new();
+    let mut default_server = None;
+    for meta in track.children.iter().filter(|e| e.name == "meta") {
+        match meta.attribute("rel") {
+            Some(DEFAULT_SERVER_REL) => {
+                default_server = MusicServer::all()
+                    .into_iter()
+                    .find(|server| server.to_string() == meta.text.trim());
+            }
+            Some(MUSIC_REL) => musics.push(serde_json::from_str(&meta.text)?),
+            _ => {}
+        }
+    }
+
+    let name = track.child_text("title").unwrap_or_default();
+    let artist = track.child_text("creator").unwrap_or_default();
+    // music ids the metas don't describe
+    for (server, identity) in track
+        .children
+        .iter()
+        .filter(|e| e.name == "identifier" || e.name == "location")
+        .filter_map(|e| parse_music_uri(&e.text))
+    {
+        if musics.iter().any(|music| music.server == server) {
+            continue;
+        }
+        musics.push(Music {
+            from_db: false,
+            server,
+            identity,
+            name: name.clone(),
+            duration: track
+                .child_text("duration")
+                .and_then(|duration| duration.parse::<i64>().ok())
+                .map(|duration| duration / 1000),
+            artists: split_artists(&artist)
+                .into_iter()
+                .map(|name| Artist { name, id: None })
+                .collect(),
+            album: track.child_text("album"),
+            album_id: None,
+            qualities: vec![],
+            cover: track.child_text("image"),
+        });
+    }
+    let Some(first) = musics.first() else {
+        return Ok(None);
+    };
+
+    let mut agg = MusicAggregator::from_music(first.clone());
+    if !name.is_empty() {
+        agg.name = name;
+        agg.artist = artist;
+    }
+    agg.default_server = default_server
+        .filter(|server| musics.iter().any(|music| music.server == *server))
+        .unwrap_or(first.server.clone());
+    agg.musics = musics;
+    Ok(Some(agg))
+}
+
+fn push_element(xml: &mut String, depth: usize, name: &str, text: &str) {
+    xml.push_str(&format!(
+        "{}<{name}>{}</{name}>\n",
+        "  ".repeat(depth),
+        escape(text)
+    ));
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse::<u32>))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        };
+        match c {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// an xml element, namespace prefixes are dropped from the names
+struct XmlElement {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<XmlElement>,
+    text: String,
+}
+
+impl XmlElement {
+    fn child(&self, name: &str) -> Option<&XmlElement> {
+        self.children.iter().find(|e| e.name == name)
+    }
+
+    fn child_text(&self, name: &str) -> Option<String> {
+        self.child(name)
+            .map(|e| e.text.trim().to_string())
+            .filter(|text| !text.is_empty())
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// reads the subset of xml playlist files use, no dtd entities
+struct XmlReader<'a> {
+    xml: &'a str,
+    pos: usize,
+}
+
+impl XmlReader<'_> {
+    fn rest(&self) -> &str {
+        &self.xml[self.pos..]
+    }
+
+    fn document(mut self) -> Result<XmlElement> {
+        self.pos = self.xml.len() - self.xml.trim_start_matches('\u{feff}').len();
+        self.skip_misc()?;
+        self.element(1)
+    }
+
+    /// whitespace, the declaration, comments and doctype
+    fn skip_misc(&mut self) -> Result<()> {
+        loop {
+            self.pos = self.xml.len() - self.rest().trim_start().len();
+            if self.rest().starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.rest().starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.rest().starts_with("<!") {
+                self.skip_past(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn skip_past(&mut self, end: &str) -> Result<&str> {
+        let len = self
+            .rest()
+            .find(end)
+            .ok_or(anyhow!("Unclosed xml markup, expected {}.", end))?;
+        let skipped = &self.xml[self.pos..self.pos + len];
+        self.pos += len + end.len();
+        Ok(skipped)
+    }
+
+    /// the start tag up to its `>`, a `>` in a quoted attribute value doesn't end it
+    fn start_tag(&mut self) -> Result<&str> {
+        let mut quote = None;
+        for (i, c) in self.rest().char_indices() {
+            match (quote, c) {
+                (None, '>') => {
+                    let tag = &self.xml[self.pos..self.pos + i];
+                    self.pos += i + 1;
+                    return Ok(tag);
+                }
+                (None, '"' | '\'') => quote = Some(c),
+                (Some(q), c) if q == c => quote = None,
+                _ => {}
+            }
+        }
+        Err(anyhow!("Unclosed xml markup, expected >."))
+    }
+
+    fn element(&mut self, depth: usize) -> Result<XmlElement> {
+        if depth > MAX_XML_DEPTH {
+            return Err(anyhow!(
+                "Xml elements are nested deeper than {}.",
+                MAX_XML_DEPTH
+            ));
+        }
+        if !self.rest().starts_with('<') {
+            return Err(anyhow!("Expected an xml element at byte {}.", self.pos));
+        }
+        self.pos += 1;
+        let tag = self.start_tag()?.to_string();
+        let empty = tag.ends_with('/');
+        let tag = tag.trim_end_matches('/');
+        let (name, mut attributes) = match tag.split_once(char::is_whitespace) {
+            Some((name, attributes)) => (name, attributes.trim()),
+            None => (tag, ""),
+        };
+        let mut element = XmlElement {
+            name: local_name(name).to_string(),
+            attributes: Vec::new(),
+            children: Vec::new(),
+            text: String::new(),
+        };
+        while let Some((key, value)) = attributes.split_once('=') {
+            let value = value.trim_start();
+            let quote = value
+                .chars()
+                .next()
+                .filter(|c| *c == '"' || *c == '\'')
+                .ok_or(anyhow!("Unquoted attribute in <{}>.", name))?;
+            let end = value[1..]
+                .find(quote)
+                .ok_or(anyhow!("Unclosed attribute in <{}>.", name))?;
+            element.attributes.push((
+                local_name(key.trim()).to_string(),
+                unescape(&value[1..end + 1]),
+            ));
+            attributes = value[end + 2..].trim_start();
+        }
+        if empty {
+            return Ok(element);
+        }
+
+        loop {
+            if self.rest().starts_with("</") {
+                self.pos += 2;
+                let end = self.skip_past(">")?.trim();
+                if end != name {
+                    return Err(anyhow!("Expected </{}>, found </{}>.", name, end));
+                }
+                return Ok(element);
+            } else if self.rest().starts_with("<![CDATA[") {
+                self.pos += "<![CDATA[".len();
+                let text = self.skip_past("]]>")?.to_string();
+                element.text.push_str(&text);
+            } else if self.rest().starts_with("<!--") || self.rest().starts_with("<?") {
+                self.skip_misc()?;
+            } else if self.rest().starts_with('<') {
+                let child = self.element(depth + 1)?;
+                element.children.push(child);
+            } else if self.rest().is_empty() {
+                return Err(anyhow!("Unclosed xml element <{}>.", name));
+            } else {
+                let len = self.rest().find('<').unwrap_or(self.rest().len());
+                element.text.push_str(&unescape(&self.rest()[..len]));
+                self.pos += len;
+            }
+        }
+    }
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interface::{music_aggregator::empty_playlist, quality::Quality};
+
+    fn music(server: MusicServer, id: &str) -> Music {
+        Music {
+            from_db: false,
+            server,
+            identity: id.to_string(),
+            name: "Tom & Jerry".to_string(),
+            duration: Some(215),
+            artists: vec![Artist {
+                name: "a".to_string(),
+                id: Some("1".to_string()),
+            }],
+            album: Some("<album>".to_string()),
+            album_id: Some("2".to_string()),
+            qualities: vec![Quality {
+                summary: "128k".to_string(),
+                bitrate: Some("128".to_string()),
+                format: Some("mp3".to_string()),
+                size: None,
+            }],
+            cover: Some("cover".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    #[serial_test::serial]
+    async fn test_xspf() {
+        let mut agg = MusicAggregator::from_music(music(MusicServer::Kuwo, "1"));
+        agg.musics.push(music(MusicServer::Netease, "2"));
+        agg.default_server = MusicServer::Netease;
+        let playlist_json = PlaylistJson {
+            playlist: Playlist::new("p".to_string(), Some("s".to_string()), None, vec![]),
+            music_aggregators: vec![agg],
+        };
+        let xml = playlist_json.to_xspf();
+        assert!(xml.contains("<title>Tom &amp; Jerry</title>"));
+        assert!(xml.contains("<identifier>netease:music:2</identifier>"));
+        assert!(xml.contains("<duration>215000</duration>"));
+        assert_eq!(PlaylistJson::from_xspf(&xml).unwrap(), playlist_json);
+
+        // a file of another player with one known identifier
+        let parsed = PlaylistJson::from_xspf(
+            r#"<?xml version="1.0"?>
+            <!-- exported -->
+            <xspf:playlist version="1" xmlns:xspf="http://xspf.org/ns/0/">
+              <xspf:title>mix</xspf:title>
+              <xspf:trackList>
+                <xspf:track>
+                  <xspf:location>file:///a.mp3</xspf:location>
+                  <xspf:identifier>kuwo:music:9</xspf:identifier>
+                  <xspf:title><![CDATA[Lemon]]></xspf:title>
+                  <xspf:creator>米津玄師</xspf:creator>
+                  <xspf:duration>256000</xspf:duration>
+                </xspf:track>
+                <xspf:track><xspf:title>unknown</xspf:title></xspf:track>
+              </xspf:trackList>
+            </xspf:playlist>"#,
+        )
+        .unwrap();
+        assert_eq!(parsed.playlist.name, "mix");
+        assert_eq!(parsed.music_aggregators.len(), 1);
+        let agg = &parsed.music_aggregators[0];
+        assert_eq!(agg.identity(), "lemon#+#米津玄師");
+        assert_eq!(agg.musics[0].identity, "9");
+        assert_eq!(agg.musics[0].duration, Some(256));
+        assert!(PlaylistJson::from_xspf("<playlist><title>x</playlist>").is_err());
+        let parsed = PlaylistJson::from_xspf(
+            r#"<playlist><title a="1 > 0">x</title><trackList/></playlist>"#,
+        )
+        .unwrap();
+        assert_eq!(parsed.playlist.name, "x");
+        let nested = format!("{}{}", "<a>".repeat(100), "</a>".repeat(100));
+        assert!(PlaylistJson::from_xspf(&nested).is_err());
+
+        // through the json import
+        let (collection_id, _) = empty_playlist().await;
+        MusicDataJson::from_xspf(&xml)
+            .unwrap()
+            .apply_to_db(None, Some(collection_id))
+            .await
+            .unwrap();
+        let playlist = Playlist::get_from_db()
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|p| p.name == "p")
+            .unwrap();
+        let saved = playlist.get_musics_from_db().await.unwrap();
+        assert_eq!(saved.len(), 1);
+        assert_eq!(saved[0].musics.len(), 2);
+        assert_eq!(
+            playlist.to_xspf().await.unwrap().matches("<track>").count(),
+            1
+        );
+    }
+}
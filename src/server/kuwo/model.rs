@@ -70,7 +70,7 @@ impl RelationTrait for Relation {
 
 impl Related<music_aggregator::Entity> for Entity {
     fn to() -> RelationDef {
-        music_aggregator::Relation::KuwoMusic.def()
+        Relation::MusicAggregator.def()
     }
 }
 
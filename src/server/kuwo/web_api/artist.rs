@@ -370,6 +370,9 @@ impl Into<Playlist> for Album {
             from_db: false,
             order: None,
             collection_id: None,
+            created_at: None,
+            updated_at: None,
+            smart_rules: None,
         }
     }
 }
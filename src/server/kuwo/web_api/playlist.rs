@@ -118,6 +118,9 @@ impl Into<Playlist> for SearchMusicList {
             collection_id: None,
             from_db: false,
             order: None,
+            created_at: None,
+            updated_at: None,
+            smart_rules: None,
         }
     }
 }
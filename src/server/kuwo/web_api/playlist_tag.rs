@@ -202,6 +202,9 @@ impl Into<Playlist> for TagPlaylist {
             music_num: self.total.parse().ok(),
             subscription: None,
             collection_id: None,
+            created_at: None,
+            updated_at: None,
+            smart_rules: None,
         }
     }
 }
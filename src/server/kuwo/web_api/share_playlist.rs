@@ -60,6 +60,9 @@ impl Into<Playlist> for ShareMusicList {
             music_num: Some(self.total),
             subscription: None,
             order: None,
+            created_at: None,
+            updated_at: None,
+            smart_rules: None,
         }
     }
 }
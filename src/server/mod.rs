@@ -1,16 +1,21 @@
 use anyhow::Result;
 use kuwo::web_api::share_playlist::get_kuwo_music_list_from_share;
 use netease::web_api::share_playlist::get_netease_music_list_from_share;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 pub mod kuwo;
 pub mod netease;
 
+use crate::interface::album_aggregator::AlbumAggregator;
+use crate::interface::artist::Artist;
+use crate::interface::artist_aggregator::{discography_overlap, ArtistAggregator, ServerArtist};
 use crate::interface::music_chart::ServerMusicChartCollection;
 use crate::interface::playlist_tag::ServerPlaylistTagCollection;
 use crate::interface::playlist_tag::TagPlaylistOrder;
 use crate::interface::server::MusicServer;
+use crate::interface::song_group::SongGroup;
+use crate::interface::utils::normalize_name;
 
 use super::interface::music_aggregator::Music;
 use super::interface::music_aggregator::MusicAggregator;
@@ -116,6 +121,15 @@ impl Music {
             }
         }
     }
+    /// get the album of the music, together with the same album on the other servers
+    pub async fn get_album_aggregator(&self) -> Result<AlbumAggregator> {
+        let (album, _) = self.get_album(1, 1).await?;
+        let album = album.ok_or(anyhow::anyhow!("No album found"))?;
+        AlbumAggregator::from_album(album)?
+            .fetch_server_online(MusicServer::all())
+            .await
+    }
+
     pub async fn get_lyric(&self) -> Result<String> {
         match self.server {
             MusicServer::Kuwo => kuwo::web_api::lyric::get_kuwo_lyric(&self.identity).await,
@@ -209,6 +223,9 @@ impl MusicAggregator {
                                 default_server: music.server.clone(),
                                 musics: vec![music],
                                 order: None,
+                                created_at: None,
+                                updated_at: None,
+                                added_at: None,
                             },
                         ),
                     );
@@ -229,6 +246,20 @@ impl MusicAggregator {
         }
     }
 
+    /// takes ownership
+    /// same as `search_online`, but clusters the live/remix/instrumental variants of each song
+    /// into a `SongGroup` so that they can be collapsed
+    pub async fn search_online_grouped(
+        aggs: Vec<MusicAggregator>,
+        servers: Vec<MusicServer>,
+        content: String,
+        page: u16,
+        size: u16,
+    ) -> anyhow::Result<Vec<SongGroup>> {
+        let aggs = Self::search_online(aggs, servers, content, page, size).await?;
+        Ok(SongGroup::from_aggs(aggs))
+    }
+
     /// takes ownership
     pub async fn fetch_server_online(
         mut self,
@@ -423,6 +454,9 @@ impl Playlist {
                         .map(|music| MusicAggregator::from_music(music))
                         .collect::<Vec<MusicAggregator>>())
                 }
+                super::interface::playlist::PlaylistType::Liked => Err(anyhow::anyhow!(
+                    "Liked playlist only exists in db, fetch it from db."
+                )),
             },
             MusicServer::Netease => match self.type_field {
                 super::interface::playlist::PlaylistType::UserPlaylist => {
@@ -455,6 +489,9 @@ impl Playlist {
                         .map(|music| MusicAggregator::from_music(music))
                         .collect())
                 }
+                super::interface::playlist::PlaylistType::Liked => Err(anyhow::anyhow!(
+                    "Liked playlist only exists in db, fetch it from db."
+                )),
             },
         }
     }
@@ -476,6 +513,247 @@ impl Playlist {
     }
 }
 
+// how many songs of each discography are compared when matching an artist across servers
+const ARTIST_MATCH_DISCOGRAPHY_SIZE: u16 = 50;
+// how many candidate artists are checked on each server
+const ARTIST_MATCH_CANDIDATES: usize = 3;
+// candidates sharing at least this ratio of songs are the same artist, whatever their names
+const ARTIST_MATCH_OVERLAP: f64 = 0.3;
+
+impl ArtistAggregator {
+    /// takes ownership
+    /// find the same artist on the other servers.
+    /// candidates are the artists in a music search by name, a candidate is accepted
+    /// if it shares enough songs, or if it has the same name and shares at least one song.
+    pub async fn fetch_server_online(mut self, mut servers: Vec<MusicServer>) -> Result<Self> {
+        servers.retain(|x| !self.artists.iter().any(|y| y.server == *x));
+        if servers.is_empty() {
+            return Err(anyhow::anyhow!("No more servers to fetch".to_string()));
+        }
+
+        let known = self
+            .artists
+            .first()
+            .ok_or(anyhow::anyhow!("Artist aggregator has no server artist"))?
+            .clone();
+        let known_musics = MusicAggregator::fetch_artist_music_aggregators(
+            known.server,
+            &known.id,
+            1,
+            ARTIST_MATCH_DISCOGRAPHY_SIZE,
+        )
+        .await?;
+        let musics = Music::search_online(servers.clone(), self.name.clone(), 1, 30).await?;
+
+        for server in servers {
+            // rank the candidates by how often they appear in the search result
+            let mut candidates: Vec<(Artist, usize)> = Vec::new();
+            for artist in musics
+                .iter()
+                .filter(|m| m.server == server)
+                .flat_map(|m| m.artists.iter())
+                .filter(|a| a.id.is_some())
+            {
+                match candidates.iter_mut().find(|(a, _)| a.id == artist.id) {
+                    Some((_, count)) => *count += 1,
+                    None => candidates.push((artist.clone(), 1)),
+                }
+            }
+            candidates.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+            let mut best: Option<(Artist, f64)> = None;
+            for (artist, _) in candidates.into_iter().take(ARTIST_MATCH_CANDIDATES) {
+                let id = artist.id.clone().unwrap_or_default();
+                let overlap = match MusicAggregator::fetch_artist_music_aggregators(
+                    server.clone(),
+                    &id,
+                    1,
+                    ARTIST_MATCH_DISCOGRAPHY_SIZE,
+                )
+                .await
+                {
+                    Ok(aggs) => discography_overlap(&known_musics, &aggs),
+                    Err(e) => {
+                        log::error!("Failed to fetch musics of artist {}: {}", artist.name, e);
+                        continue;
+                    }
+                };
+                let accepted = overlap >= ARTIST_MATCH_OVERLAP
+                    || (self.name_matches(&artist.name)
+                        && (overlap > 0.0 || known_musics.is_empty()));
+                if accepted && best.as_ref().is_none_or(|(_, best)| overlap > *best) {
+                    best = Some((artist, overlap));
+                }
+            }
+
+            if let Some((artist, _)) = best {
+                self.artists.push(ServerArtist {
+                    server,
+                    id: artist.id.unwrap_or_default(),
+                    name: artist.name,
+                });
+            }
+        }
+        Ok(self)
+    }
+
+    /// fetch the musics of the artist from every server,
+    /// songs found on several servers are merged into one music aggregator
+    pub async fn fetch_music_aggregators(
+        &self,
+        page: u16,
+        limit: u16,
+    ) -> Result<Vec<MusicAggregator>> {
+        let mut handles = Vec::with_capacity(self.artists.len());
+        for artist in self.artists.clone() {
+            handles.push(tokio::spawn(async move {
+                MusicAggregator::fetch_artist_music_aggregators(
+                    artist.server,
+                    &artist.id,
+                    page,
+                    limit,
+                )
+                .await
+            }));
+        }
+
+        let mut success = false;
+        let mut aggs: Vec<MusicAggregator> = Vec::new();
+        let mut index_map: HashMap<String, usize> = HashMap::new();
+        for handle in handles {
+            match handle.await? {
+                Ok(fetched) => {
+                    success = true;
+                    for agg in fetched {
+                        let key = normalize_name(&agg.name);
+                        if let Some(index) = index_map.get(&key) {
+                            let existing = &mut aggs[*index];
+                            for music in agg.musics {
+                                if !existing.musics.iter().any(|x| x.server == music.server) {
+                                    existing.musics.push(music);
+                                }
+                            }
+                        } else {
+                            index_map.insert(key, aggs.len());
+                            aggs.push(agg);
+                        }
+                    }
+                }
+                Err(e) => log::error!("Failed to fetch artist musics: {}", e),
+            }
+        }
+
+        if success {
+            Ok(aggs)
+        } else {
+            Err(anyhow::anyhow!("Failed to fetch artist musics from all servers"))
+        }
+    }
+}
+
+// how many candidate albums are checked on each server
+const ALBUM_MATCH_CANDIDATES: usize = 3;
+// albums by the same artist sharing at least this ratio of tracks are the same album
+const ALBUM_MATCH_OVERLAP: f64 = 0.5;
+// albums by a differently written artist need a higher ratio
+const ALBUM_MATCH_OVERLAP_OTHER_ARTIST: f64 = 0.8;
+
+impl AlbumAggregator {
+    /// takes ownership
+    /// find the same album on the other servers.
+    /// candidates are the albums of a music search by album title and artist,
+    /// they must have the same title and share enough tracks.
+    pub async fn fetch_server_online(mut self, mut servers: Vec<MusicServer>) -> Result<Self> {
+        servers.retain(|x| self.album(x).is_none());
+        if servers.is_empty() {
+            return Ok(self);
+        }
+
+        let known_tracks = self
+            .albums
+            .first()
+            .ok_or(anyhow::anyhow!("Album aggregator has no album"))?
+            .fetch_musics_online(1, 2333)
+            .await?;
+        let musics = Music::search_online(
+            servers.clone(),
+            format!("{} {}", self.name, self.artist),
+            1,
+            30,
+        )
+        .await?;
+
+        for server in servers {
+            let mut checked = HashSet::new();
+            let mut best: Option<(Playlist, f64)> = None;
+            for music in musics.iter().filter(|m| {
+                m.server == server
+                    && m.album_id.is_some()
+                    && m.album.as_deref().is_some_and(|a| self.title_matches(a))
+            }) {
+                if !checked.insert(music.album_id.clone()) {
+                    continue;
+                }
+                if checked.len() > ALBUM_MATCH_CANDIDATES {
+                    break;
+                }
+
+                let (album, tracks) = match music.get_album(1, 2333).await {
+                    Ok((Some(album), tracks)) => (album, tracks),
+                    Ok((None, _)) => continue,
+                    Err(e) => {
+                        log::error!("Failed to get album of music {}: {}", music.name, e);
+                        continue;
+                    }
+                };
+                let same_artist = album
+                    .creator
+                    .as_deref()
+                    .is_some_and(|c| normalize_name(c) == normalize_name(&self.artist));
+                let threshold = if same_artist {
+                    ALBUM_MATCH_OVERLAP
+                } else {
+                    ALBUM_MATCH_OVERLAP_OTHER_ARTIST
+                };
+                let overlap = discography_overlap(&known_tracks, &tracks);
+                if overlap >= threshold && best.as_ref().is_none_or(|(_, best)| overlap > *best) {
+                    best = Some((album, overlap));
+                }
+            }
+
+            if let Some((album, _)) = best {
+                self.albums.push(album);
+            }
+        }
+        Ok(self)
+    }
+
+    /// fetch the tracks of the album on every server,
+    /// each track is a music aggregator with all the servers it was found on
+    pub async fn fetch_musics_online(&self) -> Result<Vec<MusicAggregator>> {
+        let mut handles = Vec::with_capacity(self.albums.len());
+        for album in self.albums.clone() {
+            handles.push(tokio::spawn(async move {
+                album.fetch_musics_online(1, 2333).await
+            }));
+        }
+
+        let mut track_lists = Vec::with_capacity(handles.len());
+        for handle in handles {
+            match handle.await? {
+                Ok(tracks) => track_lists.push(tracks),
+                Err(e) => log::error!("Failed to fetch album musics: {}", e),
+            }
+        }
+        if track_lists.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Failed to fetch album musics from all servers"
+            ));
+        }
+        Ok(AlbumAggregator::merge_tracks(track_lists))
+    }
+}
+
 impl ServerMusicChartCollection {
     pub async fn get_music_chart_collection() -> Result<Vec<ServerMusicChartCollection>> {
         let mut handles = Vec::with_capacity(MusicServer::length());
@@ -793,4 +1071,51 @@ mod test {
 
         println!("{:?}", muscis);
     }
+
+    #[tokio::test]
+    async fn test_artist_aggregator() {
+        let artist = super::ArtistAggregator::from_artist(
+            MusicServer::Netease,
+            &crate::interface::artist::Artist {
+                name: "米津玄師".to_string(),
+                id: Some("159300".to_string()),
+            },
+        )
+        .unwrap();
+        let artist = artist
+            .fetch_server_online(vec![MusicServer::Kuwo])
+            .await
+            .unwrap();
+        assert_eq!(artist.artist_id(&MusicServer::Netease), Some("159300"));
+        assert!(artist
+            .artist_id(&MusicServer::Kuwo)
+            .is_some_and(|id| !id.is_empty()));
+
+        let musics = artist.fetch_music_aggregators(1, 30).await.unwrap();
+        assert!(!musics.is_empty());
+        assert!(musics.iter().all(|agg| !agg.musics.is_empty()));
+        // the kuwo artist was accepted for its shared songs, they are merged
+        assert!(musics.iter().any(|agg| agg.musics.len() > 1));
+    }
+
+    #[tokio::test]
+    async fn test_album_aggregator() {
+        let musics = Music::search_online(
+            vec![MusicServer::Netease],
+            "米津玄师 Lemon".to_string(),
+            1,
+            10,
+        )
+        .await
+        .unwrap();
+        let music = musics.iter().find(|m| m.album_id.is_some()).unwrap();
+        let album = music.get_album_aggregator().await.unwrap();
+        assert_eq!(album.albums[0].server.as_ref(), Some(&MusicServer::Netease));
+        assert!(album.title_matches(music.album.as_deref().unwrap()));
+        assert!(album.album(&MusicServer::Kuwo).is_some());
+
+        let tracks = album.fetch_musics_online().await.unwrap();
+        assert!(!tracks.is_empty());
+        assert!(tracks.iter().any(|track| track.name == music.name));
+    }
 }
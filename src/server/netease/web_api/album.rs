@@ -106,6 +106,9 @@ impl Into<Playlist> for Album {
             music_num: Some(self.size),
             subscription: None,
             order: None,
+            created_at: None,
+            updated_at: None,
+            smart_rules: None,
         }
     }
 }
@@ -511,6 +511,9 @@ impl Into<interface::playlist::Playlist> for HotAlbum {
             play_time: None,
             music_num: None,
             subscription: None,
+            created_at: None,
+            updated_at: None,
+            smart_rules: None,
         }
     }
 }
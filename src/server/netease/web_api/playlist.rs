@@ -89,6 +89,9 @@ impl Into<Playlist> for NeteasePlaylist {
             collection_id: None,
             subscription: None,
             order: None,
+            created_at: None,
+            updated_at: None,
+            smart_rules: None,
         }
     }
 }
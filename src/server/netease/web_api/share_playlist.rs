@@ -143,6 +143,9 @@ impl Into<Playlist> for GetMusicInnerPlaylist {
             music_num: Some(self.track_count),
             subscription: None,
             order: None,
+            created_at: None,
+            updated_at: None,
+            smart_rules: None,
         }
     }
 }